@@ -0,0 +1,422 @@
+//! Durable JSON-lines log of download events
+//!
+//! [`JsonLinesEventSink`] appends every event it receives as a JSON line
+//! stamped with the time and the run it belongs to. Lines are written by a
+//! background task, so emitting an event never waits for the disk, and the
+//! log can be rotated once it reaches a size. [`JsonLinesEventSink::read`]
+//! and [`JsonLinesEventSink::replay`] turn a log, rotated files included,
+//! back into events for offline analysis.
+
+use crate::*;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::{
+  fs::{File, OpenOptions, rename},
+  io::{AsyncWriteExt, BufWriter},
+  sync::{mpsc, oneshot}
+};
+
+/// A line of an event log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+  /// Time the event was received
+  pub time: DateTime<Utc>,
+
+  /// Run the event belongs to
+  pub run: String,
+
+  /// The event itself
+  #[serde(flatten)]
+  pub event: DownloadEvent
+}
+
+/// An event sink that appends events as JSON lines to a file.
+///
+/// The file is opened in append mode, so several runs can share a log and
+/// are told apart by their run id.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use downloader::{Downloader, JsonLinesEventSink};
+/// use std::sync::Arc;
+///
+/// # async fn run() -> downloader::Result<()> {
+/// let sink = JsonLinesEventSink::open_rotating(
+///   "logs/downloads.jsonl",
+///   10 * 1024 * 1024,
+///   5
+/// )
+/// .await?;
+/// let mut downloader =
+///   Downloader::new(vec!["https://example.com/data.csv"], "downloads")?;
+/// downloader.with_event_sink(Arc::new(sink));
+/// downloader.start().await?.await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct JsonLinesEventSink {
+  path: PathBuf,
+  run: String,
+  tx: mpsc::UnboundedSender<Command>
+}
+
+/// Work for the background writer.
+#[derive(Debug)]
+enum Command {
+  Line(String),
+  Flush(oneshot::Sender<()>)
+}
+
+/// Writes lines to the log and rotates it.
+struct Writer {
+  path: PathBuf,
+  file: BufWriter<File>,
+  size: u64,
+  rotation: Option<(u64, usize)>
+}
+
+impl JsonLinesEventSink {
+  /// Opens the log at `path` for appending, creating it if needed.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the file cannot be opened.
+  pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    Self::spawn(path.as_ref(), None).await
+  }
+
+  /// Opens the log at `path`, moving it aside once it would grow beyond
+  /// `max_bytes`.
+  ///
+  /// Rotated logs get a numeric suffix, `.1` being the most recent, and only
+  /// the latest `max_files` of them are kept.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the file cannot be opened.
+  pub async fn open_rotating<P: AsRef<Path>>(
+    path: P,
+    max_bytes: u64,
+    max_files: usize
+  ) -> Result<Self> {
+    Self::spawn(path.as_ref(), Some((max_bytes, max_files.max(1)))).await
+  }
+
+  async fn spawn(path: &Path, rotation: Option<(u64, usize)>) -> Result<Self> {
+    let file = Writer::open(path).await?;
+    let size = file.metadata().await.map(|m| m.len()).unwrap_or_default();
+    let writer = Writer {
+      path: path.to_path_buf(),
+      file: BufWriter::new(file),
+      size,
+      rotation
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(writer.run(rx));
+
+    Ok(Self {
+      path: path.to_path_buf(),
+      run: journal::new_batch_id(),
+      tx
+    })
+  }
+
+  /// Sets the run id stamped on the events, e.g. the journal batch.
+  pub fn with_run_id<S: Into<String>>(mut self, run: S) -> Self {
+    self.run = run.into();
+    self
+  }
+
+  /// Returns the run id stamped on the events.
+  pub fn run_id(&self) -> &str {
+    &self.run
+  }
+
+  /// Returns the path of the log.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Waits until the events received so far are written to the file.
+  pub async fn flush(&self) {
+    let (ack, done) = oneshot::channel();
+    if self.tx.send(Command::Flush(ack)).is_ok() {
+      let _ = done.await;
+    }
+  }
+
+  /// Reads the records of the log at `path`, oldest first, including the
+  /// logs rotated away from it.
+  ///
+  /// Lines that cannot be parsed, such as one cut off by a crash, are
+  /// skipped.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if a log cannot be read.
+  pub async fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Record>> {
+    let path = path.as_ref();
+    let mut paths = vec![path.to_path_buf()];
+    loop {
+      let rotated = rotated_path(path, paths.len());
+      if !rotated.exists() {
+        break;
+      }
+      paths.push(rotated);
+    }
+
+    let mut records = Vec::new();
+    for path in paths.iter().rev() {
+      let content = tokio::fs::read_to_string(path).await.map_err(|e| {
+        Error::FileSystem {
+          message: format!(
+            "Failed to read event log '{}': {e}",
+            path.display()
+          )
+        }
+      })?;
+
+      records.extend(
+        content
+          .lines()
+          .filter(|line| !line.trim().is_empty())
+          .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+              warn!("Skipping unreadable event log line: {}", e);
+              None
+            }
+          })
+      );
+    }
+
+    Ok(records)
+  }
+
+  /// Sends the events logged at `path` to `sink` in their original order,
+  /// returning how many were replayed.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if a log cannot be read.
+  pub async fn replay<P: AsRef<Path>>(
+    path: P,
+    sink: &dyn EventSink
+  ) -> Result<usize> {
+    let records = Self::read(path).await?;
+    let count = records.len();
+    for record in records {
+      sink.on_event(record.event).await;
+    }
+    Ok(count)
+  }
+}
+
+#[async_trait]
+impl EventSink for JsonLinesEventSink {
+  async fn on_event(&self, event: DownloadEvent) {
+    let record = Record {
+      time: Utc::now(),
+      run: self.run.clone(),
+      event
+    };
+
+    let mut line = match serde_json::to_string(&record) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize download event: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+
+    if self.tx.send(Command::Line(line)).is_err() {
+      debug!("Event log writer for {} stopped", self.path.display());
+    }
+  }
+}
+
+impl Writer {
+  async fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .await
+      .map_err(|e| Error::FileSystem {
+        message: format!("Failed to open event log '{}': {e}", path.display())
+      })
+  }
+
+  /// Writes lines until the sink is dropped, flushing whenever no more
+  /// lines are waiting.
+  ///
+  /// A log that cannot be written does not affect the downloads, so
+  /// failures are logged.
+  async fn run(mut self, mut rx: mpsc::UnboundedReceiver<Command>) {
+    while let Some(command) = rx.recv().await {
+      match command {
+        Command::Line(line) => {
+          if let Err(e) = self.write(line.as_bytes()).await {
+            warn!("Failed to write event log: {}", e);
+          }
+        }
+        Command::Flush(ack) => {
+          self.flush().await;
+          let _ = ack.send(());
+        }
+      }
+
+      if rx.is_empty() {
+        self.flush().await;
+      }
+    }
+
+    self.flush().await;
+  }
+
+  async fn write(&mut self, line: &[u8]) -> std::io::Result<()> {
+    if let Some((max_bytes, max_files)) = self.rotation
+      && self.size > 0
+      && self.size + line.len() as u64 > max_bytes
+    {
+      self.rotate(max_files).await?;
+    }
+
+    self.file.write_all(line).await?;
+    self.size += line.len() as u64;
+    Ok(())
+  }
+
+  async fn flush(&mut self) {
+    if let Err(e) = self.file.flush().await {
+      warn!("Failed to flush event log: {}", e);
+    }
+  }
+
+  /// Shifts the rotated logs up by one, dropping the oldest, and starts a
+  /// new log.
+  async fn rotate(&mut self, max_files: usize) -> std::io::Result<()> {
+    self.file.flush().await?;
+
+    for index in (1..max_files).rev() {
+      let from = rotated_path(&self.path, index);
+      if from.exists() {
+        rename(&from, rotated_path(&self.path, index + 1)).await?;
+      }
+    }
+    rename(&self.path, rotated_path(&self.path, 1)).await?;
+
+    // Keep writing to the rotated file if a new one cannot be created
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.path)
+      .await?;
+    self.file = BufWriter::new(file);
+    self.size = 0;
+    Ok(())
+  }
+}
+
+/// Returns the path of the `index`-th rotated log of `path`.
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+  let mut name = path.as_os_str().to_os_string();
+  name.push(format!(".{index}"));
+  PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn test_log_rotates_and_replays() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("events.jsonl");
+    let sink = JsonLinesEventSink::open_rotating(&path, 1, 2)
+      .await
+      .unwrap()
+      .with_run_id("run-1");
+
+    sink
+      .on_event(DownloadEvent::DownloadStarted { url_count: 3 })
+      .await;
+    for index in 0..3 {
+      sink
+        .on_event(DownloadEvent::FileCompleted {
+          index,
+          filename: format!("file-{index}.csv"),
+          bytes_downloaded: 1024,
+          duration: Duration::from_millis(250),
+          mirror: None
+        })
+        .await;
+    }
+    sink.flush().await;
+
+    // Every line goes to a new log, and only two rotated logs are kept
+    assert!(rotated_path(&path, 1).exists());
+    assert!(rotated_path(&path, 2).exists());
+    assert!(!rotated_path(&path, 3).exists());
+
+    let records = JsonLinesEventSink::read(&path).await.unwrap();
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|record| record.run == "run-1"));
+    let indexes: Vec<_> = records
+      .iter()
+      .filter_map(|record| match record.event {
+        DownloadEvent::FileCompleted {
+          index,
+          bytes_downloaded: 1024,
+          ..
+        } => Some(index),
+        _ => None
+      })
+      .collect();
+    assert_eq!(indexes, vec![0, 1, 2]);
+
+    let collector = CollectingEventSink::new();
+    let replayed = JsonLinesEventSink::replay(&path, &collector).await.unwrap();
+    assert_eq!(replayed, records.len());
+    assert_eq!(collector.event_count(), records.len());
+  }
+
+  #[test]
+  fn test_event_serialization() {
+    let record = Record {
+      time: Utc::now(),
+      run: "run-1".to_string(),
+      event: DownloadEvent::FileRetrying {
+        index: 1,
+        url: "https://example.com/a.csv".to_string(),
+        attempt: 2,
+        max_attempts: 3,
+        delay: Duration::from_millis(1500),
+        reason: "timeout".to_string()
+      }
+    };
+
+    let line = serde_json::to_string(&record).unwrap();
+    assert!(line.contains(r#""event":"file_retrying""#));
+    assert!(line.contains(r#""run":"run-1""#));
+
+    let parsed: Record = serde_json::from_str(&line).unwrap();
+    assert_eq!(parsed.time, record.time);
+    assert!(matches!(
+      parsed.event,
+      DownloadEvent::FileRetrying {
+        attempt: 2,
+        delay,
+        ..
+      } if delay == Duration::from_millis(1500)
+    ));
+  }
+}
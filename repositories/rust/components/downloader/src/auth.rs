@@ -0,0 +1,528 @@
+//! Credentials for authenticated downloads
+//!
+//! A [`CredentialProvider`] supplies credentials for the hosts it is scoped
+//! to, and they are sent as an `Authorization` header only to those hosts.
+//! The HTTP client strips that header when a redirect leaves the original
+//! host, so credentials never reach a redirect target on another host.
+//!
+//! Built-in providers cover static basic auth ([`BasicAuth`]) and bearer
+//! tokens ([`BearerToken`]), `~/.netrc` files ([`Netrc`]) and credentials
+//! read from environment variables ([`EnvCredentials`]).
+
+use crate::*;
+use reqwest::{RequestBuilder, StatusCode};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  path::{Path, PathBuf},
+  sync::Arc
+};
+
+/// Credentials sent with a request.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+  /// HTTP basic authentication
+  Basic {
+    username: String,
+    password: Option<String>
+  },
+
+  /// Bearer token authentication
+  Bearer { token: String }
+}
+
+impl Credentials {
+  /// Creates basic authentication credentials.
+  pub fn basic<U: Into<String>, P: Into<String>>(
+    username: U,
+    password: P
+  ) -> Self {
+    Self::Basic {
+      username: username.into(),
+      password: Some(password.into())
+    }
+  }
+
+  /// Creates bearer token credentials.
+  pub fn bearer<T: Into<String>>(token: T) -> Self {
+    Self::Bearer {
+      token: token.into()
+    }
+  }
+
+  /// Adds the `Authorization` header for these credentials to a request.
+  pub(crate) fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+    match self {
+      Credentials::Basic { username, password } =>
+        request.basic_auth(username, password.as_ref()),
+      Credentials::Bearer { token } => request.bearer_auth(token)
+    }
+  }
+}
+
+impl Debug for Credentials {
+  // Secrets stay out of logs
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Credentials::Basic { username, .. } => f
+        .debug_struct("Basic")
+        .field("username", username)
+        .field("password", &"***")
+        .finish(),
+      Credentials::Bearer { .. } =>
+        f.debug_struct("Bearer").field("token", &"***").finish(),
+    }
+  }
+}
+
+/// Supplies credentials for the URLs it is responsible for.
+pub trait CredentialProvider: Send + Sync + Debug {
+  /// Returns the credentials for `url`, or `None` if this provider has none.
+  fn credentials(&self, url: &reqwest::Url) -> Option<Credentials>;
+}
+
+/// Host name pattern scoping credentials.
+///
+/// `example.com` matches that host only, `*.example.com` matches its
+/// subdomains but not `example.com` itself, and `*` matches every host.
+/// Matching ignores case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostPattern(String);
+
+impl HostPattern {
+  /// Creates a host pattern.
+  pub fn new<S: Into<String>>(pattern: S) -> Self {
+    Self(pattern.into().to_ascii_lowercase())
+  }
+
+  /// Returns true if `host` matches this pattern.
+  pub fn matches(&self, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    match self.0.strip_prefix('*') {
+      Some("") => true,
+      Some(suffix) if suffix.starts_with('.') => host.ends_with(suffix),
+      _ => host == self.0
+    }
+  }
+
+  /// Returns true if the host of `url` matches this pattern.
+  fn matches_url(&self, url: &reqwest::Url) -> bool {
+    url.host_str().is_some_and(|host| self.matches(host))
+  }
+}
+
+impl<S: Into<String>> From<S> for HostPattern {
+  fn from(pattern: S) -> Self {
+    Self::new(pattern)
+  }
+}
+
+/// Basic authentication for the hosts matching a pattern.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+  hosts: HostPattern,
+  credentials: Credentials
+}
+
+impl BasicAuth {
+  /// Creates basic authentication for the hosts matching `hosts`.
+  pub fn new<H, U, P>(hosts: H, username: U, password: P) -> Self
+  where
+    H: Into<HostPattern>,
+    U: Into<String>,
+    P: Into<String>
+  {
+    Self {
+      hosts: hosts.into(),
+      credentials: Credentials::basic(username, password)
+    }
+  }
+}
+
+impl CredentialProvider for BasicAuth {
+  fn credentials(&self, url: &reqwest::Url) -> Option<Credentials> {
+    self
+      .hosts
+      .matches_url(url)
+      .then(|| self.credentials.clone())
+  }
+}
+
+/// Bearer token for the hosts matching a pattern.
+#[derive(Debug, Clone)]
+pub struct BearerToken {
+  hosts: HostPattern,
+  credentials: Credentials
+}
+
+impl BearerToken {
+  /// Creates a bearer token for the hosts matching `hosts`.
+  pub fn new<H: Into<HostPattern>, T: Into<String>>(
+    hosts: H,
+    token: T
+  ) -> Self {
+    Self {
+      hosts: hosts.into(),
+      credentials: Credentials::bearer(token)
+    }
+  }
+}
+
+impl CredentialProvider for BearerToken {
+  fn credentials(&self, url: &reqwest::Url) -> Option<Credentials> {
+    self
+      .hosts
+      .matches_url(url)
+      .then(|| self.credentials.clone())
+  }
+}
+
+/// Credentials read from environment variables when a request is made.
+#[derive(Debug, Clone)]
+pub struct EnvCredentials {
+  hosts: HostPattern,
+  kind: EnvKind,
+  lookup: fn(&str) -> Option<String>
+}
+
+/// Environment variables holding the credentials.
+#[derive(Debug, Clone)]
+enum EnvKind {
+  Basic {
+    username_var: String,
+    password_var: String
+  },
+  Bearer {
+    token_var: String
+  }
+}
+
+impl EnvCredentials {
+  /// Reads basic authentication from two variables.
+  pub fn basic<H, U, P>(hosts: H, username_var: U, password_var: P) -> Self
+  where
+    H: Into<HostPattern>,
+    U: Into<String>,
+    P: Into<String>
+  {
+    Self {
+      hosts: hosts.into(),
+      kind: EnvKind::Basic {
+        username_var: username_var.into(),
+        password_var: password_var.into()
+      },
+      lookup: env_var
+    }
+  }
+
+  /// Reads a bearer token from a variable.
+  pub fn bearer<H: Into<HostPattern>, T: Into<String>>(
+    hosts: H,
+    token_var: T
+  ) -> Self {
+    Self {
+      hosts: hosts.into(),
+      kind: EnvKind::Bearer {
+        token_var: token_var.into()
+      },
+      lookup: env_var
+    }
+  }
+
+  /// Reads the variables with `lookup` instead of the process environment.
+  #[cfg(test)]
+  fn with_lookup(mut self, lookup: fn(&str) -> Option<String>) -> Self {
+    self.lookup = lookup;
+    self
+  }
+}
+
+/// Returns the value of the environment variable `name`, if set.
+fn env_var(name: &str) -> Option<String> {
+  std::env::var(name).ok()
+}
+
+impl CredentialProvider for EnvCredentials {
+  fn credentials(&self, url: &reqwest::Url) -> Option<Credentials> {
+    if !self.hosts.matches_url(url) {
+      return None;
+    }
+
+    match &self.kind {
+      EnvKind::Basic {
+        username_var,
+        password_var
+      } => Some(Credentials::Basic {
+        username: (self.lookup)(username_var)?,
+        password: (self.lookup)(password_var)
+      }),
+      EnvKind::Bearer { token_var } =>
+        Some(Credentials::bearer((self.lookup)(token_var)?)),
+    }
+  }
+}
+
+/// Basic authentication from a `.netrc` file.
+///
+/// Entries apply to the exact host named by `machine`; a `default` entry
+/// applies to every other host.
+#[derive(Clone, Default)]
+pub struct Netrc {
+  machines: HashMap<String, Credentials>,
+  default: Option<Credentials>
+}
+
+impl Netrc {
+  /// Loads the file named by `NETRC`, or `~/.netrc`.
+  ///
+  /// A missing file yields no credentials.
+  pub fn load() -> Result<Self> {
+    let path = match std::env::var_os("NETRC") {
+      Some(path) => PathBuf::from(path),
+      None => match std::env::var_os("HOME") {
+        Some(home) => Path::new(&home).join(".netrc"),
+        None => return Ok(Self::default())
+      }
+    };
+
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    Self::from_path(&path)
+  }
+
+  /// Loads a `.netrc` file.
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref();
+    let contents =
+      std::fs::read_to_string(path).map_err(|e| Error::Configuration {
+        message: format!("Failed to read netrc '{}': {e}", path.display())
+      })?;
+    Ok(Self::parse(&contents))
+  }
+
+  /// Parses the contents of a `.netrc` file.
+  pub fn parse(contents: &str) -> Self {
+    let mut netrc = Self::default();
+    let mut tokens = contents
+      .lines()
+      .filter(|line| !line.trim_start().starts_with('#'))
+      .flat_map(str::split_whitespace)
+      .peekable();
+
+    while let Some(token) = tokens.next() {
+      let machine = match token {
+        "machine" => tokens.next().map(str::to_ascii_lowercase),
+        "default" => None,
+        // Macros run up to the next blank line, which whitespace splitting
+        // cannot see, so they are not supported
+        _ => continue
+      };
+
+      let mut login = None;
+      let mut password = None;
+      while let Some(&key) = tokens.peek() {
+        if matches!(key, "machine" | "default") {
+          break;
+        }
+        tokens.next();
+        match key {
+          "login" => login = tokens.next(),
+          "password" => password = tokens.next(),
+          "account" => {
+            tokens.next();
+          }
+          _ => {}
+        }
+      }
+
+      let Some(login) = login else { continue };
+      let credentials = Credentials::Basic {
+        username: login.to_string(),
+        password: password.map(str::to_string)
+      };
+      match machine {
+        Some(machine) => {
+          netrc.machines.entry(machine).or_insert(credentials);
+        }
+        None => netrc.default = Some(credentials)
+      }
+    }
+
+    netrc
+  }
+}
+
+impl Debug for Netrc {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Netrc")
+      .field("machines", &self.machines.keys().collect::<Vec<_>>())
+      .field("default", &self.default.is_some())
+      .finish()
+  }
+}
+
+impl CredentialProvider for Netrc {
+  fn credentials(&self, url: &reqwest::Url) -> Option<Credentials> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    self.machines.get(&host).or(self.default.as_ref()).cloned()
+  }
+}
+
+/// Returns the credentials of the first provider responsible for `url`.
+pub(crate) fn credentials(
+  providers: &[Arc<dyn CredentialProvider>],
+  url: &reqwest::Url
+) -> Option<Credentials> {
+  providers
+    .iter()
+    .find_map(|provider| provider.credentials(url))
+}
+
+/// Returns an authentication error if the server refused the request.
+pub(crate) fn authentication_error(
+  response: &Response,
+  url: &str
+) -> Option<Error> {
+  let message = match response.status() {
+    StatusCode::UNAUTHORIZED => "Credentials missing or rejected (HTTP 401)",
+    StatusCode::FORBIDDEN => "Access denied (HTTP 403)",
+    _ => return None
+  };
+  Some(Error::authentication_error(url, message))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::Method;
+
+  fn url(url: &str) -> reqwest::Url {
+    reqwest::Url::parse(url).unwrap()
+  }
+
+  #[test]
+  fn test_host_pattern() {
+    let exact = HostPattern::new("Example.com");
+    assert!(exact.matches("example.com"));
+    assert!(!exact.matches("api.example.com"));
+
+    let subdomains = HostPattern::new("*.example.com");
+    assert!(subdomains.matches("api.EXAMPLE.com"));
+    assert!(!subdomains.matches("example.com"));
+    assert!(!subdomains.matches("badexample.com"));
+
+    assert!(HostPattern::new("*").matches("anything.org"));
+  }
+
+  #[test]
+  fn test_scoped_providers() {
+    let providers: Vec<Arc<dyn CredentialProvider>> = vec![
+      Arc::new(BearerToken::new("*.example.com", "token")),
+      Arc::new(BasicAuth::new("example.com", "user", "secret")),
+    ];
+    let find = |u: &str| {
+      providers
+        .iter()
+        .find_map(|provider| provider.credentials(&url(u)))
+    };
+
+    assert_eq!(
+      find("https://api.example.com/file"),
+      Some(Credentials::bearer("token"))
+    );
+    assert_eq!(
+      find("https://example.com/file"),
+      Some(Credentials::basic("user", "secret"))
+    );
+    assert_eq!(find("https://other.org/file"), None);
+  }
+
+  #[test]
+  fn test_netrc_parse() {
+    let netrc = Netrc::parse(
+      "# comment\n\
+       machine data.example.com login alice password s3cret\n\
+       machine other.org\n  login bob\n  account x\n  password pw\n\
+       default login anonymous password guest\n"
+    );
+
+    assert_eq!(
+      netrc.credentials(&url("https://DATA.example.com/a")),
+      Some(Credentials::basic("alice", "s3cret"))
+    );
+    assert_eq!(
+      netrc.credentials(&url("https://other.org/a")),
+      Some(Credentials::basic("bob", "pw"))
+    );
+    assert_eq!(
+      netrc.credentials(&url("https://elsewhere.net/a")),
+      Some(Credentials::basic("anonymous", "guest"))
+    );
+  }
+
+  #[test]
+  fn test_env_credentials() {
+    let provider = EnvCredentials::bearer("example.com", "TEST_TOKEN")
+      .with_lookup(|name| (name == "TEST_TOKEN").then(|| "secret".into()));
+    assert_eq!(
+      provider.credentials(&url("https://example.com/a")),
+      Some(Credentials::bearer("secret"))
+    );
+    assert_eq!(provider.credentials(&url("https://other.org/a")), None);
+
+    let missing =
+      EnvCredentials::bearer("example.com", "DOWNLOADER_TEST_UNSET_TOKEN");
+    assert_eq!(missing.credentials(&url("https://example.com/a")), None);
+  }
+
+  #[test]
+  fn test_debug_hides_secrets() {
+    let debug = format!("{:?}", Credentials::basic("user", "secret"));
+    assert!(debug.contains("user"));
+    assert!(!debug.contains("secret"));
+  }
+
+  /// Answers every request with `response`, reporting the request heads.
+  async fn serve(
+    response: String
+  ) -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+      while let Ok((mut stream, _)) = listener.accept().await {
+        let mut buf = vec![0; 4096];
+        let n = stream.read(&mut buf).await.unwrap_or(0);
+        let _ = tx.send(String::from_utf8_lossy(&buf[..n]).to_lowercase());
+        let _ = stream.write_all(response.as_bytes()).await;
+      }
+    });
+    (format!("http://{addr}"), rx)
+  }
+
+  #[tokio::test]
+  async fn test_credentials_dropped_on_cross_host_redirect() {
+    let (target, mut target_requests) =
+      serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_string()).await;
+    let (origin, mut origin_requests) = serve(format!(
+      "HTTP/1.1 302 Found\r\nLocation: {target}/file\r\nContent-Length: 0\r\n\r\n"
+    ))
+    .await;
+
+    let config = Config::builder()
+      .credential_provider(Arc::new(BearerToken::new("*", "token")))
+      .build();
+    let origin_url = url(&format!("{origin}/file"));
+    let request = Request::with_config(Method::GET, &origin_url, &config);
+    let transport = HttpTransport::from_config(&config).unwrap();
+    assert!(transport.send(request).await.unwrap().status().is_success());
+
+    let origin_request = origin_requests.recv().await.unwrap();
+    assert!(origin_request.contains("authorization: bearer token"));
+    let target_request = target_requests.recv().await.unwrap();
+    assert!(!target_request.contains("authorization"));
+  }
+}
@@ -0,0 +1,348 @@
+//! Terminal progress bars drawn from download events
+
+use crate::*;
+use async_trait::async_trait;
+use indicatif::{
+  MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle
+};
+use std::{collections::HashMap, io::IsTerminal, sync::Mutex, time::Duration};
+
+const FILE_TEMPLATE: &str = "{prefix:32!} {wide_bar:.cyan/blue} \
+                             {bytes}/{total_bytes} {bytes_per_sec} {eta} {msg}";
+const SPINNER_TEMPLATE: &str =
+  "{prefix:32!} {spinner:.cyan} {bytes} {bytes_per_sec} {msg}";
+const TOTAL_TEMPLATE: &str = "{spinner:.green} [{elapsed_precise}] {wide_bar:.green} {pos}/{len} files \
+   {msg}";
+
+/// Draws a progress bar per active file above an aggregate bar counting the
+/// finished files.
+///
+/// Files of unknown length get a spinner until their size is known. When
+/// stderr is not a terminal, a plain line is printed for each started,
+/// retried, finished and failed file instead.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use downloader::{Downloader, IndicatifEventSink};
+/// use std::sync::Arc;
+///
+/// # async fn run() -> downloader::Result<()> {
+/// let mut downloader =
+///   Downloader::new(vec!["https://example.com/data.csv"], "downloads")?;
+/// downloader.with_event_sink(Arc::new(IndicatifEventSink::new()));
+/// downloader.start().await?.await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct IndicatifEventSink {
+  multi: MultiProgress,
+  total: ProgressBar,
+  interactive: bool,
+  files: Mutex<HashMap<usize, File>>
+}
+
+/// Bar and progress of an active file.
+#[derive(Debug)]
+struct File {
+  bar: ProgressBar,
+  name: String,
+  sized: bool
+}
+
+impl IndicatifEventSink {
+  /// Creates a sink drawing to stderr, or printing plain lines if stderr is
+  /// not a terminal.
+  pub fn new() -> Self {
+    if std::io::stderr().is_terminal() {
+      Self::with_draw_target(ProgressDrawTarget::stderr())
+    } else {
+      Self::plain()
+    }
+  }
+
+  /// Creates a sink drawing the bars to `target`.
+  pub fn with_draw_target(target: ProgressDrawTarget) -> Self {
+    Self::build(target, true)
+  }
+
+  /// Creates a sink printing a plain line per file event to stderr.
+  pub fn plain() -> Self {
+    Self::build(ProgressDrawTarget::hidden(), false)
+  }
+
+  fn build(target: ProgressDrawTarget, interactive: bool) -> Self {
+    let multi = MultiProgress::with_draw_target(target);
+    let total =
+      multi.add(ProgressBar::new(0).with_style(style(TOTAL_TEMPLATE)));
+    Self {
+      multi,
+      total,
+      interactive,
+      files: Mutex::new(HashMap::new())
+    }
+  }
+
+  /// Returns the number of files with a bar.
+  pub fn active(&self) -> usize {
+    self.files.lock().unwrap().len()
+  }
+
+  /// Returns the aggregate bar.
+  pub fn total(&self) -> &ProgressBar {
+    &self.total
+  }
+
+  /// Prints `message` above the bars, or as a line without them.
+  fn println(&self, message: String) {
+    if self.interactive {
+      let _ = self.multi.println(message);
+    } else {
+      eprintln!("{message}");
+    }
+  }
+
+  /// Removes the bar of a file that finished, returning its name.
+  fn finish(&self, index: usize) -> Option<String> {
+    self.total.inc(1);
+    let file = self.files.lock().unwrap().remove(&index)?;
+    file.bar.finish_and_clear();
+    self.multi.remove(&file.bar);
+    Some(file.name)
+  }
+
+  fn started(&self, index: usize, filename: String) {
+    if !self.interactive {
+      self.println(format!("Downloading {filename}"));
+    }
+
+    let bar = self.multi.insert_before(
+      &self.total,
+      ProgressBar::new_spinner()
+        .with_style(style(SPINNER_TEMPLATE))
+        .with_prefix(filename.clone())
+    );
+    if self.interactive {
+      bar.enable_steady_tick(Duration::from_millis(120));
+    }
+
+    let file = File {
+      bar,
+      name: filename,
+      sized: false
+    };
+    if let Some(old) = self.files.lock().unwrap().insert(index, file) {
+      self.multi.remove(&old.bar);
+    }
+  }
+
+  fn progress(&self, index: usize, bytes: u64, total: Option<u64>) {
+    let mut files = self.files.lock().unwrap();
+    let Some(file) = files.get_mut(&index) else {
+      return;
+    };
+
+    // Switch from the spinner to a bar once the length is known
+    if let Some(total) = total
+      && !file.sized
+    {
+      file.bar.disable_steady_tick();
+      file.bar.set_style(style(FILE_TEMPLATE));
+      file.bar.set_length(total);
+      file.sized = true;
+    }
+    file.bar.set_position(bytes);
+  }
+}
+
+impl Default for IndicatifEventSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[async_trait]
+impl EventSink for IndicatifEventSink {
+  async fn on_event(&self, event: DownloadEvent) {
+    match event {
+      DownloadEvent::DownloadStarted { url_count } => {
+        self.total.set_length(url_count as u64);
+        self.total.set_position(0);
+      }
+
+      DownloadEvent::FileStarted {
+        index, filename, ..
+      } => self.started(index, filename),
+
+      DownloadEvent::FileProgress {
+        index,
+        bytes_downloaded,
+        total_bytes,
+        ..
+      } => self.progress(index, bytes_downloaded, total_bytes),
+
+      DownloadEvent::FileRetrying {
+        index,
+        url,
+        attempt,
+        max_attempts,
+        reason,
+        ..
+      } => {
+        let message = format!("retry {attempt}/{max_attempts}: {reason}");
+        match self.files.lock().unwrap().get(&index) {
+          Some(file) if self.interactive => file.bar.set_message(message),
+          _ => self.println(format!("Retrying {url} ({message})"))
+        }
+      }
+
+      DownloadEvent::FileCompleted {
+        index,
+        filename,
+        bytes_downloaded,
+        duration,
+        ..
+      } => {
+        self.finish(index);
+        self.println(format!(
+          "Downloaded {filename} ({} in {:.1}s)",
+          format_filesize(bytes_downloaded),
+          duration.as_secs_f64()
+        ));
+      }
+
+      DownloadEvent::FileNotModified {
+        index, filename, ..
+      } => {
+        self.finish(index);
+        self.println(format!("Up to date {filename}"));
+      }
+
+      DownloadEvent::FileFailed {
+        index, url, error, ..
+      } => {
+        let name = self.finish(index).unwrap_or(url);
+        self.println(format!("Failed {name}: {error}"));
+      }
+
+      DownloadEvent::FileCancelled { index, .. } => {
+        self.finish(index);
+      }
+
+      DownloadEvent::DownloadCompleted {
+        successful, failed, ..
+      } => {
+        self.total.finish_with_message(format!(
+          "{successful} downloaded, {failed} failed"
+        ));
+        if !self.interactive {
+          self.println(format!(
+            "Finished: {successful} downloaded, {failed} failed"
+          ));
+        }
+      }
+
+      DownloadEvent::DownloadCancelled {
+        successful,
+        failed,
+        cancelled
+      } => {
+        self.total.abandon_with_message(format!(
+          "cancelled: {successful} downloaded, {failed} failed, {cancelled} \
+           cancelled"
+        ));
+        if !self.interactive {
+          self.println(format!(
+            "Cancelled: {successful} downloaded, {failed} failed, \
+             {cancelled} cancelled"
+          ));
+        }
+      }
+
+      DownloadEvent::Warning { message, .. } => {
+        self.println(format!("Warning: {message}"));
+      }
+
+      _ => {}
+    }
+  }
+}
+
+/// Returns the style for `template`, which is known to be valid.
+fn style(template: &str) -> ProgressStyle {
+  ProgressStyle::with_template(template)
+    .unwrap_or_else(|_| ProgressStyle::default_bar())
+    .progress_chars("=> ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_bars_follow_file_events() {
+    let sink =
+      IndicatifEventSink::with_draw_target(ProgressDrawTarget::hidden());
+    sink
+      .on_event(DownloadEvent::DownloadStarted { url_count: 2 })
+      .await;
+    for (index, filename) in ["a.csv", "b.csv"].into_iter().enumerate() {
+      sink
+        .on_event(DownloadEvent::FileStarted {
+          index,
+          url: format!("https://example.com/{filename}"),
+          filename: filename.to_string()
+        })
+        .await;
+    }
+    assert_eq!(sink.active(), 2);
+
+    // Unknown length keeps the spinner, a known one switches to a bar
+    sink
+      .on_event(DownloadEvent::FileProgress {
+        index: 0,
+        bytes_downloaded: 10,
+        total_bytes: None,
+        percentage: 0.0,
+        decompressed_bytes: None
+      })
+      .await;
+    sink
+      .on_event(DownloadEvent::FileProgress {
+        index: 1,
+        bytes_downloaded: 50,
+        total_bytes: Some(100),
+        percentage: 50.0,
+        decompressed_bytes: None
+      })
+      .await;
+    {
+      let files = sink.files.lock().unwrap();
+      assert!(!files[&0].sized);
+      assert_eq!(files[&0].bar.position(), 10);
+      assert_eq!(files[&1].bar.length(), Some(100));
+    }
+
+    sink
+      .on_event(DownloadEvent::FileCompleted {
+        index: 1,
+        filename: "b.csv".to_string(),
+        bytes_downloaded: 100,
+        duration: Duration::from_millis(5),
+        mirror: None
+      })
+      .await;
+    sink
+      .on_event(DownloadEvent::FileFailed {
+        index: 0,
+        url: "https://example.com/a.csv".to_string(),
+        error: "gone".to_string(),
+        retry_count: 0
+      })
+      .await;
+    assert_eq!(sink.active(), 0);
+    assert_eq!(sink.total().position(), 2);
+    assert_eq!(sink.total().length(), Some(2));
+  }
+}
@@ -0,0 +1,601 @@
+//! Content-addressed cache shared between downloaders
+//!
+//! A [`Cache`] keeps finished downloads in a directory that any number of
+//! downloaders, e.g. of different projects, can point at. Each file is
+//! stored once under its SHA-256 digest in `objects/`, and `urls/` records
+//! for every URL the digest and validators of the version downloaded last.
+//! A download is served from the cache without a request when its expected
+//! SHA-256 checksum is stored, or once the server confirms that the
+//! recorded version is still current. The file is then reflinked,
+//! hardlinked or copied to the target instead of being downloaded again.
+//!
+//! Hardlinked files share their contents with the cache, so a target
+//! modified in place corrupts the cached copy too; [`Cache::verify`] finds
+//! and drops such entries.
+
+use crate::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+  path::{Path, PathBuf},
+  sync::atomic::{AtomicUsize, Ordering}
+};
+use tokio::fs::{
+  copy, create_dir_all, hard_link, read_dir, remove_file, rename
+};
+
+/// A cache directory shared between downloaders.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use downloader::{Cache, Downloader};
+///
+/// # async fn run() -> downloader::Result<()> {
+/// let cache = Cache::new("/var/cache/datasets").with_max_size(20 << 30);
+/// let mut downloader = Downloader::new(
+///   vec!["https://datasets.imdbws.com/title.basics.tsv.gz"],
+///   "data"
+/// )?;
+/// downloader.with_cache(Some(cache.clone()));
+/// downloader.start().await?.await?;
+///
+/// let verification = cache.verify().await?;
+/// println!("{} corrupt files dropped", verification.corrupt.len());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+  dir: PathBuf,
+  max_size: Option<u64>
+}
+
+/// The version of a URL stored in the cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+  /// URL the file was downloaded from
+  pub url: String,
+
+  /// SHA-256 digest of the file, as lowercase hex
+  pub digest: String,
+
+  /// Size of the file in bytes
+  pub size: u64,
+
+  /// Validators of the downloaded version of the resource
+  pub validators: Validators,
+
+  /// Time the entry was last stored or served
+  pub last_used: DateTime<Utc>
+}
+
+/// How a cached file was placed at its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+  /// A copy-on-write clone sharing the blocks of the cached file
+  Reflink,
+
+  /// A second name of the cached file
+  Hardlink,
+
+  /// A full copy
+  Copy
+}
+
+/// Outcome of [`Cache::verify`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+  /// Number of files hashed
+  pub checked: usize,
+
+  /// Digests of the files that no longer matched and were removed
+  pub corrupt: Vec<String>
+}
+
+/// A file in `objects/`.
+struct Object {
+  digest: String,
+  path: PathBuf,
+  size: u64
+}
+
+impl Cache {
+  /// Creates a cache in `dir`, which is created on the first download
+  /// stored.
+  pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+    Self {
+      dir: dir.into(),
+      max_size: None
+    }
+  }
+
+  /// Sets the size the cache is trimmed to after storing a download,
+  /// evicting the least recently used files first.
+  pub fn with_max_size(mut self, bytes: u64) -> Self {
+    self.max_size = Some(bytes);
+    self
+  }
+
+  /// Returns the cache directory.
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// Returns the size limit, if any.
+  pub fn max_size(&self) -> Option<u64> {
+    self.max_size
+  }
+
+  /// Returns the entry of `url` if its file is still cached.
+  pub async fn lookup(&self, url: &str) -> Option<Entry> {
+    let entry = read_entry(&self.entry_path(url)).await?;
+    self.contains(&entry.digest).then_some(entry)
+  }
+
+  /// Returns true if a file with the SHA-256 `digest` is cached.
+  pub fn contains(&self, digest: &str) -> bool {
+    self.object_path(digest).is_file()
+  }
+
+  /// Places the cached file with the SHA-256 `digest` at `target`,
+  /// replacing any file there.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the file is not cached or cannot be
+  /// placed.
+  pub async fn link(&self, digest: &str, target: &Path) -> Result<Link> {
+    self
+      .try_link(digest, target)
+      .await?
+      .ok_or_else(|| Error::FileSystem {
+        message: format!("File {digest} is not cached")
+      })
+  }
+
+  /// Places the cached file with the SHA-256 `digest` at `target`, or
+  /// returns `None` if it is not cached, e.g. because another downloader
+  /// evicted it.
+  pub(crate) async fn try_link(
+    &self,
+    digest: &str,
+    target: &Path
+  ) -> Result<Option<Link>> {
+    let object = self.object_path(digest);
+    if let Err(e) = remove_file(target).await
+      && e.kind() != std::io::ErrorKind::NotFound
+    {
+      debug!("Failed to remove {}: {}", target.display(), e);
+    }
+
+    match place(&object, target).await {
+      Ok(link) => Ok(Some(link)),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(Error::FileSystem {
+        message: format!(
+          "Failed to link cached file {} to '{}': {e}",
+          digest,
+          target.display()
+        )
+      })
+    }
+  }
+
+  /// Stores the file at `path` with the SHA-256 `digest` as the version of
+  /// `url` described by `validators`, then trims the cache to its size
+  /// limit.
+  ///
+  /// A file already cached under `digest` is kept as is, so storing the
+  /// same content for another URL only adds an entry.
+  pub(crate) async fn insert(
+    &self,
+    url: &str,
+    validators: &Validators,
+    path: &Path,
+    digest: &str
+  ) -> Result<()> {
+    let object = self.object_path(digest);
+    if !object.is_file() {
+      if let Some(parent) = object.parent() {
+        create_dir_all(parent).await?;
+      }
+
+      // Concurrent downloaders only ever see complete files
+      let temp = temp_path(&object);
+      place(path, &temp).await?;
+      rename(&temp, &object).await?;
+    }
+
+    let size = tokio::fs::metadata(&object).await?.len();
+    self.touch(url, validators, digest, size).await?;
+    self.evict().await?;
+    Ok(())
+  }
+
+  /// Records that `url` was served with the cached file of `digest`.
+  pub(crate) async fn touch(
+    &self,
+    url: &str,
+    validators: &Validators,
+    digest: &str,
+    size: u64
+  ) -> Result<()> {
+    let entry = Entry {
+      url: url.to_string(),
+      digest: digest.to_string(),
+      size,
+      validators: validators.clone(),
+      last_used: Utc::now()
+    };
+    let contents =
+      serde_json::to_vec_pretty(&entry).map_err(|e| Error::FileSystem {
+        message: format!("Failed to serialize cache entry: {e}")
+      })?;
+
+    let path = self.entry_path(url);
+    if let Some(parent) = path.parent() {
+      create_dir_all(parent).await?;
+    }
+    let temp = temp_path(&path);
+    tokio::fs::write(&temp, contents).await?;
+    rename(&temp, &path).await?;
+    Ok(())
+  }
+
+  /// Returns the combined size of the cached files.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the cache cannot be read.
+  pub async fn size(&self) -> Result<u64> {
+    Ok(self.objects().await?.iter().map(|object| object.size).sum())
+  }
+
+  /// Removes the least recently used files until the cache fits its size
+  /// limit, returning the bytes freed.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the cache cannot be read.
+  pub async fn evict(&self) -> Result<u64> {
+    let Some(max_size) = self.max_size else {
+      return Ok(0);
+    };
+
+    let mut objects = self.objects().await?;
+    let mut size: u64 = objects.iter().map(|object| object.size).sum();
+    if size <= max_size {
+      return Ok(0);
+    }
+
+    // Files no entry refers to go first
+    let entries = self.entries().await?;
+    objects.sort_by_cached_key(|object| {
+      entries
+        .iter()
+        .filter(|(_, entry)| entry.digest == object.digest)
+        .map(|(_, entry)| entry.last_used)
+        .max()
+    });
+
+    let mut freed = 0;
+    for object in objects {
+      if size <= max_size {
+        break;
+      }
+      debug!("Evicting {} from the download cache", object.digest);
+      self.remove(&object.digest, &entries).await?;
+      size -= object.size;
+      freed += object.size;
+    }
+    Ok(freed)
+  }
+
+  /// Hashes every cached file again, removing the files whose contents no
+  /// longer match their digest together with their entries.
+  ///
+  /// Entries whose file is missing are removed as well.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the cache cannot be read.
+  pub async fn verify(&self) -> Result<Verification> {
+    let entries = self.entries().await?;
+    let mut verification = Verification::default();
+    for object in self.objects().await? {
+      let digest =
+        checksum::hash_file(&object.path, checksum::Algorithm::Sha256).await?;
+      verification.checked += 1;
+      if digest != object.digest {
+        warn!("Removing corrupt file {} from the cache", object.digest);
+        self.remove(&object.digest, &entries).await?;
+        verification.corrupt.push(object.digest);
+      }
+    }
+
+    for (path, entry) in &entries {
+      if !self.contains(&entry.digest) {
+        let _ = remove_file(path).await;
+      }
+    }
+    Ok(verification)
+  }
+
+  /// Removes the cached file with the SHA-256 `digest`, e.g. one found to
+  /// be corrupt, and the entries referring to it.
+  pub(crate) async fn discard(&self, digest: &str) -> Result<()> {
+    let entries = self.entries().await?;
+    self.remove(digest, &entries).await
+  }
+
+  /// Removes a cached file and those of `entries` referring to it.
+  async fn remove(
+    &self,
+    digest: &str,
+    entries: &[(PathBuf, Entry)]
+  ) -> Result<()> {
+    if let Err(e) = remove_file(self.object_path(digest)).await
+      && e.kind() != std::io::ErrorKind::NotFound
+    {
+      return Err(e.into());
+    }
+    for (path, entry) in entries {
+      if entry.digest == digest {
+        let _ = remove_file(path).await;
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns the cached files.
+  async fn objects(&self) -> Result<Vec<Object>> {
+    let mut objects = Vec::new();
+    for dir in list(&self.dir.join("objects")).await? {
+      for path in list(&dir).await? {
+        // Skip files still being stored
+        let Some(digest) = path
+          .file_name()
+          .and_then(|name| name.to_str())
+          .filter(|name| !name.contains('.'))
+          .map(str::to_string)
+        else {
+          continue;
+        };
+        let size = tokio::fs::metadata(&path).await?.len();
+        objects.push(Object { digest, path, size });
+      }
+    }
+    Ok(objects)
+  }
+
+  /// Returns the entries with the paths they are stored at.
+  async fn entries(&self) -> Result<Vec<(PathBuf, Entry)>> {
+    let mut entries = Vec::new();
+    for path in list(&self.dir.join("urls")).await? {
+      if path.extension().is_some_and(|ext| ext == "json")
+        && let Some(entry) = read_entry(&path).await
+      {
+        entries.push((path, entry));
+      }
+    }
+    Ok(entries)
+  }
+
+  fn object_path(&self, digest: &str) -> PathBuf {
+    let prefix = digest.get(..2).unwrap_or(digest);
+    self.dir.join("objects").join(prefix).join(digest)
+  }
+
+  fn entry_path(&self, url: &str) -> PathBuf {
+    let mut hasher = checksum::Hasher::new(checksum::Algorithm::Sha256);
+    hasher.update(url.as_bytes());
+    self
+      .dir
+      .join("urls")
+      .join(format!("{}.json", hasher.finalize()))
+  }
+}
+
+/// Returns a path next to `path` to write it under before renaming it,
+/// distinct for every call so that concurrent writers do not share it.
+fn temp_path(path: &Path) -> PathBuf {
+  static NEXT: AtomicUsize = AtomicUsize::new(0);
+  path.with_extension(format!(
+    "tmp-{}-{}",
+    std::process::id(),
+    NEXT.fetch_add(1, Ordering::Relaxed)
+  ))
+}
+
+/// Places `source` at `target`, preferring a reflink, then a hardlink, then
+/// a copy.
+async fn place(source: &Path, target: &Path) -> std::io::Result<Link> {
+  if disk::reflink(source, target).await.is_ok() {
+    return Ok(Link::Reflink);
+  }
+  if hard_link(source, target).await.is_ok() {
+    return Ok(Link::Hardlink);
+  }
+  copy(source, target).await?;
+  Ok(Link::Copy)
+}
+
+/// Returns the paths in `dir`, or none if it does not exist.
+async fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+  let mut entries = match read_dir(dir).await {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(e.into())
+  };
+
+  let mut paths = Vec::new();
+  while let Some(entry) = entries.next_entry().await? {
+    paths.push(entry.path());
+  }
+  Ok(paths)
+}
+
+async fn read_entry(path: &Path) -> Option<Entry> {
+  let contents = tokio::fs::read(path).await.ok()?;
+  match serde_json::from_slice(&contents) {
+    Ok(entry) => Some(entry),
+    Err(e) => {
+      debug!("Ignoring unreadable cache entry {}: {}", path.display(), e);
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+  use tempfile::TempDir;
+
+  fn sha256(data: &[u8]) -> String {
+    let mut hasher = checksum::Hasher::new(checksum::Algorithm::Sha256);
+    hasher.update(data);
+    hasher.finalize()
+  }
+
+  async fn download(
+    transport: &Arc<MemoryTransport>,
+    cache: &Cache,
+    entry: ManifestEntry,
+    target: &Path
+  ) -> TaskResult {
+    let config = Config::builder().cache(Some(cache.clone())).build();
+    let mut downloader =
+      Downloader::new_with_config(vec![entry.url.clone()], target, config)
+        .unwrap();
+    downloader
+      .with_transport(transport.clone())
+      .with_entry(entry);
+    let report = downloader.start().await.unwrap().await.unwrap();
+    report.entries[0].result().unwrap().clone()
+  }
+
+  fn downloads(transport: &MemoryTransport) -> usize {
+    transport
+      .requests()
+      .iter()
+      .filter(|request| request.method == reqwest::Method::GET)
+      .count()
+  }
+
+  #[tokio::test]
+  async fn test_shared_between_downloaders() {
+    let url = "https://example.com/title.basics.tsv";
+    let data = b"tconst\ttitleType\n".repeat(100);
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new(data.clone()).header(reqwest::header::ETAG, "\"v1\"")
+    );
+    let dir = TempDir::new().unwrap();
+    let cache = Cache::new(dir.path().join("cache"));
+
+    let first = download(
+      &transport,
+      &cache,
+      ManifestEntry::new(url),
+      &dir.path().join("imdb")
+    )
+    .await;
+    assert!(!first.cached);
+    assert_eq!(first.bytes_downloaded, data.len() as u64);
+    let entry = cache.lookup(url).await.unwrap();
+    assert_eq!(entry.digest, sha256(&data));
+    assert_eq!(entry.validators.etag.as_deref(), Some("\"v1\""));
+
+    // The server confirms the cached version, so nothing is transferred
+    let second = download(
+      &transport,
+      &cache,
+      ManifestEntry::new(url),
+      &dir.path().join("playground")
+    )
+    .await;
+    assert!(second.cached);
+    assert_eq!(second.bytes_downloaded, 0);
+    assert_eq!(std::fs::read(&second.path).unwrap(), data);
+    let last = transport.requests().pop().unwrap();
+    assert_eq!(last.headers[reqwest::header::IF_NONE_MATCH], "\"v1\"");
+
+    // A known checksum is served without a request
+    let requests = downloads(&transport);
+    let third = download(
+      &transport,
+      &cache,
+      ManifestEntry::new(url)
+        .checksum(Checksum::sha256(sha256(&data)).unwrap()),
+      &dir.path().join("other")
+    )
+    .await;
+    assert!(third.cached);
+    assert_eq!(std::fs::read(&third.path).unwrap(), data);
+    assert_eq!(downloads(&transport), requests);
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_stores_and_missing_objects() {
+    let dir = TempDir::new().unwrap();
+    let cache = Cache::new(dir.path().join("cache"));
+    let path = dir.path().join("file");
+    std::fs::write(&path, "0123456789").unwrap();
+    let digest = sha256(b"0123456789");
+    let validators = Validators::default();
+
+    // Tasks storing the same file at once do not share temp files
+    let stores = (0..8).map(|_| {
+      cache.insert("https://example.com/file", &validators, &path, &digest)
+    });
+    for result in futures::future::join_all(stores).await {
+      result.unwrap();
+    }
+    assert!(cache.contains(&digest));
+
+    // A file evicted in the meantime is a miss rather than an error
+    let target = dir.path().join("target");
+    cache.discard(&digest).await.unwrap();
+    assert_eq!(cache.try_link(&digest, &target).await.unwrap(), None);
+    assert!(cache.link(&digest, &target).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_evicts_and_verifies() {
+    let dir = TempDir::new().unwrap();
+    let cache = Cache::new(dir.path().join("cache")).with_max_size(15);
+    let validators = Validators::default();
+    let files: Vec<_> = ["0123456789", "abcdefghij"]
+      .iter()
+      .enumerate()
+      .map(|(index, data)| {
+        let path = dir.path().join(format!("file-{index}"));
+        std::fs::write(&path, data).unwrap();
+        (path, sha256(data.as_bytes()))
+      })
+      .collect();
+
+    // Storing the second file evicts the least recently used first one
+    for (index, (path, digest)) in files.iter().enumerate() {
+      let url = format!("https://example.com/{index}");
+      cache.insert(&url, &validators, path, digest).await.unwrap();
+    }
+    assert!(!cache.contains(&files[0].1));
+    assert!(cache.lookup("https://example.com/0").await.is_none());
+    assert!(cache.contains(&files[1].1));
+    assert_eq!(cache.size().await.unwrap(), 10);
+
+    let verification = cache.verify().await.unwrap();
+    assert_eq!(verification.checked, 1);
+    assert!(verification.corrupt.is_empty());
+
+    std::fs::write(cache.object_path(&files[1].1), "tampered!!").unwrap();
+    let verification = cache.verify().await.unwrap();
+    assert_eq!(verification.corrupt, vec![files[1].1.clone()]);
+    assert!(!cache.contains(&files[1].1));
+    assert!(cache.lookup("https://example.com/1").await.is_none());
+  }
+}
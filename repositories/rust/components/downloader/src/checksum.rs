@@ -0,0 +1,329 @@
+//! Checksum verification for downloaded files
+//!
+//! This module provides expected digests that can be attached to a URL,
+//! incremental hashers fed while a download streams in, and `.sha256`
+//! sidecar files that let later pipeline stages check integrity without
+//! hashing the file again.
+
+use crate::*;
+use sha2::Digest;
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  str::FromStr
+};
+use tokio::io::AsyncReadExt;
+
+/// Hash algorithms supported for checksum verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+  Sha256,
+  Sha1,
+  Md5
+}
+
+impl Algorithm {
+  /// Returns the length of a hex-encoded digest for this algorithm.
+  pub fn hex_len(&self) -> usize {
+    match self {
+      Algorithm::Sha256 => 64,
+      Algorithm::Sha1 => 40,
+      Algorithm::Md5 => 32
+    }
+  }
+}
+
+impl fmt::Display for Algorithm {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Algorithm::Sha256 => write!(f, "sha256"),
+      Algorithm::Sha1 => write!(f, "sha1"),
+      Algorithm::Md5 => write!(f, "md5")
+    }
+  }
+}
+
+impl FromStr for Algorithm {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s.to_ascii_lowercase().replace('-', "").as_str() {
+      "sha256" => Ok(Algorithm::Sha256),
+      "sha1" => Ok(Algorithm::Sha1),
+      "md5" => Ok(Algorithm::Md5),
+      other => Err(Error::validation_error(format!(
+        "Unsupported checksum algorithm '{other}'"
+      )))
+    }
+  }
+}
+
+/// An expected digest for a downloaded file.
+///
+/// # Examples
+///
+/// ```rust
+/// use downloader::Checksum;
+///
+/// let checksum: Checksum =
+///   "md5:d41d8cd98f00b204e9800998ecf8427e".parse().unwrap();
+/// assert!(checksum.matches("D41D8CD98F00B204E9800998ECF8427E"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+  /// Algorithm used to compute the digest
+  pub algorithm: Algorithm,
+
+  /// Expected digest as lowercase hex
+  pub expected: String
+}
+
+impl Checksum {
+  /// Creates a checksum, validating the hex digest for the algorithm.
+  pub fn new<S: AsRef<str>>(algorithm: Algorithm, expected: S) -> Result<Self> {
+    let expected = expected.as_ref().trim().to_ascii_lowercase();
+
+    if expected.len() != algorithm.hex_len()
+      || !expected.chars().all(|c| c.is_ascii_hexdigit())
+    {
+      return Err(Error::validation_error(format!(
+        "Invalid {algorithm} digest '{expected}': expected {} hex characters",
+        algorithm.hex_len()
+      )));
+    }
+
+    Ok(Self {
+      algorithm,
+      expected
+    })
+  }
+
+  /// Creates a SHA-256 checksum.
+  pub fn sha256<S: AsRef<str>>(expected: S) -> Result<Self> {
+    Self::new(Algorithm::Sha256, expected)
+  }
+
+  /// Creates a SHA-1 checksum.
+  pub fn sha1<S: AsRef<str>>(expected: S) -> Result<Self> {
+    Self::new(Algorithm::Sha1, expected)
+  }
+
+  /// Creates an MD5 checksum.
+  pub fn md5<S: AsRef<str>>(expected: S) -> Result<Self> {
+    Self::new(Algorithm::Md5, expected)
+  }
+
+  /// Returns true if `digest` (hex, any case) matches the expected digest.
+  pub fn matches(&self, digest: &str) -> bool {
+    self.expected.eq_ignore_ascii_case(digest.trim())
+  }
+
+  /// Checks a computed digest against the expected one.
+  pub fn verify(&self, url: &str, digest: &str) -> Result<()> {
+    if self.matches(digest) {
+      return Ok(());
+    }
+
+    Err(Error::content_validation_error(
+      url,
+      &format!(
+        "{} mismatch: expected {}, got {}",
+        self.algorithm, self.expected, digest
+      )
+    ))
+  }
+}
+
+impl FromStr for Checksum {
+  type Err = Error;
+
+  /// Parses `algorithm:hex`, or bare hex with the algorithm inferred from
+  /// the digest length.
+  fn from_str(s: &str) -> Result<Self> {
+    if let Some((algorithm, digest)) = s.split_once(':') {
+      return Self::new(algorithm.trim().parse()?, digest);
+    }
+
+    let algorithm = match s.trim().len() {
+      64 => Algorithm::Sha256,
+      40 => Algorithm::Sha1,
+      32 => Algorithm::Md5,
+      len => {
+        return Err(Error::validation_error(format!(
+          "Cannot infer checksum algorithm from a {len} character digest"
+        )));
+      }
+    };
+
+    Self::new(algorithm, s)
+  }
+}
+
+impl fmt::Display for Checksum {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.algorithm, self.expected)
+  }
+}
+
+/// An incremental hasher for one of the supported algorithms.
+#[derive(Debug, Clone)]
+pub enum Hasher {
+  Sha256(sha2::Sha256),
+  Sha1(sha1::Sha1),
+  Md5(md5::Md5)
+}
+
+impl Hasher {
+  /// Creates a hasher for the given algorithm.
+  pub fn new(algorithm: Algorithm) -> Self {
+    match algorithm {
+      Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+      Algorithm::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+      Algorithm::Md5 => Hasher::Md5(md5::Md5::new())
+    }
+  }
+
+  /// Returns the algorithm of this hasher.
+  pub fn algorithm(&self) -> Algorithm {
+    match self {
+      Hasher::Sha256(_) => Algorithm::Sha256,
+      Hasher::Sha1(_) => Algorithm::Sha1,
+      Hasher::Md5(_) => Algorithm::Md5
+    }
+  }
+
+  /// Feeds data into the hasher.
+  pub fn update(&mut self, data: &[u8]) {
+    match self {
+      Hasher::Sha256(h) => h.update(data),
+      Hasher::Sha1(h) => h.update(data),
+      Hasher::Md5(h) => h.update(data)
+    }
+  }
+
+  /// Finishes hashing and returns the digest as lowercase hex.
+  pub fn finalize(self) -> String {
+    match self {
+      Hasher::Sha256(h) => format!("{:x}", h.finalize()),
+      Hasher::Sha1(h) => format!("{:x}", h.finalize()),
+      Hasher::Md5(h) => format!("{:x}", h.finalize())
+    }
+  }
+}
+
+/// Feeds the contents of a file into a set of hashers.
+pub async fn hash_file_into(path: &Path, hashers: &mut [Hasher]) -> Result<()> {
+  let mut file = tokio::fs::File::open(path).await?;
+  let mut buffer = vec![0u8; 64 * 1024];
+
+  loop {
+    let read = file.read(&mut buffer).await?;
+    if read == 0 {
+      return Ok(());
+    }
+    for hasher in hashers.iter_mut() {
+      hasher.update(&buffer[..read]);
+    }
+  }
+}
+
+/// Computes the digest of a file.
+pub async fn hash_file(path: &Path, algorithm: Algorithm) -> Result<String> {
+  let mut hashers = [Hasher::new(algorithm)];
+  hash_file_into(path, &mut hashers).await?;
+  let [hasher] = hashers;
+  Ok(hasher.finalize())
+}
+
+/// Returns the `.sha256` sidecar path for a file.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+  let mut name = path.as_os_str().to_os_string();
+  name.push(".sha256");
+  PathBuf::from(name)
+}
+
+/// Writes a `.sha256` sidecar in `sha256sum` format next to a file.
+pub async fn write_sidecar(path: &Path, digest: &str) -> Result<()> {
+  let filename = path
+    .file_name()
+    .map(|n| n.to_string_lossy().into_owned())
+    .unwrap_or_default();
+
+  tokio::fs::write(sidecar_path(path), format!("{digest}  {filename}\n"))
+    .await
+    .map_err(|e| Error::FileSystem {
+      message: format!("Failed to write checksum sidecar: {e}")
+    })
+}
+
+/// Reads the SHA-256 digest from a file's `.sha256` sidecar, if present.
+pub async fn read_sidecar(path: &Path) -> Option<Checksum> {
+  let contents = tokio::fs::read_to_string(sidecar_path(path)).await.ok()?;
+  let digest = contents.split_whitespace().next()?;
+  Checksum::sha256(digest).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EMPTY_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+  #[test]
+  fn test_parse_checksum() {
+    let checksum: Checksum = format!("SHA-256:{EMPTY_SHA256}").parse().unwrap();
+    assert_eq!(checksum.algorithm, Algorithm::Sha256);
+
+    let checksum: Checksum =
+      "d41d8cd98f00b204e9800998ecf8427e".parse().unwrap();
+    assert_eq!(checksum.algorithm, Algorithm::Md5);
+
+    assert!("sha1:abc".parse::<Checksum>().is_err());
+    assert!("crc32:00000000".parse::<Checksum>().is_err());
+  }
+
+  #[test]
+  fn test_hasher_digests() {
+    let mut sha1 = Hasher::new(Algorithm::Sha1);
+    sha1.update(b"abc");
+    assert_eq!(sha1.finalize(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+    let mut md5 = Hasher::new(Algorithm::Md5);
+    md5.update(b"abc");
+    assert_eq!(md5.finalize(), "900150983cd24fb0d6963f7d28e17f72");
+
+    assert_eq!(Hasher::new(Algorithm::Sha256).finalize(), EMPTY_SHA256);
+  }
+
+  #[test]
+  fn test_verify_mismatch() {
+    let checksum = Checksum::sha256(EMPTY_SHA256).unwrap();
+    assert!(
+      checksum
+        .verify("https://example.com/a", EMPTY_SHA256)
+        .is_ok()
+    );
+
+    let error = checksum
+      .verify("https://example.com/a", &"0".repeat(64))
+      .unwrap_err();
+    assert!(matches!(error, Error::ContentValidation { .. }));
+  }
+
+  #[tokio::test]
+  async fn test_sidecar_roundtrip() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("empty.tsv.gz");
+    tokio::fs::write(&path, b"").await.unwrap();
+
+    let digest = hash_file(&path, Algorithm::Sha256).await.unwrap();
+    write_sidecar(&path, &digest).await.unwrap();
+
+    let contents = tokio::fs::read_to_string(sidecar_path(&path))
+      .await
+      .unwrap();
+    assert_eq!(contents, format!("{EMPTY_SHA256}  empty.tsv.gz\n"));
+    assert!(read_sidecar(&path).await.unwrap().matches(EMPTY_SHA256));
+  }
+}
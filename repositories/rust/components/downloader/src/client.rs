@@ -0,0 +1,258 @@
+//! HTTP client construction from the configuration
+//!
+//! The downloader's `reqwest` client carries the network settings of a
+//! [`Config`]: timeouts, redirect limit, TLS trust and client certificates,
+//! proxies and connection pooling. Failed requests are mapped back onto the
+//! typed [`Error`] variants using the same settings.
+
+use crate::*;
+use reqwest::{Certificate, Identity, NoProxy, Proxy, redirect};
+use std::{error::Error as _, path::Path};
+
+/// Builds an HTTP client with the network settings of `config`.
+///
+/// Without an explicit proxy, the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY`
+/// and `NO_PROXY` environment variables are honored.
+///
+/// # Errors
+///
+/// Returns `Error::Configuration` if a certificate, key or proxy setting
+/// cannot be used.
+pub fn build_client(config: &Config) -> Result<ReqwestClient> {
+  let mut builder = ReqwestClient::builder()
+    .connect_timeout(config.connect_timeout)
+    .read_timeout(config.timeout)
+    .redirect(redirect::Policy::limited(config.max_redirects))
+    .danger_accept_invalid_certs(!config.verify_ssl)
+    .pool_idle_timeout(config.pool_idle_timeout);
+
+  if let Some(agent) = &config.user_agent {
+    builder = builder.user_agent(agent);
+  }
+
+  if let Some(max) = config.pool_max_idle_per_host {
+    builder = builder.pool_max_idle_per_host(max);
+  }
+
+  if let Some(path) = &config.ca_bundle {
+    let pem = read(path, "CA bundle")?;
+    let certificates = Certificate::from_pem_bundle(&pem)
+      .map_err(|e| invalid(path, "CA bundle", e))?;
+    if certificates.is_empty() {
+      return Err(Error::Configuration {
+        message: format!("No certificates in CA bundle '{}'", path.display())
+      });
+    }
+    for certificate in certificates {
+      builder = builder.add_root_certificate(certificate);
+    }
+  }
+
+  if let Some(identity) = &config.client_identity {
+    builder = builder.identity(load_identity(identity)?);
+  }
+
+  for proxy in proxies(config)? {
+    builder = builder.proxy(proxy);
+  }
+
+  builder.build().map_err(|e| Error::Configuration {
+    message: format!("Failed to build HTTP client: {e}")
+  })
+}
+
+/// Maps a failed request onto the matching error variant.
+pub(crate) fn request_error(
+  config: &Config,
+  url: &str,
+  error: reqwest::Error
+) -> Error {
+  if error.is_timeout() {
+    let duration = if error.is_connect() {
+      config.connect_timeout
+    } else {
+      config.timeout
+    };
+    Error::timeout_error(url, duration.as_secs())
+  } else if error.is_redirect() {
+    Error::too_many_redirects(url, config.max_redirects, config.max_redirects)
+  } else if let Some(message) = certificate_problem(&error) {
+    Error::certificate_error(url, message.as_str())
+  } else {
+    Error::RequestFailed {
+      url: url.to_string(),
+      download: error
+    }
+  }
+}
+
+/// Returns the message of a TLS certificate failure behind `error`, if any.
+fn certificate_problem(error: &reqwest::Error) -> Option<String> {
+  let mut source = error.source();
+  while let Some(cause) = source {
+    let message = cause.to_string();
+    if message.to_ascii_lowercase().contains("certificate") {
+      return Some(message);
+    }
+    source = cause.source();
+  }
+  None
+}
+
+/// Creates the configured proxies, most specific first.
+fn proxies(config: &Config) -> Result<Vec<Proxy>> {
+  let no_proxy = match &config.no_proxy {
+    Some(hosts) => NoProxy::from_string(hosts),
+    None => NoProxy::from_env()
+  };
+  let configure = |url: &str, proxy: reqwest::Result<Proxy>| {
+    proxy
+      .map(|proxy| proxy.no_proxy(no_proxy.clone()))
+      .map_err(|e| Error::Configuration {
+        message: format!("Invalid proxy '{url}': {e}")
+      })
+  };
+
+  let mut proxies = Vec::new();
+  if let Some(url) = &config.http_proxy {
+    proxies.push(configure(url, Proxy::http(url))?);
+  }
+  if let Some(url) = &config.https_proxy {
+    proxies.push(configure(url, Proxy::https(url))?);
+  }
+  if let Some(url) = &config.proxy {
+    proxies.push(configure(url, Proxy::all(url))?);
+  }
+  Ok(proxies)
+}
+
+/// Loads a client certificate and key.
+fn load_identity(identity: &ClientIdentity) -> Result<Identity> {
+  match identity {
+    ClientIdentity::Pkcs12 { path, password } => {
+      let der = read(path, "client certificate")?;
+      Identity::from_pkcs12_der(&der, password)
+        .map_err(|e| invalid(path, "client certificate", e))
+    }
+    ClientIdentity::Pem { cert, key } => {
+      let cert_pem = read(cert, "client certificate")?;
+      let key_pem = read(key, "client key")?;
+      Identity::from_pkcs8_pem(&cert_pem, &key_pem)
+        .map_err(|e| invalid(cert, "client certificate", e))
+    }
+  }
+}
+
+/// Reads a certificate or key file.
+fn read(path: &Path, what: &str) -> Result<Vec<u8>> {
+  std::fs::read(path).map_err(|e| Error::Configuration {
+    message: format!("Failed to read {what} '{}': {e}", path.display())
+  })
+}
+
+/// Creates the error for an unusable certificate or key file.
+fn invalid(path: &Path, what: &str, error: reqwest::Error) -> Error {
+  Error::Configuration {
+    message: format!("Invalid {what} '{}': {error}", path.display())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_build_default_client() {
+    assert!(build_client(&Config::default()).is_ok());
+  }
+
+  #[test]
+  fn test_build_client_with_proxies() {
+    let config = Config::builder()
+      .proxy("socks5://127.0.0.1:1080")
+      .https_proxy("http://proxy.example.com:3128")
+      .no_proxy("localhost,.internal.example.com")
+      .pool_max_idle_per_host(4)
+      .build();
+    assert!(build_client(&config).is_ok());
+
+    let config = Config::builder().proxy("not a proxy url").build();
+    assert!(matches!(
+      build_client(&config),
+      Err(Error::Configuration { .. })
+    ));
+  }
+
+  #[test]
+  fn test_invalid_ca_bundle() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("ca.pem");
+    std::fs::write(&path, "not a certificate").unwrap();
+
+    let missing = Config::builder().ca_bundle(temp_dir.path().join("missing"));
+    assert!(matches!(
+      build_client(&missing.build()),
+      Err(Error::Configuration { .. })
+    ));
+
+    let invalid = Config::builder().ca_bundle(&path).build();
+    assert!(matches!(
+      build_client(&invalid),
+      Err(Error::Configuration { .. })
+    ));
+  }
+
+  /// Serves every connection with `response`, or never answers if None.
+  async fn serve(response: Option<&'static str>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      while let Ok((mut stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+          use tokio::io::{AsyncReadExt, AsyncWriteExt};
+          let mut buf = [0; 1024];
+          let _ = stream.read(&mut buf).await;
+          match response {
+            Some(response) => {
+              let _ = stream.write_all(response.as_bytes()).await;
+            }
+            None => tokio::time::sleep(Duration::from_secs(5)).await
+          }
+        });
+      }
+    });
+    format!("http://{addr}/file")
+  }
+
+  #[tokio::test]
+  async fn test_redirect_loop_is_too_many_redirects() {
+    let url = serve(Some(
+      "HTTP/1.1 302 Found\r\nLocation: /file\r\nContent-Length: 0\r\n\r\n"
+    ))
+    .await;
+    let config = Config::builder().max_redirects(2).build();
+    let client = build_client(&config).unwrap();
+
+    let error = client.get(&url).send().await.unwrap_err();
+    assert!(matches!(
+      request_error(&config, &url, error),
+      Error::TooManyRedirects { limit: 2, .. }
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_silent_server_is_timeout() {
+    let url = serve(None).await;
+    let config = Config::builder()
+      .timeout(Duration::from_millis(100))
+      .build();
+    let client = build_client(&config).unwrap();
+
+    let error = client.get(&url).send().await.unwrap_err();
+    assert!(matches!(
+      request_error(&config, &url, error),
+      Error::Timeout { .. }
+    ));
+  }
+}
@@ -5,7 +5,7 @@
 //! and progress reporting.
 
 use crate::*;
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 
 /// Comprehensive configuration for the downloader.
 ///
@@ -33,18 +33,27 @@ pub struct Config {
   /// Maximum number of concurrent downloads (None = unlimited)
   pub concurrency_limit: Option<usize>,
 
-  /// Timeout for individual HTTP requests
+  /// Maximum time to wait for the server to send data
   pub timeout: Duration,
 
+  /// Maximum time to wait for a connection to be established
+  pub connect_timeout: Duration,
+
   /// Maximum number of retry attempts for failed downloads
   pub max_retries: usize,
 
   /// Delay between retry attempts
   pub retry_delay: Duration,
 
+  /// Policy deciding which failures are retried and how long to wait
+  pub retry_policy: Arc<dyn RetryPolicy>,
+
   /// Policy for handling existing files
   pub overwrite_policy: OverwritePolicy,
 
+  /// Strategy for renaming files whose target path is already taken
+  pub conflict_strategy: filename::ConflictStrategy,
+
   /// Strategy for generating filenames from URLs
   pub filename_strategy: filename::Strategy,
 
@@ -69,8 +78,89 @@ pub struct Config {
   /// Whether to verify SSL certificates
   pub verify_ssl: bool,
 
-  /// Additional HTTP headers to send with requests
-  pub custom_headers: Vec<(String, String)>
+  /// PEM file with additional root certificates to trust
+  pub ca_bundle: Option<PathBuf>,
+
+  /// Client certificate presented to servers requiring mutual TLS
+  pub client_identity: Option<ClientIdentity>,
+
+  /// Proxy for all requests (`http://`, `https://` or `socks5://` URL)
+  pub proxy: Option<String>,
+
+  /// Proxy for plain HTTP requests, taking precedence over `proxy`
+  pub http_proxy: Option<String>,
+
+  /// Proxy for HTTPS requests, taking precedence over `proxy`
+  pub https_proxy: Option<String>,
+
+  /// Comma-separated hosts and domains to reach without a proxy, in
+  /// `NO_PROXY` syntax (None = use the `NO_PROXY` environment variable)
+  pub no_proxy: Option<String>,
+
+  /// Maximum number of idle connections kept per host (None = unlimited)
+  pub pool_max_idle_per_host: Option<usize>,
+
+  /// How long idle connections are kept (None = forever)
+  pub pool_idle_timeout: Option<Duration>,
+
+  /// Additional HTTP headers to send with requests to every host
+  pub custom_headers: Vec<(String, String)>,
+
+  /// Providers of credentials for the hosts they are scoped to, consulted
+  /// in order
+  pub credentials: Vec<Arc<dyn CredentialProvider>>,
+
+  /// Whether to keep partial downloads and resume them with range requests
+  pub resume: bool,
+
+  /// Whether to record the batch in a journal in the target directory, so
+  /// an interrupted batch can be continued (off by default)
+  pub journal: bool,
+
+  /// Maximum number of ranged segments to split a single file into (1 =
+  /// no segmentation)
+  pub segments: usize,
+
+  /// Minimum size of each segment in bytes
+  pub min_segment_size: u64,
+
+  /// Whether to write a `.sha256` sidecar next to each finished file
+  pub checksum_sidecar: bool,
+
+  /// Bytes to keep free on the target filesystem, checked against the file
+  /// sizes found by a preview before downloads start (None = no check)
+  pub disk_headroom: Option<u64>,
+
+  /// Whether to reserve disk space for temp files of known length, so a
+  /// full disk fails a download as it starts
+  pub preallocate: bool,
+
+  /// Format to decompress files with its extension from while they
+  /// download, saving them without the extension (None = save as is)
+  pub decompress: Option<Decompression>,
+
+  /// Whether to keep the compressed original next to a decompressed file
+  pub keep_compressed: bool,
+
+  /// Steps run on each file as soon as its download finishes
+  pub hooks: Vec<Hook>,
+
+  /// Maximum number of files running their hooks at once
+  pub hook_concurrency: usize,
+
+  /// Time each hook may run before it is stopped and reported as failed
+  /// (None = no limit)
+  pub hook_timeout: Option<Duration>,
+
+  /// Cache shared with other downloaders to serve files from and store
+  /// finished downloads in (None = no cache)
+  pub cache: Option<Cache>,
+
+  /// Maximum combined download rate in bytes per second (None = unlimited)
+  pub bandwidth_limit: Option<u64>,
+
+  /// Maximum download rate in bytes per second for individual hosts
+  pub host_bandwidth_limits: HashMap<String, u64>
 }
 
 impl Default for Config {
@@ -78,9 +168,12 @@ impl Default for Config {
     Self {
       concurrency_limit: Some(5),
       timeout: Duration::from_secs(30),
+      connect_timeout: Duration::from_secs(10),
       max_retries: 3,
       retry_delay: Duration::from_secs(1),
+      retry_policy: Arc::new(ExponentialBackoff::default()),
       overwrite_policy: OverwritePolicy::Error,
+      conflict_strategy: filename::ConflictStrategy::NumericSuffix,
       filename_strategy: filename::Strategy::Smart,
       max_file_size: None,
       user_agent: Some(format!(
@@ -94,7 +187,31 @@ impl Default for Config {
       progress_interval: Duration::from_millis(500),
       event_sink: Arc::new(events::NoOpEventSink),
       verify_ssl: true,
-      custom_headers: Vec::new()
+      ca_bundle: None,
+      client_identity: None,
+      proxy: None,
+      http_proxy: None,
+      https_proxy: None,
+      no_proxy: None,
+      pool_max_idle_per_host: None,
+      pool_idle_timeout: Some(Duration::from_secs(90)),
+      custom_headers: Vec::new(),
+      credentials: Vec::new(),
+      resume: true,
+      journal: false,
+      segments: 1,
+      min_segment_size: 16 * 1024 * 1024,
+      checksum_sidecar: false,
+      disk_headroom: Some(0),
+      preallocate: false,
+      decompress: None,
+      keep_compressed: false,
+      hooks: Vec::new(),
+      hook_concurrency: 2,
+      hook_timeout: Some(Duration::from_secs(600)),
+      cache: None,
+      bandwidth_limit: None,
+      host_bandwidth_limits: HashMap::new()
     }
   }
 }
@@ -141,6 +258,8 @@ impl Config {
       max_file_size: None,
       fetch_metadata: true,
       progress_interval: Duration::from_millis(250),
+      segments: 4,
+      preallocate: true,
       ..Default::default()
     }
   }
@@ -175,6 +294,12 @@ impl ConfigBuilder {
     self
   }
 
+  /// Sets the connection timeout.
+  pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+    self.config.connect_timeout = timeout;
+    self
+  }
+
   /// Sets the maximum number of retry attempts.
   pub fn max_retries(mut self, retries: usize) -> Self {
     self.config.max_retries = retries;
@@ -213,6 +338,21 @@ impl ConfigBuilder {
     self
   }
 
+  /// Replaces existing files only when the server reports a newer version.
+  pub fn overwrite_if_modified(mut self) -> Self {
+    self.config.overwrite_policy = OverwritePolicy::IfModified;
+    self
+  }
+
+  /// Sets the strategy for renaming files whose target path is taken.
+  pub fn conflict_strategy(
+    mut self,
+    strategy: filename::ConflictStrategy
+  ) -> Self {
+    self.config.conflict_strategy = strategy;
+    self
+  }
+
   /// Sets the filename extraction strategy.
   pub fn filename_strategy(mut self, strategy: filename::Strategy) -> Self {
     self.config.filename_strategy = strategy;
@@ -249,6 +389,12 @@ impl ConfigBuilder {
     self
   }
 
+  /// Sets the policy deciding which failures are retried and when.
+  pub fn retry_policy(mut self, policy: Arc<dyn RetryPolicy>) -> Self {
+    self.config.retry_policy = policy;
+    self
+  }
+
   /// Sets a custom event sink for notifications.
   pub fn event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
     self.config.event_sink = sink;
@@ -261,7 +407,68 @@ impl ConfigBuilder {
     self
   }
 
+  /// Trusts the root certificates in a PEM file in addition to the
+  /// system ones.
+  pub fn ca_bundle<P: Into<PathBuf>>(mut self, path: P) -> Self {
+    self.config.ca_bundle = Some(path.into());
+    self
+  }
+
+  /// Sets the client certificate for mutual TLS.
+  pub fn client_identity(mut self, identity: ClientIdentity) -> Self {
+    self.config.client_identity = Some(identity);
+    self
+  }
+
+  /// Sets the proxy for all requests.
+  pub fn proxy<S: Into<String>>(mut self, url: S) -> Self {
+    self.config.proxy = Some(url.into());
+    self
+  }
+
+  /// Sets the proxy for plain HTTP requests.
+  pub fn http_proxy<S: Into<String>>(mut self, url: S) -> Self {
+    self.config.http_proxy = Some(url.into());
+    self
+  }
+
+  /// Sets the proxy for HTTPS requests.
+  pub fn https_proxy<S: Into<String>>(mut self, url: S) -> Self {
+    self.config.https_proxy = Some(url.into());
+    self
+  }
+
+  /// Sets the hosts to reach without a proxy, in `NO_PROXY` syntax.
+  pub fn no_proxy<S: Into<String>>(mut self, hosts: S) -> Self {
+    self.config.no_proxy = Some(hosts.into());
+    self
+  }
+
+  /// Sets the maximum number of idle connections kept per host.
+  pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+    self.config.pool_max_idle_per_host = Some(max);
+    self
+  }
+
+  /// Sets how long idle connections are kept.
+  pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+    self.config.pool_idle_timeout = timeout;
+    self
+  }
+
+  /// Adds a provider of credentials, consulted after those added before.
+  pub fn credential_provider(
+    mut self,
+    provider: Arc<dyn CredentialProvider>
+  ) -> Self {
+    self.config.credentials.push(provider);
+    self
+  }
+
   /// Adds a custom HTTP header.
+  ///
+  /// The header is sent to every host; use a credential provider for
+  /// authentication instead.
   pub fn header<K: Into<String>, V: Into<String>>(
     mut self,
     key: K,
@@ -284,6 +491,103 @@ impl ConfigBuilder {
     self
   }
 
+  /// Enables or disables resuming partial downloads.
+  pub fn resume(mut self, resume: bool) -> Self {
+    self.config.resume = resume;
+    self
+  }
+
+  /// Enables or disables the journal of the batch in the target directory.
+  pub fn journal(mut self, enabled: bool) -> Self {
+    self.config.journal = enabled;
+    self
+  }
+
+  /// Sets the maximum number of segments to split a single file into.
+  pub fn segments(mut self, segments: usize) -> Self {
+    self.config.segments = segments;
+    self
+  }
+
+  /// Sets the minimum size of each segment in bytes.
+  pub fn min_segment_size(mut self, size: u64) -> Self {
+    self.config.min_segment_size = size;
+    self
+  }
+
+  /// Enables or disables writing `.sha256` sidecars for finished files.
+  pub fn checksum_sidecar(mut self, enabled: bool) -> Self {
+    self.config.checksum_sidecar = enabled;
+    self
+  }
+
+  /// Sets the bytes to keep free on the target filesystem, or disables the
+  /// free space check with `None`.
+  pub fn disk_headroom(mut self, headroom: Option<u64>) -> Self {
+    self.config.disk_headroom = headroom;
+    self
+  }
+
+  /// Enables or disables reserving disk space for temp files.
+  pub fn preallocate(mut self, enabled: bool) -> Self {
+    self.config.preallocate = enabled;
+    self
+  }
+
+  /// Sets the format to decompress matching files from while they
+  /// download.
+  pub fn decompress(mut self, format: Option<Decompression>) -> Self {
+    self.config.decompress = format;
+    self
+  }
+
+  /// Enables or disables keeping the compressed original of decompressed
+  /// files.
+  pub fn keep_compressed(mut self, keep: bool) -> Self {
+    self.config.keep_compressed = keep;
+    self
+  }
+
+  /// Adds a hook, run on each file after the hooks added before.
+  pub fn hook(mut self, hook: Hook) -> Self {
+    self.config.hooks.push(hook);
+    self
+  }
+
+  /// Sets the maximum number of files running their hooks at once.
+  pub fn hook_concurrency(mut self, limit: usize) -> Self {
+    self.config.hook_concurrency = limit;
+    self
+  }
+
+  /// Sets the time each hook may run before it is reported as failed.
+  pub fn hook_timeout(mut self, timeout: Option<Duration>) -> Self {
+    self.config.hook_timeout = timeout;
+    self
+  }
+
+  /// Sets the cache to serve files from and store downloads in.
+  pub fn cache(mut self, cache: Option<Cache>) -> Self {
+    self.config.cache = cache;
+    self
+  }
+
+  /// Sets the maximum combined download rate in bytes per second.
+  pub fn bandwidth_limit(mut self, limit: Option<u64>) -> Self {
+    self.config.bandwidth_limit = limit;
+    self
+  }
+
+  /// Sets the maximum download rate in bytes per second for one host.
+  pub fn host_bandwidth_limit<S: Into<String>>(
+    mut self,
+    host: S,
+    limit: u64
+  ) -> Self {
+    self.config.host_bandwidth_limits.insert(host.into(), limit);
+    self
+  }
+
   /// Builds the final configuration.
   pub fn build(self) -> Config {
     self.config
@@ -309,7 +613,21 @@ pub enum OverwritePolicy {
   Error,
 
   /// Automatically rename new files to avoid conflicts
-  Rename
+  Rename,
+
+  /// Re-download existing files only if the remote resource has changed,
+  /// as determined by the validators stored in their sidecar metadata
+  IfModified
+}
+
+/// Client certificate and private key for mutual TLS.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientIdentity {
+  /// PKCS#12 archive holding the certificate chain and key
+  Pkcs12 { path: PathBuf, password: String },
+
+  /// PEM certificate chain and PKCS#8 PEM private key
+  Pem { cert: PathBuf, key: PathBuf }
 }
 
 #[cfg(test)]
@@ -321,8 +639,15 @@ mod tests {
     let config = Config::default();
     assert_eq!(config.concurrency_limit, Some(5));
     assert_eq!(config.timeout, Duration::from_secs(30));
+    assert_eq!(config.connect_timeout, Duration::from_secs(10));
     assert_eq!(config.max_retries, 3);
     assert_eq!(config.overwrite_policy, OverwritePolicy::Error);
+    assert_eq!(config.proxy, None);
+    assert_eq!(
+      config.conflict_strategy,
+      filename::ConflictStrategy::NumericSuffix
+    );
+    assert!(config.resume);
   }
 
   #[test]
@@ -360,5 +685,6 @@ mod tests {
     assert_eq!(large.concurrency_limit, Some(2));
     assert_eq!(large.timeout, Duration::from_secs(300));
     assert_eq!(large.max_file_size, None);
+    assert_eq!(large.segments, 4);
   }
 }
@@ -0,0 +1,169 @@
+//! Cancellation, pause and resume of running downloads
+//!
+//! A [`Control`] is a cloneable handle shared by the executor and every task
+//! of a batch. Tasks check it between chunks and before each attempt, so a
+//! paused batch keeps its permits and connections while it waits, and a
+//! cancelled batch stops promptly with its partial files left in place for a
+//! later resume.
+
+use crate::*;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Run state of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Running,
+  Paused,
+  Cancelled
+}
+
+/// Handle for cancelling, pausing and resuming downloads.
+///
+/// Clones share the same state. Cancellation is final: once cancelled, a
+/// control stays cancelled, and a new batch needs a new control.
+///
+/// # Examples
+///
+/// ```rust
+/// use downloader::Control;
+///
+/// let control = Control::new();
+/// control.pause();
+/// assert!(control.is_paused());
+///
+/// control.cancel();
+/// assert!(control.is_cancelled());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Control {
+  state: Arc<watch::Sender<State>>
+}
+
+impl Default for Control {
+  fn default() -> Self {
+    Self {
+      state: Arc::new(watch::Sender::new(State::Running))
+    }
+  }
+}
+
+impl Control {
+  /// Creates a control in the running state.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Cancels all downloads watching this control.
+  pub fn cancel(&self) {
+    self.state.send_replace(State::Cancelled);
+  }
+
+  /// Pauses downloads at their next chunk until [`Control::resume`].
+  pub fn pause(&self) {
+    self.transition(State::Running, State::Paused);
+  }
+
+  /// Resumes paused downloads.
+  pub fn resume(&self) {
+    self.transition(State::Paused, State::Running);
+  }
+
+  /// Returns true if the downloads have been cancelled.
+  pub fn is_cancelled(&self) -> bool {
+    *self.state.borrow() == State::Cancelled
+  }
+
+  /// Returns true if the downloads are paused.
+  pub fn is_paused(&self) -> bool {
+    *self.state.borrow() == State::Paused
+  }
+
+  /// Cancels this control when the process receives Ctrl-C.
+  pub fn cancel_on_ctrl_c(&self) {
+    let control = self.clone();
+    tokio::spawn(async move {
+      if tokio::signal::ctrl_c().await.is_ok() {
+        info!("Interrupted, cancelling downloads");
+        control.cancel();
+      }
+    });
+  }
+
+  /// Completes once the downloads are cancelled.
+  pub async fn cancelled(&self) {
+    let mut rx = self.state.subscribe();
+    // The sender lives as long as `self`, so this only returns on cancel
+    let _ = rx.wait_for(|state| *state == State::Cancelled).await;
+  }
+
+  /// Waits while paused.
+  ///
+  /// Returns `Error::Cancelled` if the downloads are or become cancelled.
+  pub async fn checkpoint(&self) -> Result<()> {
+    let mut rx = self.state.subscribe();
+    match rx.wait_for(|state| *state != State::Paused).await {
+      Ok(state) if *state == State::Cancelled => Err(Error::Cancelled),
+      _ => Ok(())
+    }
+  }
+
+  /// Moves from one state to another, if currently in `from`.
+  fn transition(&self, from: State, to: State) {
+    self.state.send_if_modified(|state| {
+      if *state == from {
+        *state = to;
+        true
+      } else {
+        false
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn test_cancel_is_final() {
+    let control = Control::new();
+    control.cancel();
+    control.resume();
+    control.pause();
+    assert!(control.is_cancelled());
+    assert!(!control.is_paused());
+  }
+
+  #[tokio::test]
+  async fn test_checkpoint_waits_while_paused() {
+    let control = Control::new();
+    control.pause();
+
+    let waiter = tokio::spawn({
+      let control = control.clone();
+      async move { control.checkpoint().await }
+    });
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!waiter.is_finished());
+
+    control.resume();
+    assert!(waiter.await.unwrap().is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_checkpoint_fails_when_cancelled() {
+    let control = Control::new();
+    control.pause();
+
+    let waiter = tokio::spawn({
+      let control = control.clone();
+      async move { control.checkpoint().await }
+    });
+
+    control.cancel();
+    assert!(matches!(waiter.await.unwrap(), Err(Error::Cancelled)));
+  }
+}
@@ -4,17 +4,22 @@
 //! configuration options, progress reporting, and user-friendly error handling.
 
 use crate::*;
+use futures::stream::{self, StreamExt};
 use std::{
+  cmp::Reverse,
   collections::HashMap,
   path::{Path, PathBuf},
   sync::Arc,
-  time::Duration
+  time::{Duration, Instant}
 };
 use tokio::{
-  fs::{create_dir_all, remove_dir_all},
+  fs::{create_dir_all, remove_dir, remove_dir_all},
   task::JoinHandle
 };
 
+/// Directory in the target directory holding partial downloads
+const TEMP_DIR: &str = ".tmp_downloads";
+
 #[derive(Debug)]
 pub struct Downloader {
   /// URLs to download
@@ -25,12 +30,29 @@ pub struct Downloader {
   config: Config,
   /// HTTP client with configured settings
   client: ReqwestClient,
+  /// Whether client settings changed since the client was built
+  client_outdated: bool,
+  /// Transports taking precedence over HTTP(S) and local files
+  transports: Vec<Arc<dyn Transport>>,
   /// URL validator
   validator: validation::UrlValidator,
   /// Event sink for notifications
   event_sink: Arc<dyn EventSink>,
   /// Cached validation results
-  validated_urls: Option<Vec<validation::Url>>
+  validated_urls: Option<Vec<validation::Url>>,
+  /// Expected checksums keyed by URL
+  checksums: HashMap<String, Checksum>,
+  /// Manifest entries keyed by URL
+  entries: HashMap<String, ManifestEntry>,
+  /// Bandwidth limiter shared by all downloads
+  throttle: Throttle,
+  /// Cancellation and pause handle for running downloads
+  control: Control,
+  /// Journal batch continued by the next run
+  batch: Option<String>,
+  /// Target and temp paths of files continued from the journal, keyed by
+  /// URL
+  restored: HashMap<String, (PathBuf, PathBuf)>
 }
 
 impl Default for Downloader {
@@ -39,10 +61,18 @@ impl Default for Downloader {
       urls: Vec::new(),
       target_dir: PathBuf::new(),
       config: Config::default(),
-      client: ReqwestClient::new(),
+      client: build_client(&Config::default()).unwrap_or_default(),
+      client_outdated: false,
+      transports: Vec::new(),
       validator: validation::UrlValidator::default(),
       event_sink: Arc::new(LoggingEventSink::default()),
-      validated_urls: None
+      validated_urls: None,
+      checksums: HashMap::new(),
+      entries: HashMap::new(),
+      throttle: Throttle::unlimited(),
+      control: Control::new(),
+      batch: None,
+      restored: HashMap::new()
     }
   }
 }
@@ -72,11 +102,109 @@ impl Downloader {
     Ok(Self {
       urls: urls.into_iter().map(|s| s.as_ref().to_string()).collect(),
       target_dir: target_dir.as_ref().to_path_buf(),
+      client: build_client(&config)?,
+      throttle: Throttle::from_config(&config),
       config,
       ..Default::default()
     })
   }
 
+  /// Reopens the journal in `target_dir` and creates a downloader for the
+  /// files of its last batch that did not complete.
+  ///
+  /// The continued downloads are recorded as part of the same batch. Files
+  /// keep the target and temp paths they were queued with, so partial files
+  /// left in the temp directory are resumed. Checksums, mirrors and headers
+  /// of manifest entries are not journaled and can be added again with
+  /// [`Downloader::with_entry`].
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the journal cannot be read or holds no
+  /// batch.
+  pub async fn from_journal<P: AsRef<Path>>(
+    target_dir: P,
+    config: Config
+  ) -> Result<Self> {
+    let status =
+      Journal::status(&target_dir)
+        .await?
+        .ok_or_else(|| Error::FileSystem {
+          message: "No batch recorded in the download journal".to_string()
+        })?;
+
+    let urls: Vec<&str> =
+      status.unfinished().map(|file| file.url.as_str()).collect();
+    let mut downloader = Self::new_with_config(urls, target_dir, config)?;
+    downloader.config.journal = true;
+    downloader.restored = status
+      .unfinished()
+      .filter_map(|file| {
+        let paths = (file.target.clone()?, file.temp_path.clone()?);
+        Some((file.url.clone(), paths))
+      })
+      .collect();
+    downloader.batch = Some(status.batch);
+    Ok(downloader)
+  }
+
+  /// Loads the manifest at `manifest` and creates a downloader for its
+  /// files.
+  ///
+  /// The format is detected from the file extension: `.toml`, `.json`, or
+  /// plain text with one URL per line otherwise.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::Manifest` with the line of the first invalid value, or
+  /// `Error::FileSystem` if the manifest cannot be read.
+  pub async fn from_manifest<M, P>(
+    manifest: M,
+    target_dir: P,
+    config: Config
+  ) -> Result<Self>
+  where
+    M: AsRef<Path>,
+    P: AsRef<Path>
+  {
+    BatchManifest::load(manifest)
+      .await?
+      .into_downloader(target_dir, config)
+  }
+
+  /// Returns the URLs to download.
+  pub fn urls(&self) -> &[String] {
+    &self.urls
+  }
+
+  /// Returns the directory files are downloaded to.
+  pub fn target_dir(&self) -> &Path {
+    &self.target_dir
+  }
+
+  /// Creates a downloader for other URLs with the same settings.
+  ///
+  /// The bandwidth limiter is shared, while cancellation starts afresh.
+  pub(crate) fn with_urls(&self, urls: Vec<String>) -> Self {
+    Self {
+      urls,
+      target_dir: self.target_dir.clone(),
+      config: self.config.clone(),
+      client: self.client.clone(),
+      client_outdated: self.client_outdated,
+      transports: self.transports.clone(),
+      validator: self.validator.clone(),
+      event_sink: self.event_sink.clone(),
+      validated_urls: None,
+      checksums: self.checksums.clone(),
+      entries: self.entries.clone(),
+      throttle: self.throttle.clone(),
+      control: Control::new(),
+      batch: self.batch.clone(),
+      restored: self.restored.clone()
+    }
+  }
+
   pub fn with_concurrency_limit(&mut self, limit: usize) -> &mut Self {
     self.config.concurrency_limit = Some(limit);
     self
@@ -84,6 +212,13 @@ impl Downloader {
 
   pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
     self.config.timeout = timeout;
+    self.client_outdated = true;
+    self
+  }
+
+  pub fn with_connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.config.connect_timeout = timeout;
+    self.client_outdated = true;
     self
   }
 
@@ -97,6 +232,14 @@ impl Downloader {
     self
   }
 
+  pub fn with_retry_policy(
+    &mut self,
+    policy: Arc<dyn RetryPolicy>
+  ) -> &mut Self {
+    self.config.retry_policy = policy;
+    self
+  }
+
   pub fn with_overwrite_policy(
     &mut self,
     policy: OverwritePolicy
@@ -125,6 +268,19 @@ impl Downloader {
     self
   }
 
+  pub fn overwrite_if_modified(&mut self) -> &mut Self {
+    self.config.overwrite_policy = OverwritePolicy::IfModified;
+    self
+  }
+
+  pub fn with_conflict_strategy(
+    &mut self,
+    strategy: filename::ConflictStrategy
+  ) -> &mut Self {
+    self.config.conflict_strategy = strategy;
+    self
+  }
+
   pub fn with_filename_strategy(
     &mut self,
     strategy: filename::Strategy
@@ -140,11 +296,13 @@ impl Downloader {
 
   pub fn with_user_agent<S: Into<String>>(&mut self, agent: S) -> &mut Self {
     self.config.user_agent = Some(agent.into());
+    self.client_outdated = true;
     self
   }
 
   pub fn with_max_redirects(&mut self, redirects: usize) -> &mut Self {
     self.config.max_redirects = redirects;
+    self.client_outdated = true;
     self
   }
 
@@ -159,12 +317,66 @@ impl Downloader {
   }
 
   pub fn with_event_sink(&mut self, sink: Arc<dyn EventSink>) -> &mut Self {
-    self.config.event_sink = sink;
+    self.config.event_sink = sink.clone();
+    self.event_sink = sink;
     self
   }
 
   pub fn with_ssl_verification(&mut self, verify: bool) -> &mut Self {
     self.config.verify_ssl = verify;
+    self.client_outdated = true;
+    self
+  }
+
+  pub fn with_ca_bundle<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+    self.config.ca_bundle = Some(path.into());
+    self.client_outdated = true;
+    self
+  }
+
+  pub fn with_client_identity(
+    &mut self,
+    identity: ClientIdentity
+  ) -> &mut Self {
+    self.config.client_identity = Some(identity);
+    self.client_outdated = true;
+    self
+  }
+
+  pub fn with_proxy<S: Into<String>>(&mut self, url: S) -> &mut Self {
+    self.config.proxy = Some(url.into());
+    self.client_outdated = true;
+    self
+  }
+
+  pub fn with_no_proxy<S: Into<String>>(&mut self, hosts: S) -> &mut Self {
+    self.config.no_proxy = Some(hosts.into());
+    self.client_outdated = true;
+    self
+  }
+
+  pub fn with_credential_provider(
+    &mut self,
+    provider: Arc<dyn CredentialProvider>
+  ) -> &mut Self {
+    self.config.credentials.push(provider);
+    self
+  }
+
+  /// Adds a transport for the URLs it supports, taking precedence over the
+  /// built-in HTTP(S) and `file://` transports and those added before.
+  pub fn with_transport(&mut self, transport: Arc<dyn Transport>) -> &mut Self {
+    self.transports.push(transport);
+    self
+  }
+
+  /// Replaces the URL validator, e.g. to allow `file://` URLs.
+  pub fn with_url_validator(
+    &mut self,
+    validator: validation::UrlValidator
+  ) -> &mut Self {
+    self.validator = validator;
+    self.validated_urls = None;
     self
   }
 
@@ -189,6 +401,139 @@ impl Downloader {
     self
   }
 
+  pub fn with_resume(&mut self, resume: bool) -> &mut Self {
+    self.config.resume = resume;
+    self
+  }
+
+  pub fn with_segments(&mut self, segments: usize) -> &mut Self {
+    self.config.segments = segments;
+    self
+  }
+
+  pub fn with_min_segment_size(&mut self, size: u64) -> &mut Self {
+    self.config.min_segment_size = size;
+    self
+  }
+
+  pub fn with_checksum_sidecar(&mut self, enabled: bool) -> &mut Self {
+    self.config.checksum_sidecar = enabled;
+    self
+  }
+
+  /// Sets the bytes to keep free on the target filesystem, or disables the
+  /// free space check with `None`.
+  ///
+  /// The check uses the file sizes found by [`Downloader::preview`], so it
+  /// only applies to batches previewed before they start.
+  pub fn with_disk_headroom(&mut self, headroom: Option<u64>) -> &mut Self {
+    self.config.disk_headroom = headroom;
+    self
+  }
+
+  pub fn with_preallocation(&mut self, enabled: bool) -> &mut Self {
+    self.config.preallocate = enabled;
+    self
+  }
+
+  pub fn with_hook(&mut self, hook: Hook) -> &mut Self {
+    self.config.hooks.push(hook);
+    self
+  }
+
+  pub fn with_hook_concurrency(&mut self, limit: usize) -> &mut Self {
+    self.config.hook_concurrency = limit;
+    self
+  }
+
+  pub fn with_hook_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+    self.config.hook_timeout = timeout;
+    self
+  }
+
+  pub fn with_decompression(
+    &mut self,
+    format: Option<Decompression>
+  ) -> &mut Self {
+    self.config.decompress = format;
+    self
+  }
+
+  pub fn with_keep_compressed(&mut self, keep: bool) -> &mut Self {
+    self.config.keep_compressed = keep;
+    self
+  }
+
+  pub fn with_cache(&mut self, cache: Option<Cache>) -> &mut Self {
+    self.config.cache = cache;
+    self
+  }
+
+  pub fn with_bandwidth_limit(&mut self, limit: Option<u64>) -> &mut Self {
+    self.config.bandwidth_limit = limit;
+    self.throttle.set_global_limit(limit);
+    self
+  }
+
+  pub fn with_host_bandwidth_limit<S: Into<String>>(
+    &mut self,
+    host: S,
+    limit: u64
+  ) -> &mut Self {
+    let host = host.into();
+    self.throttle.set_host_limit(&host, Some(limit));
+    self.config.host_bandwidth_limits.insert(host, limit);
+    self
+  }
+
+  /// Returns a handle to the bandwidth limiter of this downloader.
+  ///
+  /// The limits set through the handle apply immediately, including to
+  /// downloads that are already running.
+  pub fn throttle(&self) -> Throttle {
+    self.throttle.clone()
+  }
+
+  /// Returns a handle to cancel, pause or resume the downloads of this
+  /// downloader, for use from another task while `start` is running.
+  pub fn control(&self) -> Control {
+    self.control.clone()
+  }
+
+  /// Replaces the cancellation and pause handle, e.g. to start again after a
+  /// cancelled run.
+  pub fn with_control(&mut self, control: Control) -> &mut Self {
+    self.control = control;
+    self
+  }
+
+  /// Sets the expected checksum of the file downloaded from `url`.
+  ///
+  /// The download fails with `Error::ContentValidation` if the digest of the
+  /// received data does not match.
+  pub fn with_checksum<S: Into<String>>(
+    &mut self,
+    url: S,
+    checksum: Checksum
+  ) -> &mut Self {
+    self.checksums.insert(url.into(), checksum);
+    self
+  }
+
+  /// Adds the file of a manifest entry, downloading it with the target,
+  /// checksum, size limit, headers and priority of the entry.
+  pub fn with_entry(&mut self, entry: ManifestEntry) -> &mut Self {
+    if !self.urls.contains(&entry.url) {
+      self.urls.push(entry.url.clone());
+    }
+    if let Some(checksum) = &entry.checksum {
+      self.checksums.insert(entry.url.clone(), checksum.clone());
+    }
+    self.entries.insert(entry.url.clone(), entry);
+    self.validated_urls = None;
+    self
+  }
+
   /// Validates all URLs and generates a preview of what will be downloaded.
   ///
   /// This method performs URL validation, filename extraction, conflict
@@ -201,6 +546,7 @@ impl Downloader {
   /// file, potential conflicts, and warnings.
   pub async fn preview(&mut self) -> Result<preview::Manifest> {
     trace!("Generating download preview for {} URLs", self.urls.len());
+    self.refresh_client()?;
 
     self
       .event_sink
@@ -209,71 +555,224 @@ impl Downloader {
       })
       .await;
 
-    let validated_urls = self.validate_urls().await?;
-    self.validated_urls = Some(validated_urls.clone());
+    let mut validated_urls = self.validate_urls().await?;
 
     let mut files = Vec::new();
     let mut total_size = Some(0u64);
-    let mut conflicts = HashMap::<String, Vec<String>>::new();
+    let mut conflicts = HashMap::<String, Vec<(String, PathBuf)>>::new();
     let mut warnings = Vec::new();
 
+    // Probe the files concurrently, keeping their order and reusing the
+    // responses already fetched to name them
+    let transport = self.transport();
+    let probes: Vec<_> = if self.config.fetch_metadata {
+      stream::iter(&validated_urls)
+        .map(|validated| {
+          let config = self.config_for(&validated.original);
+          let transport = transport.clone();
+          async move {
+            if let Some(probe) = &validated.probe {
+              return Some(Ok(probe.clone()));
+            }
+            Some(
+              preview::Probe::fetch(
+                transport.as_ref(),
+                &config,
+                &validated.parsed
+              )
+              .await
+            )
+          }
+        })
+        .buffered(
+          self
+            .config
+            .concurrency_limit
+            .unwrap_or(preview::PROBE_CONCURRENCY)
+            .max(1)
+        )
+        .collect()
+        .await
+    } else {
+      validated_urls.iter().map(|_| None).collect()
+    };
+
     // Process each validated URL
-    for validated in &validated_urls {
-      let estimated_size = if self.config.fetch_metadata {
-        utils::fetch_content_length(&self.client, &validated.parsed).await
-      } else {
-        None
+    let available_space = disk::available_space(&self.target_dir);
+    let mut needed_space = 0u64;
+    let mut writable = HashMap::<PathBuf, bool>::new();
+    for (validated, probe) in validated_urls.iter_mut().zip(probes) {
+      let (probe, reason) = match probe {
+        Some(Ok(probe)) => (Some(probe), None),
+        Some(Err(e)) => (None, Some(e.to_string())),
+        None => (None, None)
       };
+      let estimated_size = probe
+        .as_ref()
+        .filter(|probe| probe.is_success())
+        .and_then(|probe| probe.validators.content_length);
+      validated.size_hint = estimated_size;
 
       // Update total size calculation
       if let (Some(total), Some(size)) = (total_size.as_mut(), estimated_size) {
         *total += size;
-      } else if estimated_size.is_some() {
+      } else {
         total_size = None; // Can't calculate total if any size is unknown
       }
 
-      let status = preview::Status::new(
-        self.config.max_file_size,
-        validated,
-        estimated_size
-      )
-      .await;
+      let mut status = if reason.is_some() {
+        preview::Status::Unreachable
+      } else {
+        preview::Status::new(
+          self.config_for(&validated.original).max_file_size,
+          validated,
+          probe.as_ref()
+        )
+        .await
+      };
+
+      // Check write access and free space for files to download
+      if matches!(status, preview::Status::Ready | preview::Status::Stale) {
+        let dir = validated
+          .target_path
+          .parent()
+          .unwrap_or(&self.target_dir)
+          .to_path_buf();
+        let is_writable = match writable.get(&dir) {
+          Some(&is_writable) => is_writable,
+          None => {
+            let is_writable = disk::is_writable(&dir).await;
+            writable.insert(dir, is_writable);
+            is_writable
+          }
+        };
+
+        if !is_writable {
+          status = preview::Status::PermissionDenied;
+        } else if let Some(size) = estimated_size {
+          let size = self.remaining_size(validated, size).await;
+          if self.exceeds_space(needed_space + size, available_space) {
+            status = preview::Status::InsufficientSpace;
+          } else {
+            needed_space += size;
+          }
+        }
+      }
 
-      // Check for filename conflicts
+      // Check for filename conflicts, keyed by the name before renaming
+      let original_filename = validated
+        .renamed_from
+        .as_deref()
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| validated.filename.clone());
       conflicts
-        .entry(validated.filename.clone())
+        .entry(original_filename)
         .or_default()
-        .push(validated.original.clone());
+        .push((validated.original.clone(), validated.target_path.clone()));
 
       files.push(preview::Target {
         url: validated.original.clone(),
         filename: validated.filename.clone(),
         target_path: validated.target_path.clone(),
+        renamed_from: validated.renamed_from.clone(),
         estimated_size,
-        status
+        status,
+        probe,
+        reason
       });
     }
 
+    self.validated_urls = Some(validated_urls);
+
+    // Keep URLs that failed validation as entries, in the given order
+    let mut validated_files = files.into_iter().peekable();
+    let mut files = Vec::with_capacity(self.urls.len());
+    for url in &self.urls {
+      if let Some(file) = validated_files.next_if(|file| &file.url == url) {
+        files.push(file);
+      } else if let Err(e) = self.validator.validate(url) {
+        files.push(preview::Target::invalid(url, &e));
+      }
+    }
+    files.extend(validated_files);
+
     // Convert conflicts map to conflict list
     let conflicts: Vec<preview::Conflict> = conflicts
       .into_iter()
-      .filter(|(_, urls)| urls.len() > 1)
-      .map(|(filename, urls)| preview::Conflict { filename, urls })
+      .filter(|(_, entries)| entries.len() > 1)
+      .map(|(filename, entries)| {
+        let (urls, targets) = entries.into_iter().unzip();
+        preview::Conflict {
+          filename,
+          urls,
+          targets
+        }
+      })
       .collect();
 
     // Generate warnings
     if !conflicts.is_empty() {
-      warnings.push(format!("{} filename conflicts detected", conflicts.len()));
+      warnings.push(format!(
+        "{} filename conflicts resolved by renaming",
+        conflicts.len()
+      ));
+    }
+
+    let renamed_count =
+      files.iter().filter(|f| f.renamed_from.is_some()).count();
+    if renamed_count > 0 {
+      warnings.push(format!("{renamed_count} files will be renamed"));
     }
 
     let existing_count = files
       .iter()
-      .filter(|f| matches!(f.status, preview::Status::Exists))
+      .filter(|f| {
+        matches!(
+          f.status,
+          preview::Status::Exists
+            | preview::Status::UpToDate
+            | preview::Status::Stale
+        )
+      })
       .count();
     if existing_count > 0 {
       warnings.push(format!("{existing_count} files already exist"));
     }
 
+    let stale_count = files
+      .iter()
+      .filter(|f| matches!(f.status, preview::Status::Stale))
+      .count();
+    if stale_count > 0 {
+      warnings.push(format!("{stale_count} existing files are out of date"));
+    }
+
+    let count = |status| files.iter().filter(|f| f.status == status).count();
+    for (status, warning) in [
+      (preview::Status::InvalidUrl, "URLs are invalid"),
+      (preview::Status::Unreachable, "files are unreachable"),
+      (
+        preview::Status::AuthRequired,
+        "files require authentication"
+      ),
+      (preview::Status::AccessDenied, "files are forbidden"),
+      (preview::Status::TooLarge, "files exceed the size limit"),
+      (
+        preview::Status::InsufficientSpace,
+        "files do not fit in the free disk space"
+      ),
+      (
+        preview::Status::PermissionDenied,
+        "files cannot be written to their directory"
+      )
+    ] {
+      let count = count(status);
+      if count > 0 {
+        warnings.push(format!("{count} {warning}"));
+      }
+    }
+
     let preview = preview::Manifest {
       files,
       total_size,
@@ -305,19 +804,23 @@ impl Downloader {
   /// Starts the download process with progress reporting.
   ///
   /// This method validates URLs (if not already done), handles existing files
-  /// according to the overwrite policy, and starts concurrent downloads with
-  /// progress reporting.
+  /// according to the overwrite policy, and spawns concurrent downloads in the
+  /// background.
   ///
   /// # Returns
   ///
-  /// Returns a `Reporter` that provides real-time updates on download
-  /// progress.
+  /// Returns a `DownloadHandle` as soon as the downloads are running. It
+  /// provides real-time progress updates and resolves to a `BatchReport`
+  /// when awaited.
   ///
   /// # Errors
   ///
-  /// Returns the first error encountered during the download process.
-  pub async fn start(&mut self) -> Result<progress::Reporter> {
+  /// Returns the first error encountered while preparing the downloads.
+  /// Failures of individual files are reported in the results instead.
+  pub async fn start(&mut self) -> Result<DownloadHandle> {
     info!("Starting download process");
+    let start_time = Instant::now();
+    self.refresh_client()?;
 
     self
       .event_sink
@@ -337,13 +840,32 @@ impl Downloader {
     };
 
     // Handle existing files according to policy
-    let urls_to_download = self.handle_existing_files(validated_urls).await?;
+    let (mut urls_to_download, skipped) =
+      self.handle_existing_files(validated_urls).await?;
+    let skipped: Vec<_> = skipped
+      .into_iter()
+      .map(|validated| report::Entry {
+        url: validated.original,
+        target: validated.target_path,
+        outcome: report::Outcome::Skipped {
+          reason: SkipReason::Exists
+        }
+      })
+      .collect();
 
     if urls_to_download.is_empty() {
       warn!("No files to download after handling existing files");
-      return Ok(progress::Reporter::completed());
+      let report = BatchReport::new(
+        skipped,
+        start_time.elapsed(),
+        Some(self.with_urls(Vec::new()))
+      );
+      return Ok(DownloadHandle::completed(self.control.clone(), report));
     }
 
+    // Fail early if the files sized by a preview do not fit on the disk
+    self.check_disk_space(&urls_to_download).await?;
+
     // Ensure target directory exists
     create_dir_all(&self.target_dir)
       .await
@@ -351,8 +873,16 @@ impl Downloader {
         message: format!("Failed to create target directory: {e}")
       })?;
 
+    // Record the batch so it can be continued after an interruption
+    let journal = if self.config.journal {
+      let batch = self.batch.get_or_insert_with(journal::new_batch_id).clone();
+      Some(Journal::open(&self.target_dir, batch).await?)
+    } else {
+      None
+    };
+
     // Create temporary directory for atomic operations
-    let temp_dir = self.target_dir.join(".tmp_downloads");
+    let temp_dir = self.target_dir.join(TEMP_DIR);
     create_dir_all(&temp_dir)
       .await
       .map_err(|e| Error::FileSystem {
@@ -360,84 +890,347 @@ impl Downloader {
       })?;
 
     // Create progress reporter
-    let progress_reporter = progress::Reporter::new(urls_to_download.len());
+    let progress_reporter = progress::Reporter::with_throttle(
+      urls_to_download.len(),
+      self.throttle.clone()
+    );
     let progress_tx = progress_reporter.sender();
 
-    // Prepare download tasks
+    // Prepare download tasks, sharing one retry budget across the batch
+    let retry_budget =
+      RetryBudget::new(self.config.retry_policy.batch_budget());
+    let transport = self.transport();
     let mut tasks = Vec::new();
+    let mut targets = Vec::new();
+    // Start files with a higher manifest priority first
+    urls_to_download.sort_by_key(|validated| {
+      Reverse(
+        self
+          .entries
+          .get(&validated.original)
+          .map_or(0, |entry| entry.priority)
+      )
+    });
     for (index, validated_url) in urls_to_download.into_iter().enumerate() {
-      let temp_path =
-        temp_dir.join(format!("{}_{}", index, validated_url.filename));
+      targets.push((
+        validated_url.original.clone(),
+        validated_url.target_path.clone()
+      ));
+
+      let temp_path = self.temp_path(&validated_url);
+
+      // Manifest entries may target subdirectories
+      if let Some(parent) = validated_url.target_path.parent() {
+        create_dir_all(parent)
+          .await
+          .map_err(|e| Error::FileSystem {
+            message: format!("Failed to create directory for download: {e}")
+          })?;
+      }
+
+      if let Some(journal) = &journal {
+        journal
+          .record(
+            &validated_url.original,
+            journal::State::Queued {
+              target: validated_url.target_path.clone(),
+              temp_path: temp_path.clone()
+            }
+          )
+          .await;
+      }
 
       let task = DownloadTask {
         url: validated_url.parsed,
+        original_url: validated_url.original.clone(),
         temp_path,
         final_path: validated_url.target_path,
         index,
-        client: self.client.clone(),
-        config: self.config.clone(),
+        transport: transport.clone(),
+        config: self.config_for(&validated_url.original),
         progress_tx: progress_tx.clone(),
-        event_sink: self.event_sink.clone()
+        event_sink: self.event_sink.clone(),
+        semaphore: None,
+        checksum: self.checksums.get(&validated_url.original).cloned(),
+        renamed_from: validated_url.renamed_from,
+        retry_budget: retry_budget.clone(),
+        throttle: Throttle::unlimited(),
+        control: Control::new(),
+        journal: journal.clone(),
+        decompress: validated_url.decompress,
+        mirrors: self
+          .entries
+          .get(&validated_url.original)
+          .map(ManifestEntry::mirror_urls)
+          .unwrap_or_default(),
+        mirror: None
       };
 
       tasks.push(task);
     }
 
-    // Execute downloads directly (no background spawn)
-    let executor = TaskExecutor::new(self.config.concurrency_limit);
+    // Run hooks on files as their tasks finish them
+    let hooks = (!self.config.hooks.is_empty()).then(|| {
+      Arc::new(hooks::Runner::new(
+        &self.config,
+        targets.clone(),
+        self.event_sink.clone()
+      ))
+    });
+    if let Some(hooks) = &hooks {
+      for task in &mut tasks {
+        task.event_sink = hooks.clone();
+      }
+    }
+
+    // Execute downloads in the background
+    let executor = TaskExecutor::new(self.config.concurrency_limit)
+      .with_throttle(self.throttle.clone())
+      .with_control(self.control.clone());
+    let event_sink = self.event_sink.clone();
+    let resume = self.config.resume;
+    let retry = self.with_urls(Vec::new());
+
+    let report = tokio::spawn(async move {
+      let results = executor.run(tasks).await;
+      let hook_failures = match &hooks {
+        Some(hooks) => hooks.finish().await,
+        None => Vec::new()
+      };
+      Self::clean_up_temp_dir(&temp_dir, resume).await;
+      Self::report_results(event_sink.as_ref(), &results).await;
+
+      let entries = targets
+        .into_iter()
+        .zip(results)
+        .map(|((url, target), result)| report::Entry {
+          url,
+          target,
+          outcome: match result {
+            Ok(result) => report::Outcome::Downloaded(result),
+            Err(failure) => report::Outcome::Failed(failure)
+          }
+        })
+        .chain(skipped)
+        .collect();
+      BatchReport::new(entries, start_time.elapsed(), Some(retry))
+        .with_hook_failures(hook_failures)
+    });
+
+    Ok(DownloadHandle::new(
+      progress_reporter,
+      self.control.clone(),
+      report
+    ))
+  }
+
+  /// Returns the temp path of a download, named after the target only so
+  /// a later run finds the same partial file, or the one recorded in the
+  /// journal for a continued file.
+  fn temp_path(&self, validated: &validation::Url) -> PathBuf {
+    if let Some((_, temp_path)) = self.restored.get(&validated.original) {
+      return temp_path.clone();
+    }
+
+    let relative = validated
+      .target_path
+      .strip_prefix(&self.target_dir)
+      .unwrap_or(Path::new(&validated.filename));
+    let temp_name = relative
+      .components()
+      .map(|component| component.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("__");
+    self
+      .target_dir
+      .join(TEMP_DIR)
+      .join(format!("{temp_name}.part"))
+  }
+
+  /// Checks that the files to download fit on the target filesystem with
+  /// the configured headroom, counting the sizes found by a preview minus
+  /// the bytes of partial files.
+  async fn check_disk_space(&self, urls: &[validation::Url]) -> Result<()> {
+    let mut needed = 0u64;
+    for validated in urls {
+      if let Some(size) = validated.size_hint {
+        needed += self.remaining_size(validated, size).await;
+      }
+    }
+
+    let available = disk::available_space(&self.target_dir);
+    match (self.config.disk_headroom, available) {
+      (Some(headroom), Some(available))
+        if self.exceeds_space(needed, Some(available)) =>
+      {
+        warn!(
+          "{} needed for downloads but {} available",
+          format_filesize(needed),
+          format_filesize(available)
+        );
+        Err(Error::insufficient_space(
+          needed.saturating_add(headroom),
+          available
+        ))
+      }
+      _ => Ok(())
+    }
+  }
+
+  /// Returns the bytes left to download for a file of `size` bytes, minus
+  /// those already in its partial file.
+  async fn remaining_size(
+    &self,
+    validated: &validation::Url,
+    size: u64
+  ) -> u64 {
+    let partial = tokio::fs::metadata(self.temp_path(validated))
+      .await
+      .map_or(0, |m| m.len());
+    size.saturating_sub(partial)
+  }
+
+  /// Returns true if `needed` bytes plus the configured headroom do not fit
+  /// in the `available` space. Never true when the check is disabled.
+  fn exceeds_space(&self, needed: u64, available: Option<u64>) -> bool {
+    needed > 0
+      && self.config.disk_headroom.zip(available).is_some_and(
+        |(headroom, available)| needed.saturating_add(headroom) > available
+      )
+  }
+
+  /// Returns the configuration for `url`, with the size limit and headers
+  /// of its manifest entry.
+  fn config_for(&self, url: &str) -> Config {
+    match self.entries.get(url) {
+      Some(entry) => entry.config(&self.config),
+      None => self.config.clone()
+    }
+  }
+
+  /// Returns the transport routing requests to the added transports, then
+  /// to HTTP(S) and local files.
+  fn transport(&self) -> Arc<dyn Transport> {
+    let transports = self.transports.iter().cloned().fold(
+      Transports::new(self.client.clone(), &self.config),
+      Transports::with
+    );
+    Arc::new(transports)
+  }
 
-    warn!("Before results");
-    let results = executor.execute(tasks).await;
-    warn!("After results");
+  /// Rebuilds the HTTP client if its settings changed since it was built.
+  fn refresh_client(&mut self) -> Result<()> {
+    if self.client_outdated {
+      self.client = build_client(&self.config)?;
+      self.client_outdated = false;
+    }
+    Ok(())
+  }
 
-    // Clean up temporary directory
-    if let Err(e) = remove_dir_all(&temp_dir).await {
+  /// Removes the temporary directory, keeping partial files for a later
+  /// resume.
+  async fn clean_up_temp_dir(temp_dir: &Path, resume: bool) {
+    if resume {
+      if let Err(e) = remove_dir(temp_dir).await {
+        debug!("Keeping temp directory with partial downloads: {}", e);
+      }
+    } else if let Err(e) = remove_dir_all(temp_dir).await {
       error!("Failed to clean up temp directory: {}", e);
     }
+  }
 
-    // Process results and send final event
+  /// Logs failed downloads and sends the final batch event.
+  async fn report_results(
+    event_sink: &dyn EventSink,
+    results: &[std::result::Result<TaskResult, report::Failure>]
+  ) {
     let mut success_count = 0;
+    let mut cancelled_count = 0;
     let mut failed_urls = Vec::new();
-    for (index, result) in results.into_iter().enumerate() {
+    for (index, result) in results.iter().enumerate() {
       match result {
         Ok(_) => success_count += 1,
-        Err(e) => {
+        Err(report::Failure {
+          error: Error::Cancelled,
+          ..
+        }) => cancelled_count += 1,
+        Err(report::Failure { error: e, .. }) => {
           error!("Download {} failed: {}", index, e);
           failed_urls.push(format!("Task {index}: {e}"));
         }
       }
     }
 
-    self
-      .event_sink
-      .on_event(DownloadEvent::DownloadCompleted {
+    let event = if cancelled_count > 0 {
+      DownloadEvent::DownloadCancelled {
+        successful: success_count,
+        failed: failed_urls.len(),
+        cancelled: cancelled_count
+      }
+    } else {
+      DownloadEvent::DownloadCompleted {
         successful: success_count,
         failed: failed_urls.len(),
         errors: failed_urls
-      })
-      .await;
-
-    // Return the progress_reporter so it stays alive
-    Ok(progress_reporter)
+      }
+    };
+    event_sink.on_event(event).await;
   }
 
   /// Validates and prepares all URLs for downloading.
+  ///
+  /// Duplicate target paths within the batch are always renamed, and
+  /// existing files too under `OverwritePolicy::Rename`.
   async fn validate_urls(&self) -> Result<Vec<validation::Url>> {
-    validation::Url::new(
+    let mut validated = validation::Url::new(
       self.urls.clone(),
       &self.target_dir,
       &self.config.filename_strategy,
-      self.validator.clone()
+      self.validator.clone(),
+      self.transport().as_ref(),
+      &self.config
     )
-    .await
+    .await?;
+
+    for url in &mut validated {
+      if let Some(entry) = self.entries.get(&url.original) {
+        entry.apply_target(url, &self.target_dir);
+      }
+
+      // Decompressed files are saved without the extension of their format
+      if let Some(format) = self.config.decompress
+        && format.matches(&url.target_path)
+      {
+        url.target_path = format.decoded_path(&url.target_path);
+        url.exists = url.target_path.exists();
+        url.decompress = Some(format);
+      }
+
+      // Files continued from the journal keep their recorded target
+      if let Some((target, _)) = self.restored.get(&url.original) {
+        url.target_path = target.clone();
+        url.exists = target.exists();
+      }
+    }
+
+    filename::ConflictResolver::new(self.config.conflict_strategy.clone())
+      .resolve_targets(
+        &mut validated,
+        self.config.overwrite_policy == OverwritePolicy::Rename
+      );
+
+    Ok(validated)
   }
 
   /// Handles existing files according to the configured overwrite policy.
+  ///
+  /// Returns the files to download and the files skipped because they exist.
   async fn handle_existing_files(
     &self,
     validated_urls: Vec<validation::Url>
-  ) -> Result<Vec<validation::Url>> {
+  ) -> Result<(Vec<validation::Url>, Vec<validation::Url>)> {
     let mut to_download = Vec::new();
+    let mut skipped = Vec::new();
     let mut existing_files = Vec::new();
 
     for validated in validated_urls {
@@ -447,7 +1240,9 @@ impl Downloader {
 
       match self.config.overwrite_policy {
         OverwritePolicy::Skip =>
-          if !validated.exists {
+          if validated.exists {
+            skipped.push(validated);
+          } else {
             to_download.push(validated);
           },
         OverwritePolicy::Overwrite => {
@@ -460,7 +1255,11 @@ impl Downloader {
           to_download.push(validated);
         }
         OverwritePolicy::Rename => {
-          //TODO: Implement logic for auto-renaming
+          // Conflicting targets were renamed during validation
+          to_download.push(validated);
+        }
+        OverwritePolicy::IfModified => {
+          // Existing files are requested conditionally by the task
           to_download.push(validated);
         }
       }
@@ -474,10 +1273,16 @@ impl Downloader {
         crate::config::OverwritePolicy::Overwrite => {
           warn!("Will overwrite {} existing files", existing_files.len());
         }
+        crate::config::OverwritePolicy::IfModified => {
+          info!(
+            "Checking {} existing files for updates",
+            existing_files.len()
+          );
+        }
         _ => {}
       }
     }
 
-    Ok(to_download)
+    Ok((to_download, skipped))
   }
 }
@@ -0,0 +1,287 @@
+//! Decompression of downloads while their bytes arrive
+//!
+//! With a [`Decompression`] format configured, files whose name ends with
+//! its extension are decoded chunk by chunk into a second temp file, which
+//! becomes the target without the extension. The compressed bytes are still
+//! written to the usual temp file, so checksums describe what the server
+//! sent and an interrupted download resumes by replaying them through the
+//! decoder.
+
+use crate::*;
+use flate2::write::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use std::{
+  ffi::OsString,
+  io::Write,
+  path::{Path, PathBuf}
+};
+use tokio::{
+  fs::File,
+  io::{AsyncReadExt, AsyncWriteExt}
+};
+
+/// Compression formats that can be decoded while downloading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Decompression {
+  /// gzip, including files of several concatenated members
+  Gzip
+}
+
+/// Streaming decoder writing to the decoded temp file.
+pub(crate) struct Decoder {
+  codec: MultiGzDecoder<Vec<u8>>,
+  file: File,
+  path: PathBuf,
+  bytes: u64
+}
+
+impl Decompression {
+  /// Returns the file extension of the format, without the dot.
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Decompression::Gzip => "gz"
+    }
+  }
+
+  /// Returns true if `path` has the extension of the format.
+  pub fn matches(&self, path: &Path) -> bool {
+    path
+      .extension()
+      .is_some_and(|extension| extension.eq_ignore_ascii_case(self.extension()))
+  }
+
+  /// Returns the path of the decompressed file, `data.tsv` for
+  /// `data.tsv.gz`.
+  pub fn decoded_path(&self, path: &Path) -> PathBuf {
+    path.with_extension("")
+  }
+
+  /// Returns the path the compressed original of `decoded` is kept at.
+  pub fn encoded_path(&self, decoded: &Path) -> PathBuf {
+    let mut path = OsString::from(decoded.as_os_str());
+    path.push(".");
+    path.push(self.extension());
+    PathBuf::from(path)
+  }
+}
+
+impl Decoder {
+  /// Creates the decoded temp file of `temp_path`, replacing any left by an
+  /// earlier attempt.
+  pub(crate) async fn create(
+    format: Decompression,
+    temp_path: &Path
+  ) -> Result<Self> {
+    let path = decoded_temp_path(temp_path);
+    let file = File::create(&path).await.map_err(|e| Error::FileSystem {
+      message: format!("Failed to create decompressed temp file: {e}")
+    })?;
+
+    let codec = match format {
+      Decompression::Gzip => MultiGzDecoder::new(Vec::new())
+    };
+    Ok(Self {
+      codec,
+      file,
+      path,
+      bytes: 0
+    })
+  }
+
+  /// Returns the number of decompressed bytes written so far.
+  pub(crate) fn bytes(&self) -> u64 {
+    self.bytes
+  }
+
+  /// Returns the decoded temp file.
+  pub(crate) fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Decodes a chunk of compressed bytes.
+  pub(crate) async fn write(&mut self, url: &str, chunk: &[u8]) -> Result<()> {
+    self
+      .codec
+      .write_all(chunk)
+      .map_err(|e| invalid_data(url, e))?;
+    self.drain().await
+  }
+
+  /// Decodes the compressed bytes already in `path`, e.g. of a partial
+  /// download being resumed.
+  pub(crate) async fn write_file(
+    &mut self,
+    url: &str,
+    path: &Path
+  ) -> Result<()> {
+    let mut file = File::open(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+      let read = file.read(&mut buffer).await?;
+      if read == 0 {
+        return Ok(());
+      }
+      self.write(url, &buffer[..read]).await?;
+    }
+  }
+
+  /// Decodes the end of the stream and flushes the decoded file, returning
+  /// the number of decompressed bytes.
+  pub(crate) async fn finish(mut self, url: &str) -> Result<u64> {
+    self.codec.try_finish().map_err(|e| invalid_data(url, e))?;
+    self.drain().await?;
+    self.file.flush().await.map_err(|e| Error::FileSystem {
+      message: format!("Failed to flush decompressed temp file: {e}")
+    })?;
+    Ok(self.bytes)
+  }
+
+  /// Writes the decoded output buffered by the codec.
+  async fn drain(&mut self) -> Result<()> {
+    let output = std::mem::take(self.codec.get_mut());
+    if output.is_empty() {
+      return Ok(());
+    }
+
+    self
+      .file
+      .write_all(&output)
+      .await
+      .map_err(|e| Error::FileSystem {
+        message: format!("Failed to write decompressed temp file: {e}")
+      })?;
+    self.bytes += output.len() as u64;
+    Ok(())
+  }
+}
+
+/// Returns the temp path the decoded bytes of `temp_path` are written to.
+pub(crate) fn decoded_temp_path(temp_path: &Path) -> PathBuf {
+  let mut path = OsString::from(temp_path.as_os_str());
+  path.push(".decoded");
+  PathBuf::from(path)
+}
+
+/// Creates the error for compressed bytes that cannot be decoded.
+fn invalid_data(url: &str, error: std::io::Error) -> Error {
+  Error::content_validation_error(
+    url,
+    format!("cannot decompress: {error}").as_str()
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::{Compression, write::GzEncoder};
+  use std::{sync::Arc, time::Duration};
+  use tempfile::TempDir;
+
+  fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  #[test]
+  fn test_paths() {
+    let format = Decompression::Gzip;
+    assert!(format.matches(Path::new("title.basics.tsv.gz")));
+    assert!(format.matches(Path::new("ARCHIVE.GZ")));
+    assert!(!format.matches(Path::new("title.basics.tsv")));
+    assert_eq!(
+      format.decoded_path(Path::new("dir/title.basics.tsv.gz")),
+      PathBuf::from("dir/title.basics.tsv")
+    );
+    assert_eq!(
+      format.encoded_path(Path::new("dir/title.basics.tsv")),
+      PathBuf::from("dir/title.basics.tsv.gz")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_decodes_chunks_and_partial_files() {
+    let dir = TempDir::new().unwrap();
+    let data = b"tconst\ttitleType\n".repeat(1000);
+    let compressed = [gzip(&data), gzip(b"second member\n")].concat();
+    let (head, tail) = compressed.split_at(compressed.len() / 3);
+
+    let temp_path = dir.path().join("data.tsv.gz.part");
+    std::fs::write(&temp_path, head).unwrap();
+
+    let mut decoder = Decoder::create(Decompression::Gzip, &temp_path)
+      .await
+      .unwrap();
+    decoder.write_file("test", &temp_path).await.unwrap();
+    for chunk in tail.chunks(100) {
+      decoder.write("test", chunk).await.unwrap();
+    }
+    let path = decoder.path().to_path_buf();
+    let bytes = decoder.finish("test").await.unwrap();
+
+    let expected = [data.as_slice(), b"second member\n"].concat();
+    assert_eq!(bytes, expected.len() as u64);
+    assert_eq!(std::fs::read(path).unwrap(), expected);
+
+    let mut decoder = Decoder::create(Decompression::Gzip, &temp_path)
+      .await
+      .unwrap();
+    let error = decoder.write("test", b"not gzip at all").await.unwrap_err();
+    assert!(matches!(error, Error::ContentValidation { .. }));
+  }
+
+  #[tokio::test]
+  async fn test_downloads_decompress_while_resuming() {
+    let url = "https://example.com/title.basics.tsv.gz";
+    let data = b"tconst\ttitleType\ttt0000001\tshort\n".repeat(500);
+    let compressed = gzip(&data);
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new(compressed.clone())
+        .header(reqwest::header::ETAG, "\"v1\"")
+        .chunk_size(64)
+        .fault(Fault::Truncate(compressed.len() / 2))
+    );
+
+    let target = TempDir::new().unwrap();
+    let sink = Arc::new(CollectingEventSink::new());
+    let config = Config::builder()
+      .retry_delay(Duration::from_millis(1))
+      .progress_interval(Duration::ZERO)
+      .decompress(Some(Decompression::Gzip))
+      .keep_compressed(true)
+      .build();
+    let mut downloader =
+      Downloader::new_with_config(vec![url], target.path(), config).unwrap();
+    downloader
+      .with_transport(transport)
+      .with_event_sink(sink.clone());
+    let report = downloader.start().await.unwrap().await.unwrap();
+
+    let entry = &report.entries[0];
+    assert_eq!(entry.target, target.path().join("title.basics.tsv"));
+    let result = entry.result().unwrap();
+    assert!(result.resumed_bytes > 0);
+    assert_eq!(result.bytes_downloaded, compressed.len() as u64);
+    assert_eq!(result.decompressed_bytes, Some(data.len() as u64));
+    assert_eq!(std::fs::read(&entry.target).unwrap(), data);
+    assert_eq!(
+      std::fs::read(target.path().join("title.basics.tsv.gz")).unwrap(),
+      compressed
+    );
+    assert!(!target.path().join(".tmp_downloads").exists());
+
+    // Progress reports both the compressed and the decompressed bytes
+    assert!(sink.events().iter().any(|event| matches!(
+      event,
+      DownloadEvent::FileProgress {
+        bytes_downloaded,
+        decompressed_bytes: Some(decompressed),
+        ..
+      } if *decompressed > *bytes_downloaded
+    )));
+  }
+}
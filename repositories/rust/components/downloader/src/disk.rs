@@ -0,0 +1,216 @@
+//! Free space and write access of download locations
+
+use crate::*;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions, remove_file};
+
+/// Returns the closest ancestor of `path` that exists, as directories are
+/// created only when the downloads start.
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+  path
+    .ancestors()
+    .map(|dir| {
+      if dir.as_os_str().is_empty() {
+        Path::new(".")
+      } else {
+        dir
+      }
+    })
+    .find(|dir| dir.is_dir())
+    .map(Path::to_path_buf)
+}
+
+/// Returns the bytes available to unprivileged users on the filesystem
+/// holding `path`, or `None` where this cannot be determined.
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+  let dir = existing_ancestor(path)?;
+  match rustix::fs::statvfs(&dir) {
+    Ok(stat) => Some(stat.f_bavail.saturating_mul(stat.f_frsize)),
+    Err(e) => {
+      debug!("Failed to query free space of {}: {}", dir.display(), e);
+      None
+    }
+  }
+}
+
+/// Returns the bytes available on the filesystem holding `path`, or `None`
+/// where this cannot be determined.
+#[cfg(not(unix))]
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+  None
+}
+
+/// Reserves `len` bytes after `offset` for `file` at `path` without changing
+/// its size, so resuming from the file length still works.
+///
+/// Filesystems that do not support preallocation are skipped.
+///
+/// # Errors
+///
+/// Returns `Error::InsufficientSpace` if the filesystem is full.
+#[cfg(target_os = "linux")]
+pub(crate) fn preallocate(
+  file: &File,
+  path: &Path,
+  offset: u64,
+  len: u64
+) -> Result<()> {
+  use rustix::{
+    fs::{FallocateFlags, fallocate},
+    io::Errno
+  };
+
+  if len == 0 {
+    return Ok(());
+  }
+
+  match fallocate(file, FallocateFlags::KEEP_SIZE, offset, len) {
+    Ok(()) => Ok(()),
+    Err(Errno::NOSPC) => Err(Error::insufficient_space(
+      len,
+      available_space(path).unwrap_or(0)
+    )),
+    Err(e) => {
+      debug!("Cannot preallocate {}: {}", path.display(), e);
+      Ok(())
+    }
+  }
+}
+
+/// Reserves disk space where supported; a no-op on this platform.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn preallocate(
+  file: &File,
+  path: &Path,
+  offset: u64,
+  len: u64
+) -> Result<()> {
+  Ok(())
+}
+
+/// Creates `target` as a copy-on-write clone of `source`, failing where the
+/// filesystem cannot share blocks between files.
+#[cfg(target_os = "linux")]
+pub(crate) async fn reflink(
+  source: &Path,
+  target: &Path
+) -> std::io::Result<()> {
+  let source = File::open(source).await?;
+  let file = OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(target)
+    .await?;
+
+  if let Err(e) = rustix::fs::ioctl_ficlone(&file, &source) {
+    drop(file);
+    let _ = remove_file(target).await;
+    return Err(e.into());
+  }
+  Ok(())
+}
+
+/// Reflinks are not supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn reflink(
+  source: &Path,
+  target: &Path
+) -> std::io::Result<()> {
+  Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Returns true if files can be created in `dir`, or in its closest
+/// existing ancestor if it does not exist yet.
+///
+/// Creates and removes an empty probe file, as permission bits do not tell
+/// about ACLs, read-only mounts or running as root.
+pub(crate) async fn is_writable(dir: &Path) -> bool {
+  let Some(dir) = existing_ancestor(dir) else {
+    return false;
+  };
+
+  let probe = dir.join(format!(".downloader-probe-{}", std::process::id()));
+  match OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(&probe)
+    .await
+  {
+    Ok(_) => {
+      let _ = remove_file(&probe).await;
+      true
+    }
+    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => true,
+    Err(e) => {
+      debug!("Cannot write to {}: {}", dir.display(), e);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn test_checks_closest_existing_dir() {
+    let dir = TempDir::new().unwrap();
+    let missing = dir.path().join("not/created/yet");
+
+    assert!(is_writable(&missing).await);
+    assert!(!missing.exists());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    #[cfg(unix)]
+    assert!(available_space(&missing).is_some_and(|space| space > 0));
+  }
+
+  #[tokio::test]
+  async fn test_preallocate_keeps_length() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("file.part");
+    let file = File::create(&path).await.unwrap();
+
+    preallocate(&file, &path, 0, 1024 * 1024).unwrap();
+    assert_eq!(file.metadata().await.unwrap().len(), 0);
+
+    #[cfg(target_os = "linux")]
+    {
+      let too_large = available_space(&path).unwrap() + (1 << 30);
+      let error = preallocate(&file, &path, 0, too_large).unwrap_err();
+      assert!(matches!(error, Error::InsufficientSpace { .. }));
+    }
+  }
+
+  #[tokio::test]
+  async fn test_start_checks_previewed_sizes() {
+    let url = "https://example.com/data.bin";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(url, Fixture::new(vec![7u8; 4096]));
+
+    let target = TempDir::new().unwrap();
+    let mut downloader = Downloader::new(vec![url], target.path()).unwrap();
+    downloader
+      .with_transport(transport)
+      .with_disk_headroom(Some(u64::MAX / 2));
+    let preview = downloader.preview().await.unwrap();
+    assert_eq!(preview.files[0].status, preview::Status::InsufficientSpace);
+
+    let error = downloader.start().await.unwrap_err();
+    assert!(matches!(error, Error::InsufficientSpace { .. }));
+    assert!(!target.path().join("data.bin").exists());
+
+    downloader
+      .with_disk_headroom(Some(1024))
+      .with_preallocation(true);
+    let report = downloader.start().await.unwrap().await.unwrap();
+    assert!(report.is_success());
+    assert_eq!(
+      std::fs::read(target.path().join("data.bin")).unwrap(),
+      vec![7u8; 4096]
+    );
+  }
+}
@@ -27,6 +27,10 @@ pub enum Error {
     download: reqwest::Error,
   },
 
+  /// Transfer broke off before the full response was received
+  #[error("Transfer interrupted for '{url}': {message}")]
+  Interrupted { url: String, message: String },
+
   /// HTTP server returned an error status
   #[error("HTTP {status} error for '{url}': {message}")]
   HttpStatus {
@@ -63,6 +67,14 @@ pub enum Error {
   #[error("URL validation failed: {message}")]
   Validation { message: String },
 
+  /// Manifest file could not be parsed or has an invalid entry
+  #[error("Invalid manifest '{path}' at line {line}: {message}")]
+  Manifest {
+    path: String,
+    line: usize,
+    message: String,
+  },
+
   /// Configuration error
   #[error("Configuration error: {message}")]
   Configuration { message: String },
@@ -117,6 +129,10 @@ pub enum Error {
   #[error("Rate limited by server for '{url}': retry after {retry_after}s")]
   RateLimited { url: String, retry_after: u64 },
 
+  /// Download was cancelled through its control handle
+  #[error("Download cancelled")]
+  Cancelled,
+
   /// Multiple errors occurred (batch operations)
   #[error("Multiple errors occurred: {}", format_multiple_errors(.errors))]
   MultipleErrors { errors: Vec<Error> },
@@ -203,6 +219,30 @@ impl Error {
     }
   }
 
+  /// Creates an error for a transfer that broke off.
+  pub fn interrupted<S: AsRef<str>, M: Into<String>>(
+    url: S,
+    message: M,
+  ) -> Self {
+    Self::Interrupted {
+      url: url.as_ref().to_string(),
+      message: message.into(),
+    }
+  }
+
+  /// Creates an error for an invalid manifest entry at `line`.
+  pub fn manifest<P: Into<String>, M: Into<String>>(
+    path: P,
+    line: usize,
+    message: M,
+  ) -> Self {
+    Self::Manifest {
+      path: path.into(),
+      line,
+      message: message.into(),
+    }
+  }
+
   /// Creates a timeout error.
   pub fn timeout_error<S: AsRef<str>>(url: S, duration: u64) -> Self {
     Self::Timeout {
@@ -266,6 +306,7 @@ impl Error {
     match self {
       // Network issues that might be temporary
       Error::RequestFailed { .. } => true,
+      Error::Interrupted { .. } => true,
       Error::Timeout { .. } => true,
       Error::RateLimited { .. } => true,
 
@@ -298,6 +339,7 @@ impl Error {
         | Error::Authentication { .. }
         | Error::InsufficientSpace { .. }
         | Error::Configuration { .. }
+        | Error::Manifest { .. }
     )
   }
 
@@ -321,19 +363,23 @@ impl Error {
       ],
       Error::Authentication { .. } => vec![
         "Check your credentials",
-        "Verify you have permission to access this redownload",
-        "Add authentication headers to the configuration",
+        "Verify you have permission to access this resource",
+        "Add a credential provider for this host to the configuration",
       ],
       Error::InsufficientSpace { .. } => vec![
         "Free up disk space",
         "Choose a different download location",
         "Delete unnecessary files",
       ],
-      Error::RequestFailed { .. } => vec![
+      Error::RequestFailed { .. } | Error::Interrupted { .. } => vec![
         "Check your internet connection",
         "Verify the server is accessible",
         "Try again later if the server is temporarily down",
       ],
+      Error::Manifest { .. } => vec![
+        "Fix the manifest at the reported line",
+        "Check the field names and value types of the entry",
+      ],
       Error::Timeout { .. } => vec![
         "Increase the timeout duration",
         "Check your internet connection speed",
@@ -346,13 +392,15 @@ impl Error {
   /// Returns the error category for grouping similar errors.
   pub fn category(&self) -> ErrorKind {
     match self {
-      Error::InvalidUrl { .. } | Error::Validation { .. } => {
-        ErrorKind::Validation
-      }
+      Error::InvalidUrl { .. }
+      | Error::Validation { .. }
+      | Error::Manifest { .. } => ErrorKind::Validation,
       Error::InvalidPath { .. } | Error::FileSystem { .. } => {
         ErrorKind::FileSystem
       }
-      Error::RequestFailed { .. } | Error::Timeout { .. } => ErrorKind::Network,
+      Error::RequestFailed { .. }
+      | Error::Interrupted { .. }
+      | Error::Timeout { .. } => ErrorKind::Network,
       Error::HttpStatus { .. } => ErrorKind::Http,
       Error::FileExists(_) | Error::ExistingFiles { .. } => {
         ErrorKind::FileConflict
@@ -363,7 +411,7 @@ impl Error {
       Error::FileTooLarge { .. } | Error::InsufficientSpace { .. } => {
         ErrorKind::Storage
       }
-      Error::TaskFailed { .. } => ErrorKind::Task,
+      Error::TaskFailed { .. } | Error::Cancelled => ErrorKind::Task,
       Error::Configuration { .. } => ErrorKind::Configuration,
       _ => ErrorKind::Other,
     }
@@ -371,7 +419,7 @@ impl Error {
 }
 
 /// Categories for grouping related errors.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
 pub enum ErrorKind {
   Validation,
   FileSystem,
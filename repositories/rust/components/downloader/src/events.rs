@@ -5,6 +5,7 @@
 //! and status changes.
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 /// Events emitted during the download process.
@@ -12,7 +13,11 @@ use std::fmt::Debug;
 /// These events provide detailed information about the download lifecycle,
 /// allowing users to implement custom logging, UI updates, or other
 /// response mechanisms.
-#[derive(Debug, Clone)]
+///
+/// Events serialize with their variant name in snake case under an `event`
+/// key, next to the fields of the variant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
 pub enum DownloadEvent {
   /// Preview generation has started
   PreviewStarted { url_count: usize },
@@ -39,7 +44,10 @@ pub enum DownloadEvent {
     index: usize,
     bytes_downloaded: u64,
     total_bytes: Option<u64>,
-    percentage: f64
+    percentage: f64,
+    /// Bytes written by decompressing the downloaded ones, if decompressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    decompressed_bytes: Option<u64>
   },
 
   /// A single file download completed successfully
@@ -47,7 +55,17 @@ pub enum DownloadEvent {
     index: usize,
     filename: String,
     bytes_downloaded: u64,
-    duration: std::time::Duration
+    duration: std::time::Duration,
+    /// Mirror the file was downloaded from instead of its URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mirror: Option<String>
+  },
+
+  /// The server reported that an existing file has not been modified
+  FileNotModified {
+    index: usize,
+    url: String,
+    filename: String
   },
 
   /// A single file download failed
@@ -58,13 +76,17 @@ pub enum DownloadEvent {
     retry_count: usize
   },
 
+  /// A file download was cancelled; its partial file is kept for resuming
+  FileCancelled { index: usize, url: String },
+
   /// A file download is being retried
   FileRetrying {
     index: usize,
     url: String,
     attempt: usize,
     max_attempts: usize,
-    delay: std::time::Duration
+    delay: std::time::Duration,
+    reason: String
   },
 
   /// All downloads have completed
@@ -74,6 +96,13 @@ pub enum DownloadEvent {
     errors: Vec<String>
   },
 
+  /// The download process was cancelled before all files completed
+  DownloadCancelled {
+    successful: usize,
+    failed: usize,
+    cancelled: usize
+  },
+
   /// A warning occurred (non-fatal)
   Warning {
     message: String,
@@ -180,7 +209,8 @@ impl EventSink for LoggingEventSink {
         index,
         bytes_downloaded,
         total_bytes,
-        percentage
+        percentage,
+        ..
       } =>
         if self.log_progress {
           if let Some(total) = total_bytes {
@@ -197,17 +227,27 @@ impl EventSink for LoggingEventSink {
         index,
         filename,
         bytes_downloaded,
-        duration
+        duration,
+        mirror
       } => {
         info!(
-          "File {}: Completed {} ({} in {:.1}s)",
+          "File {}: Completed {} ({} in {:.1}s){}",
           index,
           filename,
           format_bytes(bytes_downloaded),
-          duration.as_secs_f64()
+          duration.as_secs_f64(),
+          mirror.map(|m| format!(" from {m}")).unwrap_or_default()
         );
       }
 
+      DownloadEvent::FileNotModified {
+        index,
+        url,
+        filename
+      } => {
+        info!("File {}: {} is up to date ({})", index, filename, url);
+      }
+
       DownloadEvent::FileFailed {
         index,
         url,
@@ -223,20 +263,26 @@ impl EventSink for LoggingEventSink {
           error!("File {}: Failed - {} ({})", index, url, error);
         },
 
+      DownloadEvent::FileCancelled { index, url } => {
+        info!("File {}: Cancelled - {}", index, url);
+      }
+
       DownloadEvent::FileRetrying {
         index,
         url,
         attempt,
         max_attempts,
-        delay
+        delay,
+        reason
       } => {
         warn!(
-          "File {}: Retrying {}/{} after {:.1}s - {}",
+          "File {}: Retrying {}/{} after {:.1}s - {} ({})",
           index,
           attempt,
           max_attempts,
           delay.as_secs_f64(),
-          url
+          url,
+          reason
         );
       }
 
@@ -257,6 +303,17 @@ impl EventSink for LoggingEventSink {
           }
         },
 
+      DownloadEvent::DownloadCancelled {
+        successful,
+        failed,
+        cancelled
+      } => {
+        warn!(
+          "Download cancelled: {} successful, {} failed, {} cancelled",
+          successful, failed, cancelled
+        );
+      }
+
       DownloadEvent::Warning { message, context } => {
         if let Some(ctx) = context {
           warn!("{}: {}", ctx, message);
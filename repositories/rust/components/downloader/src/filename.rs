@@ -3,7 +3,8 @@
 //! This module provides various strategies for generating filenames
 //! from URLs, handling edge cases, conflicts, and user preferences.
 
-use crate::{Error, Result};
+use crate::{Error, Result, validation};
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE, HeaderMap};
 use std::{
   ffi::OsStr,
   path::{Path, PathBuf}
@@ -13,7 +14,7 @@ use std::{
 ///
 /// Different strategies provide varying levels of intelligence and
 /// safety when determining what to name downloaded files.
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, Default)]
 pub enum Strategy {
   /// Simple extraction from URL path (original behavior)
   Simple,
@@ -51,19 +52,60 @@ impl Strategy {
   ) -> Result<String> {
     match self {
       Strategy::Simple => extract_simple(url),
-      Strategy::Smart => extract_smart(url, index),
+      Strategy::Smart => extract_smart(url, index, None),
       Strategy::Sequential => extract_sequential(index),
       Strategy::UrlHash => extract_url_hash(url),
       Strategy::Custom(func) => func(url, index)
     }
   }
 
+  /// Extracts a filename using the headers of a response for the URL.
+  ///
+  /// The smart strategy prefers the `Content-Disposition` filename and
+  /// infers a missing extension from `Content-Type`; the other strategies
+  /// ignore the headers.
+  pub fn extract_filename_with_headers(
+    &self,
+    url: &reqwest::Url,
+    index: usize,
+    headers: &HeaderMap
+  ) -> Result<String> {
+    match self {
+      Strategy::Smart => extract_smart(url, index, Some(headers)),
+      _ => self.extract_filename(url, index)
+    }
+  }
+
+  /// Returns true if the URL alone may not name the file well, so the
+  /// response headers are worth fetching before extracting the filename.
+  ///
+  /// This is the case for the smart strategy when the URL has a query
+  /// string or its last path segment has no extension.
+  pub fn uses_response_headers(&self, url: &reqwest::Url) -> bool {
+    if *self != Strategy::Smart {
+      return false;
+    }
+
+    url.query().is_some()
+      || try_from_path_segments(url).is_none_or(|name| !name.contains('.'))
+  }
+
   /// Creates a custom filename strategy from a function.
   pub fn custom(func: fn(&reqwest::Url, usize) -> Result<String>) -> Self {
     Strategy::Custom(func)
   }
 }
 
+impl PartialEq for Strategy {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Strategy::Custom(a), Strategy::Custom(b)) =>
+        std::ptr::fn_addr_eq(*a, *b),
+      _ => std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+  }
+}
+
 /// Simple filename extraction (original behavior).
 ///
 /// Extracts the last path segment and adds .bin if no extension.
@@ -93,33 +135,47 @@ fn extract_simple(url: &reqwest::Url) -> Result<String> {
 /// - Sanitizes invalid filesystem characters
 /// - Provides intelligent fallbacks
 /// - Handles edge cases gracefully
-fn extract_smart(url: &reqwest::Url, index: usize) -> Result<String> {
+/// - Uses response headers, when available, for the name and extension
+fn extract_smart(
+  url: &reqwest::Url,
+  index: usize,
+  headers: Option<&HeaderMap>
+) -> Result<String> {
   trace!("Extracting smart filename from: {url}");
 
-  // Try multiple extraction methods in order of preference
-  let candidates = vec![
-    try_from_content_disposition(url),
-    try_from_path_segments(url),
-    try_from_query_params(url),
-    try_from_domain_and_path(url),
-  ];
-
-  let mut filename = None;
-  for candidate in candidates {
-    if let Some(name) = candidate
-      && is_valid_filename(&name)
-    {
-      filename = Some(name);
-      break;
-    }
-  }
+  // The Content-Disposition filename is decoded while it is parsed
+  let disposition = headers
+    .and_then(try_from_content_disposition)
+    .filter(|name| is_valid_filename(name));
+
+  let mut filename = match disposition {
+    Some(name) => name,
+    None => {
+      // Try multiple extraction methods in order of preference
+      let candidates = vec![
+        try_from_path_segments(url),
+        try_from_query_params(url),
+        try_from_domain_and_path(url),
+      ];
+
+      let mut filename = None;
+      for candidate in candidates {
+        if let Some(name) = candidate
+          && is_valid_filename(&name)
+        {
+          filename = Some(name);
+          break;
+        }
+      }
 
-  let mut filename = filename.unwrap_or_else(|| format!("download_{index}"));
+      let filename = filename.unwrap_or_else(|| format!("download_{index}"));
 
-  // URL decode the filename
-  filename = urlencoding::decode(&filename)
-    .map(|s| s.into_owned())
-    .unwrap_or(filename);
+      // URL decode the filename
+      urlencoding::decode(&filename)
+        .map(|s| s.into_owned())
+        .unwrap_or(filename)
+    }
+  };
 
   // Sanitize the filename
   filename = sanitize_filename(&filename);
@@ -142,7 +198,7 @@ fn extract_smart(url: &reqwest::Url, index: usize) -> Result<String> {
 
   // Add extension if missing
   if !filename.contains('.') {
-    filename = infer_extension(&filename, url)
+    filename = infer_extension(&filename, url, headers)
       .unwrap_or_else(|_| format!("{filename}.bin"));
   }
 
@@ -193,12 +249,135 @@ fn extract_url_hash(url: &reqwest::Url) -> Result<String> {
   Ok(format!("{hash:016x}.{extension}"))
 }
 
-/// Attempts to extract filename from Content-Disposition header (future
-/// enhancement).
-fn try_from_content_disposition(_url: &reqwest::Url) -> Option<String> {
-  // This would require making an HTTP HEAD request
-  // For now, return None to fall back to other methods
-  None
+/// Attempts to extract filename from the Content-Disposition header.
+fn try_from_content_disposition(headers: &HeaderMap) -> Option<String> {
+  let value = headers.get(CONTENT_DISPOSITION)?.to_str().ok()?;
+  let filename = sanitize_filename(&parse_content_disposition(value)?);
+  (!filename.is_empty()).then_some(filename)
+}
+
+/// Parses the filename from a `Content-Disposition` header value.
+///
+/// The RFC 5987 `filename*` parameter takes precedence over `filename`, as
+/// required by RFC 6266. A percent-encoded `filename` is decoded as well.
+/// Any directory components are dropped.
+pub fn parse_content_disposition(value: &str) -> Option<String> {
+  let mut filename = None;
+  let mut extended = None;
+
+  // The first part is the disposition type
+  for param in split_header_params(value).into_iter().skip(1) {
+    let Some((name, value)) = param.split_once('=') else {
+      continue;
+    };
+
+    match name.trim().to_ascii_lowercase().as_str() {
+      "filename*" => extended = decode_ext_value(value.trim()),
+      "filename" => {
+        let name = unquote(value.trim());
+        filename = Some(match urlencoding::decode(&name) {
+          Ok(decoded) => decoded.into_owned(),
+          Err(_) => name
+        });
+      }
+      _ => {}
+    }
+  }
+
+  extended
+    .or(filename)
+    .and_then(|name| name.rsplit(['/', '\\']).next().map(str::to_string))
+    .filter(|name| !name.trim().is_empty())
+}
+
+/// Splits a header value on semicolons outside of quoted strings.
+fn split_header_params(value: &str) -> Vec<&str> {
+  let mut params = Vec::new();
+  let mut start = 0;
+  let mut in_quotes = false;
+  let mut escaped = false;
+
+  for (i, c) in value.char_indices() {
+    match c {
+      _ if escaped => escaped = false,
+      '\\' if in_quotes => escaped = true,
+      '"' => in_quotes = !in_quotes,
+      ';' if !in_quotes => {
+        params.push(&value[start..i]);
+        start = i + 1;
+      }
+      _ => {}
+    }
+  }
+
+  params.push(&value[start..]);
+  params
+}
+
+/// Removes the quotes and escapes of a quoted-string, if quoted.
+fn unquote(value: &str) -> String {
+  let Some(inner) = value
+    .strip_prefix('"')
+    .map(|v| v.strip_suffix('"').unwrap_or(v))
+  else {
+    return value.to_string();
+  };
+
+  let mut unquoted = String::with_capacity(inner.len());
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    if c == '\\' {
+      unquoted.extend(chars.next());
+    } else {
+      unquoted.push(c);
+    }
+  }
+  unquoted
+}
+
+/// Decodes an RFC 5987 extended value (`charset'language'percent-encoded`).
+fn decode_ext_value(value: &str) -> Option<String> {
+  let mut parts = value.splitn(3, '\'');
+  let charset = parts.next()?.trim();
+  let _language = parts.next()?;
+  let bytes = urlencoding::decode_binary(parts.next()?.as_bytes());
+
+  if charset.eq_ignore_ascii_case("utf-8") {
+    String::from_utf8(bytes.into_owned()).ok()
+  } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+    Some(bytes.iter().map(|&b| b as char).collect())
+  } else {
+    None
+  }
+}
+
+/// Returns the usual file extension for a `Content-Type` header value.
+pub fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+  let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+
+  let extension = match mime.as_str() {
+    "application/gzip" | "application/x-gzip" => "gz",
+    "application/zstd" => "zst",
+    "application/x-bzip2" => "bz2",
+    "application/zip" => "zip",
+    "application/x-tar" => "tar",
+    "application/json" => "json",
+    "application/xml" | "text/xml" => "xml",
+    "application/pdf" => "pdf",
+    "application/vnd.apache.parquet" => "parquet",
+    "text/tab-separated-values" => "tsv",
+    "text/csv" => "csv",
+    "text/plain" => "txt",
+    "text/html" => "html",
+    "image/jpeg" => "jpg",
+    "image/png" => "png",
+    "image/gif" => "gif",
+    "video/mp4" => "mp4",
+    "audio/mpeg" => "mp3",
+    _ => return None
+  };
+
+  Some(extension)
 }
 
 /// Attempts to extract filename from URL path segments.
@@ -302,7 +481,11 @@ fn is_reserved_filename(filename: &str) -> bool {
 
 /// Attempts to infer an appropriate file extension based on URL and content
 /// hints.
-fn infer_extension(filename: &str, url: &reqwest::Url) -> Result<String> {
+fn infer_extension(
+  filename: &str,
+  url: &reqwest::Url,
+  headers: Option<&HeaderMap>
+) -> Result<String> {
   // Try to infer from URL path
   if let Some(path_ext) = url.path().rfind('.') {
     let ext = &url.path()[path_ext + 1..];
@@ -314,6 +497,15 @@ fn infer_extension(filename: &str, url: &reqwest::Url) -> Result<String> {
     }
   }
 
+  // Try to infer from the response Content-Type
+  if let Some(ext) = headers
+    .and_then(|h| h.get(CONTENT_TYPE))
+    .and_then(|v| v.to_str().ok())
+    .and_then(extension_for_content_type)
+  {
+    return Ok(format!("{filename}.{ext}"));
+  }
+
   // Try to infer from domain or query parameters
   let url_str = url.as_str().to_lowercase();
   if url_str.contains("image")
@@ -344,9 +536,10 @@ pub struct ConflictResolver {
   strategy: ConflictStrategy
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum ConflictStrategy {
   /// Add numeric suffix: file.txt -> file (1).txt
+  #[default]
   NumericSuffix,
 
   /// Add timestamp suffix: file.txt -> file_20240127_143022.txt
@@ -363,25 +556,82 @@ impl ConflictResolver {
   }
 
   /// Resolves a filename conflict by generating an alternative name.
+  ///
+  /// The returned path is neither in `existing_files` nor on disk; a
+  /// timestamp or hash suffix that still collides gets a numeric suffix.
   pub fn resolve_conflict(
     &self,
     original_path: &Path,
     existing_files: &[PathBuf]
   ) -> PathBuf {
-    match self.strategy {
+    let candidate = match self.strategy {
       ConflictStrategy::NumericSuffix =>
-        self.resolve_with_numeric_suffix(original_path, existing_files),
+        return self.resolve_with_numeric_suffix(
+          original_path,
+          existing_files,
+          true
+        ),
       ConflictStrategy::TimestampSuffix =>
         self.resolve_with_timestamp_suffix(original_path),
       ConflictStrategy::HashSuffix =>
         self.resolve_with_hash_suffix(original_path),
+    };
+
+    if existing_files.contains(&candidate) || candidate.exists() {
+      self.resolve_with_numeric_suffix(&candidate, existing_files, true)
+    } else {
+      candidate
+    }
+  }
+
+  /// Gives every URL of a batch a distinct target path.
+  ///
+  /// Later URLs whose target duplicates an earlier one are renamed. With
+  /// `rename_existing`, targets that already exist on disk are renamed too.
+  /// Otherwise duplicates get the first numeric suffix not taken in the
+  /// batch, whether or not it exists on disk, so that running the batch
+  /// again picks the same names. Renamed URLs keep their original path in
+  /// `renamed_from`.
+  pub fn resolve_targets(
+    &self,
+    urls: &mut [validation::Url],
+    rename_existing: bool
+  ) {
+    let mut claimed = Vec::with_capacity(urls.len());
+
+    for url in urls.iter_mut() {
+      if claimed.contains(&url.target_path) || (rename_existing && url.exists) {
+        let renamed = if rename_existing {
+          self.resolve_conflict(&url.target_path, &claimed)
+        } else {
+          self.resolve_with_numeric_suffix(&url.target_path, &claimed, false)
+        };
+        debug!(
+          "Renaming {} to {} to avoid a conflict",
+          url.target_path.display(),
+          renamed.display()
+        );
+
+        url.filename = renamed
+          .file_name()
+          .map(|n| n.to_string_lossy().into_owned())
+          .unwrap_or_else(|| url.filename.clone());
+        url.renamed_from =
+          Some(std::mem::replace(&mut url.target_path, renamed));
+        url.exists = url.target_path.exists();
+      }
+
+      claimed.push(url.target_path.clone());
     }
   }
 
+  /// Appends the first free numeric suffix, skipping paths on disk too if
+  /// `avoid_existing` is set.
   fn resolve_with_numeric_suffix(
     &self,
     original_path: &Path,
-    existing_files: &[PathBuf]
+    existing_files: &[PathBuf],
+    avoid_existing: bool
   ) -> PathBuf {
     let parent = original_path.parent().unwrap_or_else(|| Path::new(""));
     let stem = original_path
@@ -403,7 +653,9 @@ impl ConflictResolver {
 
       let new_path = parent.join(&new_filename);
 
-      if !existing_files.contains(&new_path) && !new_path.exists() {
+      let taken = existing_files.contains(&new_path)
+        || (avoid_existing && new_path.exists());
+      if !taken {
         return new_path;
       }
 
@@ -468,6 +720,7 @@ impl ConflictResolver {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::{Downloader, Fixture, MemoryTransport};
 
   #[test]
   fn test_simple_filename_extraction() {
@@ -487,11 +740,11 @@ mod tests {
   #[test]
   fn test_smart_filename_extraction() {
     let url = reqwest::Url::parse("https://example.com/my%20file.pdf").unwrap();
-    let result = extract_smart(&url, 0).unwrap();
+    let result = extract_smart(&url, 0, None).unwrap();
     assert_eq!(result, "my_file.pdf");
 
     let url = reqwest::Url::parse("https://example.com/CON").unwrap();
-    let result = extract_smart(&url, 5).unwrap();
+    let result = extract_smart(&url, 5, None).unwrap();
     assert!(result.starts_with("file_5."));
   }
 
@@ -524,4 +777,126 @@ mod tests {
     assert!(result.ends_with(".pdf"));
     assert!(result.len() > 20); // Hash + extension
   }
+
+  #[test]
+  fn test_resolve_targets() {
+    let dir = tempfile::TempDir::new().unwrap();
+    std::fs::write(dir.path().join("taken.tsv"), b"").unwrap();
+
+    let url = |name: &str| {
+      let target_path = dir.path().join(name);
+      validation::Url {
+        original: format!("https://example.com/{name}"),
+        parsed: reqwest::Url::parse("https://example.com/").unwrap(),
+        filename: name.to_string(),
+        exists: target_path.exists(),
+        target_path,
+        size_hint: None,
+        renamed_from: None,
+        decompress: None,
+        probe: None
+      }
+    };
+    let mut urls = vec![url("data.tsv"), url("data.tsv"), url("taken.tsv")];
+
+    let resolver = ConflictResolver::new(ConflictStrategy::NumericSuffix);
+    resolver.resolve_targets(&mut urls, true);
+
+    assert_eq!(urls[0].renamed_from, None);
+    assert_eq!(urls[1].filename, "data (1).tsv");
+    assert_eq!(urls[1].renamed_from, Some(dir.path().join("data.tsv")));
+    assert_eq!(urls[2].target_path, dir.path().join("taken (1).tsv"));
+    assert!(!urls[2].exists);
+  }
+
+  #[tokio::test]
+  async fn test_duplicate_targets_stable_across_runs() {
+    let transport = std::sync::Arc::new(MemoryTransport::new());
+    transport.insert("https://a.example.com/data.tsv", Fixture::new("a\n"));
+    transport.insert("https://b.example.com/data.tsv", Fixture::new("b\n"));
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let run = || async {
+      let mut downloader = Downloader::new(
+        vec![
+          "https://a.example.com/data.tsv",
+          "https://b.example.com/data.tsv",
+        ],
+        dir.path()
+      )
+      .unwrap();
+      downloader.with_transport(transport.clone()).skip_existing();
+      downloader.start().await.unwrap().await.unwrap()
+    };
+
+    let first = run().await;
+    assert_eq!(first.downloaded().count(), 2);
+    let renamed = dir.path().join("data (1).tsv");
+    assert_eq!(std::fs::read(&renamed).unwrap(), b"b\n");
+
+    // The duplicate keeps its name and is skipped like the first file
+    let second = run().await;
+    let skipped: Vec<_> = second.skipped().map(|e| &e.target).collect();
+    assert_eq!(skipped, [&dir.path().join("data.tsv"), &renamed]);
+    assert!(!dir.path().join("data (2).tsv").exists());
+  }
+
+  #[test]
+  fn test_parse_content_disposition() {
+    assert_eq!(
+      parse_content_disposition("attachment; filename=\"title; basics.tsv\""),
+      Some("title; basics.tsv".to_string())
+    );
+    assert_eq!(
+      parse_content_disposition(
+        "attachment; filename=\"fallback.txt\"; filename*=UTF-8''na%C3%AFve.txt"
+      ),
+      Some("naïve.txt".to_string())
+    );
+    assert_eq!(
+      parse_content_disposition("attachment; filename=../../etc/passwd"),
+      Some("passwd".to_string())
+    );
+    assert_eq!(
+      parse_content_disposition("attachment; filename*=UTF-8''100%2525.csv"),
+      Some("100%25.csv".to_string())
+    );
+    assert_eq!(
+      parse_content_disposition("attachment; filename=\"my%20file.csv\""),
+      Some("my file.csv".to_string())
+    );
+    assert_eq!(parse_content_disposition("inline"), None);
+  }
+
+  #[test]
+  fn test_smart_filename_from_headers() {
+    let url =
+      reqwest::Url::parse("https://example.com/download?id=123").unwrap();
+    assert!(Strategy::Smart.uses_response_headers(&url));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      CONTENT_DISPOSITION,
+      "attachment; filename=\"report:2024\"".parse().unwrap()
+    );
+    headers.insert(CONTENT_TYPE, "text/csv; charset=utf-8".parse().unwrap());
+
+    let filename = Strategy::Smart
+      .extract_filename_with_headers(&url, 0, &headers)
+      .unwrap();
+    assert_eq!(filename, "report_2024.csv");
+
+    // An extended filename is not percent-decoded a second time
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      CONTENT_DISPOSITION,
+      "attachment; filename*=UTF-8''rate%2541.csv"
+        .parse()
+        .unwrap()
+    );
+    let filename = Strategy::Smart
+      .extract_filename_with_headers(&url, 0, &headers)
+      .unwrap();
+    assert_eq!(filename, "rate%41.csv");
+  }
 }
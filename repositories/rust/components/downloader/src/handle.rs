@@ -0,0 +1,152 @@
+//! Handle to a batch of downloads running in the background
+//!
+//! [`Downloader::start`] spawns the batch and returns a [`DownloadHandle`]
+//! right away. The handle gives access to live progress while the downloads
+//! run and resolves to a [`BatchReport`] when awaited.
+
+use crate::*;
+use std::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll}
+};
+use tokio::task::JoinHandle;
+
+/// Handle to a running batch of downloads.
+///
+/// Awaiting the handle waits for the batch to finish and yields a report on
+/// every file. Dropping it detaches the batch, which keeps running; use
+/// [`DownloadHandle::cancel`] to stop it.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use downloader::Downloader;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut downloader =
+///   Downloader::new(vec!["https://httpbin.org/json"], "./downloads")?;
+/// let handle = downloader.start().await?;
+///
+/// let mut updates = handle.subscribe();
+/// tokio::spawn(async move {
+///   while let Ok(snapshot) = updates.recv().await {
+///     println!("{}", snapshot.summary());
+///   }
+/// });
+///
+/// let report = handle.await?;
+/// for entry in report.failed() {
+///   println!("{} failed", entry.url);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DownloadHandle {
+  /// Live progress of the batch
+  reporter: Reporter,
+  /// Cancellation and pause handle shared with the tasks
+  control: Control,
+  /// Background task producing the report
+  report: JoinHandle<BatchReport>
+}
+
+impl DownloadHandle {
+  /// Creates a handle for a spawned batch.
+  pub(crate) fn new(
+    reporter: Reporter,
+    control: Control,
+    report: JoinHandle<BatchReport>
+  ) -> Self {
+    Self {
+      reporter,
+      control,
+      report
+    }
+  }
+
+  /// Creates a handle for a batch with nothing to download.
+  pub(crate) fn completed(control: Control, report: BatchReport) -> Self {
+    Self::new(
+      Reporter::completed(),
+      control,
+      tokio::spawn(async { report })
+    )
+  }
+
+  /// Returns the live progress reporter of the batch.
+  pub fn reporter(&self) -> &Reporter {
+    &self.reporter
+  }
+
+  /// Subscribes to progress snapshots of the batch.
+  pub fn subscribe(&self) -> broadcast::Receiver<Snapshot> {
+    self.reporter.subscribe()
+  }
+
+  /// Returns the current progress of the batch.
+  pub fn snapshot(&self) -> Snapshot {
+    self.reporter.current_snapshot()
+  }
+
+  /// Returns the control for pausing, resuming and cancelling the batch.
+  pub fn control(&self) -> &Control {
+    &self.control
+  }
+
+  /// Cancels the batch; awaiting the handle still yields the report.
+  pub fn cancel(&self) {
+    self.control.cancel();
+  }
+
+  /// Returns true once every download of the batch has finished.
+  pub fn is_finished(&self) -> bool {
+    self.report.is_finished()
+  }
+}
+
+impl Future for DownloadHandle {
+  type Output = Result<BatchReport>;
+
+  fn poll(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<Self::Output> {
+    Pin::new(&mut self.report).poll(cx).map_err(Error::from)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_handle_resolves_to_report() {
+    let reporter = Reporter::new(1);
+    let mut updates = reporter.subscribe();
+    let sender = reporter.sender();
+    let report = tokio::spawn(async move {
+      sender.failed(0, "Network error".to_string());
+      BatchReport::new(Vec::new(), Duration::ZERO, None)
+    });
+
+    let handle = DownloadHandle::new(reporter, Control::new(), report);
+
+    let snapshot = updates.recv().await.unwrap();
+    assert_eq!(snapshot.failed, 1);
+    assert!(handle.await.unwrap().is_success());
+  }
+
+  #[tokio::test]
+  async fn test_completed_handle() {
+    let control = Control::new();
+    let report = BatchReport::new(Vec::new(), Duration::ZERO, None);
+    let handle = DownloadHandle::completed(control.clone(), report);
+
+    handle.cancel();
+    assert!(control.is_cancelled());
+    assert!(handle.await.unwrap().entries.is_empty());
+  }
+}
@@ -0,0 +1,613 @@
+//! Steps run on each file as soon as its download finishes
+//!
+//! A [`Hook`] is an async closure or an external command. The hooks of a
+//! batch are run for every file that completes, and optionally for every
+//! file that fails, while the other downloads continue. Commands receive the
+//! file path and metadata as `DOWNLOAD_*` environment variables. A failing
+//! hook does not fail the download: it is reported as a
+//! [`DownloadEvent::Warning`] and listed in the [`BatchReport`]. So is a hook
+//! still running after `Config::hook_timeout`, which is then stopped.
+
+use crate::*;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::{
+  ffi::OsStr,
+  fmt,
+  future::Future,
+  path::PathBuf,
+  process::Stdio,
+  sync::{Arc, Mutex},
+  time::Duration
+};
+use tokio::{process::Command, sync::Semaphore, task::JoinSet};
+
+type Callback = Arc<
+  dyn Fn(Context) -> BoxFuture<'static, std::result::Result<(), String>>
+    + Send
+    + Sync
+>;
+
+/// A step run on a file after its download finished.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use downloader::{Config, Hook, HookTrigger};
+///
+/// let config = Config::builder()
+///   .hook(Hook::command("gunzip").arg("--keep").arg_path())
+///   .hook(
+///     Hook::closure(|context| async move {
+///       println!("{} failed: {:?}", context.url, context.error);
+///       Ok::<_, String>(())
+///     })
+///     .on(&[HookTrigger::Failed])
+///   )
+///   .hook_concurrency(4)
+///   .build();
+/// ```
+#[derive(Clone)]
+pub struct Hook {
+  name: String,
+  action: Action,
+  completed: bool,
+  failed: bool
+}
+
+/// What a hook runs.
+#[derive(Clone)]
+enum Action {
+  Closure(Callback),
+  Command { program: String, args: Vec<Arg> }
+}
+
+/// Argument of a hook command.
+#[derive(Debug, Clone)]
+enum Arg {
+  Value(String),
+  Path
+}
+
+/// Outcome of a download that runs a hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+  /// The file was downloaded to its target path
+  Completed,
+
+  /// The download failed
+  Failed
+}
+
+/// The file a hook is run on.
+#[derive(Debug, Clone)]
+pub struct Context {
+  /// Index of the download in the batch
+  pub index: usize,
+
+  /// URL the file was downloaded from
+  pub url: String,
+
+  /// Target path of the file
+  pub path: PathBuf,
+
+  /// Whether the download completed or failed
+  pub trigger: Trigger,
+
+  /// Bytes downloaded, for completed files
+  pub bytes_downloaded: Option<u64>,
+
+  /// Time the download took, for completed files
+  pub duration: Option<Duration>,
+
+  /// Error of a failed download
+  pub error: Option<String>
+}
+
+/// A hook that failed on a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+  /// Index of the download in the batch
+  pub index: usize,
+
+  /// URL of the file
+  pub url: String,
+
+  /// Target path of the file
+  pub path: PathBuf,
+
+  /// Name of the hook
+  pub hook: String,
+
+  /// Why the hook failed
+  pub error: String
+}
+
+impl Hook {
+  /// Creates a hook running `f` on each completed file.
+  ///
+  /// An error returned by the closure is reported as a hook failure.
+  pub fn closure<F, Fut, E>(f: F) -> Self
+  where
+    F: Fn(Context) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = std::result::Result<(), E>> + Send + 'static,
+    E: fmt::Display
+  {
+    let f = Arc::new(f);
+    let callback: Callback = Arc::new(move |context| {
+      let future = f(context);
+      Box::pin(async move { future.await.map_err(|e| e.to_string()) })
+    });
+
+    Self {
+      name: "closure".to_string(),
+      action: Action::Closure(callback),
+      completed: true,
+      failed: false
+    }
+  }
+
+  /// Creates a hook running `program` on each completed file.
+  ///
+  /// The command gets `DOWNLOAD_INDEX`, `DOWNLOAD_URL`, `DOWNLOAD_PATH` and
+  /// `DOWNLOAD_STATUS` in its environment, plus `DOWNLOAD_BYTES` and
+  /// `DOWNLOAD_DURATION` (seconds) for completed files or `DOWNLOAD_ERROR`
+  /// for failed ones. It fails if it cannot be started or exits with a
+  /// non-zero status.
+  pub fn command<S: Into<String>>(program: S) -> Self {
+    let program = program.into();
+    Self {
+      name: program.clone(),
+      action: Action::Command {
+        program,
+        args: Vec::new()
+      },
+      completed: true,
+      failed: false
+    }
+  }
+
+  /// Adds an argument to a command hook.
+  pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+    if let Action::Command { args, .. } = &mut self.action {
+      args.push(Arg::Value(arg.into()));
+    }
+    self
+  }
+
+  /// Adds arguments to a command hook.
+  pub fn args<I, S>(mut self, values: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>
+  {
+    if let Action::Command { args, .. } = &mut self.action {
+      args.extend(values.into_iter().map(|value| Arg::Value(value.into())));
+    }
+    self
+  }
+
+  /// Adds the path of the file as an argument to a command hook.
+  pub fn arg_path(mut self) -> Self {
+    if let Action::Command { args, .. } = &mut self.action {
+      args.push(Arg::Path);
+    }
+    self
+  }
+
+  /// Sets the name the hook is reported by.
+  pub fn named<S: Into<String>>(mut self, name: S) -> Self {
+    self.name = name.into();
+    self
+  }
+
+  /// Sets the download outcomes the hook runs on.
+  pub fn on(mut self, triggers: &[Trigger]) -> Self {
+    self.completed = triggers.contains(&Trigger::Completed);
+    self.failed = triggers.contains(&Trigger::Failed);
+    self
+  }
+
+  /// Returns the name the hook is reported by.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Returns true if the hook runs on downloads with this outcome.
+  pub fn runs_on(&self, trigger: Trigger) -> bool {
+    match trigger {
+      Trigger::Completed => self.completed,
+      Trigger::Failed => self.failed
+    }
+  }
+
+  /// Runs the hook on a file.
+  async fn run(&self, context: &Context) -> std::result::Result<(), String> {
+    let (program, args) = match &self.action {
+      Action::Closure(callback) => return callback(context.clone()).await,
+      Action::Command { program, args } => (program, args)
+    };
+
+    let output = Command::new(program)
+      .args(args.iter().map(|arg| match arg {
+        Arg::Value(value) => OsStr::new(value),
+        Arg::Path => context.path.as_os_str()
+      }))
+      .envs(context.env())
+      .stdin(Stdio::null())
+      .kill_on_drop(true)
+      .output()
+      .await
+      .map_err(|e| format!("failed to start '{program}': {e}"))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+      debug!("Hook {} output: {}", self.name, stdout.trim());
+    }
+
+    if output.status.success() {
+      return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match stderr.trim().lines().last() {
+      Some(line) => Err(format!("{} ({line})", output.status)),
+      None => Err(output.status.to_string())
+    }
+  }
+}
+
+impl fmt::Debug for Hook {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut debug = f.debug_struct("Hook");
+    debug.field("name", &self.name);
+    if let Action::Command { program, args } = &self.action {
+      debug.field("program", program).field("args", args);
+    }
+    debug
+      .field("completed", &self.completed)
+      .field("failed", &self.failed)
+      .finish()
+  }
+}
+
+impl Context {
+  /// Returns the environment variables passed to command hooks.
+  pub fn env(&self) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+      ("DOWNLOAD_INDEX", self.index.to_string()),
+      ("DOWNLOAD_URL", self.url.clone()),
+      ("DOWNLOAD_PATH", self.path.to_string_lossy().into_owned()),
+      ("DOWNLOAD_STATUS", self.trigger.to_string()),
+    ];
+    if let Some(bytes) = self.bytes_downloaded {
+      env.push(("DOWNLOAD_BYTES", bytes.to_string()));
+    }
+    if let Some(duration) = self.duration {
+      env.push((
+        "DOWNLOAD_DURATION",
+        format!("{:.3}", duration.as_secs_f64())
+      ));
+    }
+    if let Some(error) = &self.error {
+      env.push(("DOWNLOAD_ERROR", error.clone()));
+    }
+    env
+  }
+}
+
+impl fmt::Display for Trigger {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Trigger::Completed => write!(f, "completed"),
+      Trigger::Failed => write!(f, "failed")
+    }
+  }
+}
+
+/// Event sink running the hooks of a batch on the files its tasks finish,
+/// before passing events on.
+#[derive(Debug)]
+pub(crate) struct Runner {
+  hooks: Arc<[Hook]>,
+  targets: Vec<(String, PathBuf)>,
+  sink: Arc<dyn EventSink>,
+  semaphore: Arc<Semaphore>,
+  timeout: Option<Duration>,
+  running: Mutex<JoinSet<Option<Failure>>>
+}
+
+impl Runner {
+  /// Creates a runner for the hooks in `config`, given the URL and target
+  /// path of each task by index.
+  pub(crate) fn new(
+    config: &Config,
+    targets: Vec<(String, PathBuf)>,
+    sink: Arc<dyn EventSink>
+  ) -> Self {
+    Self {
+      hooks: config.hooks.clone().into(),
+      targets,
+      sink,
+      semaphore: Arc::new(Semaphore::new(config.hook_concurrency.max(1))),
+      timeout: config.hook_timeout,
+      running: Mutex::new(JoinSet::new())
+    }
+  }
+
+  /// Waits for the running hooks, returning the failures ordered by file.
+  pub(crate) async fn finish(&self) -> Vec<Failure> {
+    let running = std::mem::take(&mut *self.running.lock().unwrap());
+    let mut failures: Vec<Failure> =
+      running.join_all().await.into_iter().flatten().collect();
+    failures.sort_by_key(|failure| failure.index);
+    failures
+  }
+
+  /// Runs the hooks for a finished file in order, holding a slot of the
+  /// hook concurrency. A failing hook skips the hooks after it, as they
+  /// usually depend on its result.
+  fn spawn(&self, context: Context) {
+    let hooks = self.hooks.clone();
+    let sink = self.sink.clone();
+    let semaphore = self.semaphore.clone();
+    let timeout = self.timeout;
+
+    self.running.lock().unwrap().spawn(async move {
+      let _permit = semaphore.acquire_owned().await.ok()?;
+      for hook in hooks.iter().filter(|hook| hook.runs_on(context.trigger)) {
+        let result = match timeout {
+          Some(limit) => tokio::time::timeout(limit, hook.run(&context))
+            .await
+            .unwrap_or_else(|_| {
+              Err(format!("timed out after {:.1}s", limit.as_secs_f64()))
+            }),
+          None => hook.run(&context).await
+        };
+        let Err(error) = result else {
+          continue;
+        };
+
+        warn!(
+          "Hook {} failed for {}: {}",
+          hook.name,
+          context.path.display(),
+          error
+        );
+        sink
+          .on_event(DownloadEvent::Warning {
+            message: format!(
+              "Hook {} failed for {}: {error}",
+              hook.name,
+              context.path.display()
+            ),
+            context: Some(context.url.clone())
+          })
+          .await;
+
+        return Some(Failure {
+          index: context.index,
+          url: context.url,
+          path: context.path,
+          hook: hook.name.clone(),
+          error
+        });
+      }
+      None
+    });
+  }
+
+  /// Returns the file a completed or failed event is about.
+  fn context(&self, event: &DownloadEvent) -> Option<Context> {
+    let (index, trigger) = match event {
+      DownloadEvent::FileCompleted { index, .. } =>
+        (*index, Trigger::Completed),
+      DownloadEvent::FileFailed { index, .. } => (*index, Trigger::Failed),
+      _ => return None
+    };
+    let (url, path) = self.targets.get(index)?;
+
+    let mut context = Context {
+      index,
+      url: url.clone(),
+      path: path.clone(),
+      trigger,
+      bytes_downloaded: None,
+      duration: None,
+      error: None
+    };
+    match event {
+      DownloadEvent::FileCompleted {
+        bytes_downloaded,
+        duration,
+        ..
+      } => {
+        context.bytes_downloaded = Some(*bytes_downloaded);
+        context.duration = Some(*duration);
+      }
+      DownloadEvent::FileFailed { error, .. } => {
+        context.error = Some(error.clone());
+      }
+      _ => {}
+    }
+    Some(context)
+  }
+}
+
+#[async_trait]
+impl EventSink for Runner {
+  async fn on_event(&self, event: DownloadEvent) {
+    let context = self.context(&event);
+    self.sink.on_event(event).await;
+
+    if let Some(context) = context
+      && self.hooks.iter().any(|hook| hook.runs_on(context.trigger))
+    {
+      self.spawn(context);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn test_hooks_run_on_finished_files() {
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert("https://example.com/a.tsv", Fixture::new("a\tb\n"));
+    transport.insert("https://example.com/b.tsv", Fixture::new("c\td\n"));
+    transport.insert(
+      "https://example.com/missing.tsv",
+      Fixture::new("").status(404)
+    );
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let config = Config::builder()
+      .max_retries(0)
+      .hook(
+        Hook::closure(move |context: Context| {
+          let recorded = recorded.clone();
+          async move {
+            let contents = tokio::fs::read_to_string(&context.path)
+              .await
+              .unwrap_or_default();
+            recorded.lock().unwrap().push((context.trigger, contents));
+            Ok::<_, String>(())
+          }
+        })
+        .on(&[Trigger::Completed, Trigger::Failed])
+      )
+      .hook(
+        Hook::closure(|context: Context| async move {
+          match context.url.ends_with("b.tsv") {
+            true => Err(format!("cannot ingest {}", context.path.display())),
+            false => Ok(())
+          }
+        })
+        .named("ingest")
+      )
+      .hook_concurrency(2)
+      .build();
+
+    let target = TempDir::new().unwrap();
+    let sink = Arc::new(CollectingEventSink::new());
+    let mut downloader = Downloader::new_with_config(
+      vec![
+        "https://example.com/a.tsv",
+        "https://example.com/b.tsv",
+        "https://example.com/missing.tsv",
+      ],
+      target.path(),
+      config
+    )
+    .unwrap();
+    downloader
+      .with_transport(transport)
+      .with_event_sink(sink.clone());
+    let report = downloader.start().await.unwrap().await.unwrap();
+
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort_by(|a, b| a.1.cmp(&b.1));
+    assert_eq!(
+      seen,
+      vec![
+        (Trigger::Failed, String::new()),
+        (Trigger::Completed, "a\tb\n".to_string()),
+        (Trigger::Completed, "c\td\n".to_string()),
+      ]
+    );
+
+    assert_eq!(report.hook_failures.len(), 1);
+    let failure = &report.hook_failures[0];
+    assert_eq!(failure.hook, "ingest");
+    assert!(failure.url.ends_with("b.tsv"));
+    assert!(failure.error.starts_with("cannot ingest"));
+
+    // The warning is sent before the batch finishes
+    let events = sink.events();
+    let warning = events
+      .iter()
+      .position(|event| match event {
+        DownloadEvent::Warning { message, .. } => message.contains("ingest"),
+        _ => false
+      })
+      .unwrap();
+    let completed = events
+      .iter()
+      .position(|event| {
+        matches!(event, DownloadEvent::DownloadCompleted { .. })
+      })
+      .unwrap();
+    assert!(warning < completed);
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn test_command_hook_gets_environment() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("data.tsv");
+    std::fs::write(&path, "a\tb\n").unwrap();
+    let context = Context {
+      index: 3,
+      url: "https://example.com/data.tsv".to_string(),
+      path: path.clone(),
+      trigger: Trigger::Completed,
+      bytes_downloaded: Some(4),
+      duration: Some(Duration::from_millis(1500)),
+      error: None
+    };
+
+    let script = concat!(
+      r#"echo "$DOWNLOAD_INDEX $DOWNLOAD_STATUS" "#,
+      r#""$DOWNLOAD_BYTES $DOWNLOAD_DURATION" > "$1.meta""#
+    );
+    let hook = Hook::command("sh")
+      .arg("-c")
+      .arg(script)
+      .arg("hook")
+      .arg_path();
+    hook.run(&context).await.unwrap();
+    assert_eq!(
+      std::fs::read_to_string(dir.path().join("data.tsv.meta")).unwrap(),
+      "3 completed 4 1.500\n"
+    );
+
+    let hook = Hook::command("sh").args(["-c", "echo broken >&2; exit 3"]);
+    let error = hook.run(&context).await.unwrap_err();
+    assert!(error.contains("3") && error.ends_with("(broken)"));
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn test_hook_timeout_reports_failure() {
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert("https://example.com/a.tsv", Fixture::new("a\tb\n"));
+
+    let config = Config::builder()
+      .hook(Hook::command("sleep").arg("30").named("stuck"))
+      .hook_timeout(Some(Duration::from_millis(200)))
+      .build();
+
+    let target = TempDir::new().unwrap();
+    let mut downloader = Downloader::new_with_config(
+      vec!["https://example.com/a.tsv"],
+      target.path(),
+      config
+    )
+    .unwrap();
+    downloader.with_transport(transport);
+
+    let start = std::time::Instant::now();
+    let report = downloader.start().await.unwrap().await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(report.is_success());
+    assert_eq!(report.hook_failures.len(), 1);
+    assert_eq!(report.hook_failures[0].hook, "stuck");
+    assert!(report.hook_failures[0].error.starts_with("timed out"));
+  }
+}
@@ -0,0 +1,488 @@
+//! Persistent journal of a batch of downloads
+//!
+//! With [`Config::journal`] enabled, the state transitions of the files of
+//! a batch are appended as JSON lines to a journal in the target directory:
+//! queued, started, bytes received, completed and failed. The records carry the
+//! URL together with the temp path and validators once known, so a batch
+//! interrupted by a crash can be continued with [`Downloader::from_journal`],
+//! which resumes the partial files. [`Journal::status`] reads the state of the
+//! last batch without taking part in it, e.g. from another process.
+
+use crate::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+  path::{Path, PathBuf},
+  sync::Arc
+};
+use tokio::{
+  fs::{File, OpenOptions},
+  io::AsyncWriteExt,
+  sync::Mutex
+};
+
+/// File name of the journal in the target directory.
+pub const JOURNAL_FILE: &str = ".download-journal.jsonl";
+
+/// A line of the journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+  /// Time the record was written
+  pub time: DateTime<Utc>,
+
+  /// Batch the record belongs to
+  pub batch: String,
+
+  /// URL of the file
+  pub url: String,
+
+  /// New state of the file
+  #[serde(flatten)]
+  pub state: State
+}
+
+/// State transition of a file recorded in the journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum State {
+  /// The file was added to the batch
+  Queued { target: PathBuf, temp_path: PathBuf },
+
+  /// The server responded; `offset` bytes are kept from an earlier attempt
+  Started { offset: u64, validators: Validators },
+
+  /// Bytes received so far
+  Bytes { downloaded: u64, total: Option<u64> },
+
+  /// The file is complete at its target path
+  Completed {
+    bytes_downloaded: u64,
+    validators: Validators
+  },
+
+  /// The download failed or was cancelled
+  Failed { error: String }
+}
+
+/// Appends the records of one batch to the journal of a target directory.
+#[derive(Debug, Clone)]
+pub struct Journal {
+  batch: String,
+  file: Arc<Mutex<File>>
+}
+
+impl Journal {
+  /// Returns the path of the journal in `target_dir`.
+  pub fn path<P: AsRef<Path>>(target_dir: P) -> PathBuf {
+    target_dir.as_ref().join(JOURNAL_FILE)
+  }
+
+  /// Opens the journal in `target_dir` for appending records of `batch`.
+  pub(crate) async fn open(target_dir: &Path, batch: String) -> Result<Self> {
+    let path = Self::path(target_dir);
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)
+      .await
+      .map_err(|e| Error::FileSystem {
+        message: format!("Failed to open journal '{}': {e}", path.display())
+      })?;
+
+    Ok(Self {
+      batch,
+      file: Arc::new(Mutex::new(file))
+    })
+  }
+
+  /// Returns the batch this journal records.
+  pub fn batch(&self) -> &str {
+    &self.batch
+  }
+
+  /// Appends a record, syncing it to disk once a file is finished.
+  ///
+  /// A journal that cannot be written only loses the ability to continue
+  /// the batch, so failures are logged instead of failing the download.
+  pub(crate) async fn record(&self, url: &str, state: State) {
+    let finished =
+      matches!(state, State::Completed { .. } | State::Failed { .. });
+    let record = Record {
+      time: Utc::now(),
+      batch: self.batch.clone(),
+      url: url.to_string(),
+      state
+    };
+
+    let mut line = match serde_json::to_string(&record) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!("Failed to serialize journal record: {}", e);
+        return;
+      }
+    };
+    line.push('\n');
+
+    let mut file = self.file.lock().await;
+    let written = async {
+      file.write_all(line.as_bytes()).await?;
+      file.flush().await?;
+      if finished {
+        file.sync_data().await?;
+      }
+      std::io::Result::Ok(())
+    };
+    if let Err(e) = written.await {
+      warn!("Failed to write download journal: {}", e);
+    }
+  }
+
+  /// Reads all records of the journal in `target_dir`.
+  ///
+  /// Lines that cannot be parsed, such as one cut off by a crash, are
+  /// skipped.
+  pub async fn read<P: AsRef<Path>>(target_dir: P) -> Result<Vec<Record>> {
+    let path = Self::path(target_dir);
+    let content = tokio::fs::read_to_string(&path).await.map_err(|e| {
+      Error::FileSystem {
+        message: format!("Failed to read journal '{}': {e}", path.display())
+      }
+    })?;
+
+    Ok(
+      content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+          Ok(record) => Some(record),
+          Err(e) => {
+            warn!("Skipping unreadable journal line: {}", e);
+            None
+          }
+        })
+        .collect()
+    )
+  }
+
+  /// Returns the status of the last batch recorded in the journal in
+  /// `target_dir`, or `None` if the journal holds no records.
+  pub async fn status<P: AsRef<Path>>(
+    target_dir: P
+  ) -> Result<Option<BatchStatus>> {
+    Ok(BatchStatus::from_records(Self::read(target_dir).await?))
+  }
+}
+
+/// Creates an identifier for a new batch.
+pub(crate) fn new_batch_id() -> String {
+  format!(
+    "{}-{}",
+    Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+    std::process::id()
+  )
+}
+
+/// State of a batch as recorded in its journal.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchStatus {
+  /// Identifier of the batch
+  pub batch: String,
+
+  /// Files of the batch in the order they were queued
+  pub files: Vec<FileStatus>
+}
+
+/// State of a file of a batch as recorded in its journal.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStatus {
+  /// URL of the file
+  pub url: String,
+
+  /// Target path of the file
+  pub target: Option<PathBuf>,
+
+  /// Temp path holding the partial download
+  pub temp_path: Option<PathBuf>,
+
+  /// Current state of the file
+  pub state: FileState,
+
+  /// Bytes received so far, including bytes kept from earlier attempts
+  pub bytes_downloaded: u64,
+
+  /// Full length of the file, if known
+  pub total_bytes: Option<u64>,
+
+  /// Validators of the response the file is downloaded from
+  pub validators: Option<Validators>,
+
+  /// Error of a failed download
+  pub error: Option<String>,
+
+  /// Time of the last record of the file
+  pub updated: DateTime<Utc>
+}
+
+/// Current state of a file of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+  /// Waiting to be downloaded
+  Queued,
+
+  /// Being downloaded
+  Downloading,
+
+  /// Downloaded to its target path
+  Completed,
+
+  /// Failed or cancelled
+  Failed
+}
+
+impl FileStatus {
+  /// Creates the status of a file first seen in `record`.
+  fn new(record: &Record) -> Self {
+    Self {
+      url: record.url.clone(),
+      target: None,
+      temp_path: None,
+      state: FileState::Queued,
+      bytes_downloaded: 0,
+      total_bytes: None,
+      validators: None,
+      error: None,
+      updated: record.time
+    }
+  }
+
+  /// Applies the state transition of a record.
+  fn apply(&mut self, record: Record) {
+    self.updated = record.time;
+    match record.state {
+      State::Queued { target, temp_path } => {
+        self.target = Some(target);
+        self.temp_path = Some(temp_path);
+        self.state = FileState::Queued;
+        self.error = None;
+      }
+      State::Started { offset, validators } => {
+        self.state = FileState::Downloading;
+        self.bytes_downloaded = offset;
+        self.total_bytes = validators.content_length;
+        self.validators = Some(validators);
+      }
+      State::Bytes { downloaded, total } => {
+        self.state = FileState::Downloading;
+        self.bytes_downloaded = downloaded;
+        self.total_bytes = total;
+      }
+      State::Completed {
+        bytes_downloaded,
+        validators
+      } => {
+        self.state = FileState::Completed;
+        self.bytes_downloaded = bytes_downloaded;
+        self.validators = Some(validators);
+        self.error = None;
+      }
+      State::Failed { error } => {
+        self.state = FileState::Failed;
+        self.error = Some(error);
+      }
+    }
+  }
+}
+
+impl BatchStatus {
+  /// Collects the status of the last batch among `records`.
+  fn from_records(records: Vec<Record>) -> Option<Self> {
+    let batch = records.last()?.batch.clone();
+    let mut files: Vec<FileStatus> = Vec::new();
+
+    for record in records.into_iter().filter(|r| r.batch == batch) {
+      match files.iter_mut().find(|file| file.url == record.url) {
+        Some(file) => file.apply(record),
+        None => {
+          let mut file = FileStatus::new(&record);
+          file.apply(record);
+          files.push(file);
+        }
+      }
+    }
+
+    Some(Self { batch, files })
+  }
+
+  /// Returns the files that are not completed.
+  pub fn unfinished(&self) -> impl Iterator<Item = &FileStatus> {
+    self
+      .files
+      .iter()
+      .filter(|file| file.state != FileState::Completed)
+  }
+
+  /// Returns the number of files in the given state.
+  pub fn count(&self, state: FileState) -> usize {
+    self.files.iter().filter(|file| file.state == state).count()
+  }
+
+  /// Returns true if every file of the batch is completed.
+  pub fn is_complete(&self) -> bool {
+    self.unfinished().next().is_none()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn test_status_of_last_batch() {
+    let dir = TempDir::new().unwrap();
+    let url = "https://example.com/a.tsv";
+    let old = Journal::open(dir.path(), "old".to_string()).await.unwrap();
+    old
+      .record(
+        url,
+        State::Failed {
+          error: "gone".to_string()
+        }
+      )
+      .await;
+
+    let journal = Journal::open(dir.path(), "new".to_string()).await.unwrap();
+    journal
+      .record(
+        url,
+        State::Queued {
+          target: dir.path().join("a.tsv"),
+          temp_path: dir.path().join("a.tsv.part")
+        }
+      )
+      .await;
+    journal
+      .record(
+        url,
+        State::Bytes {
+          downloaded: 10,
+          total: Some(20)
+        }
+      )
+      .await;
+    journal
+      .record(
+        "https://example.com/b.tsv",
+        State::Completed {
+          bytes_downloaded: 5,
+          validators: Validators::default()
+        }
+      )
+      .await;
+
+    // A line cut off by a crash is skipped
+    let mut content = tokio::fs::read_to_string(Journal::path(dir.path()))
+      .await
+      .unwrap();
+    content.push_str("{\"time\":\"2024-");
+    tokio::fs::write(Journal::path(dir.path()), content)
+      .await
+      .unwrap();
+
+    let status = Journal::status(dir.path()).await.unwrap().unwrap();
+    assert_eq!(status.batch, "new");
+    assert_eq!(status.files.len(), 2);
+    assert_eq!(status.files[0].state, FileState::Downloading);
+    assert_eq!(status.files[0].bytes_downloaded, 10);
+    assert_eq!(status.files[0].total_bytes, Some(20));
+    assert_eq!(status.count(FileState::Completed), 1);
+    assert_eq!(status.unfinished().count(), 1);
+    assert!(!status.is_complete());
+  }
+
+  #[tokio::test]
+  async fn test_continue_interrupted_batch() {
+    let transport = Arc::new(MemoryTransport::new());
+    let complete = "https://example.com/complete.csv";
+    let broken = "https://example.com/broken.csv";
+    transport.insert(complete, Fixture::new("a,b\n"));
+    transport.insert(
+      broken,
+      Fixture::new("0123456789")
+        .header(reqwest::header::ETAG, "\"v1\"")
+        .fault(Fault::Truncate(4))
+    );
+
+    let target = TempDir::new().unwrap();
+    let config = Config::builder().max_retries(0).journal(true).build();
+    let mut downloader = Downloader::new_with_config(
+      vec![complete, broken],
+      target.path(),
+      config.clone()
+    )
+    .unwrap();
+    downloader.with_transport(transport.clone());
+    let report = downloader.start().await.unwrap().await.unwrap();
+    assert_eq!(report.failed().count(), 1);
+
+    let status = Journal::status(target.path()).await.unwrap().unwrap();
+    assert_eq!(status.count(FileState::Failed), 1);
+    assert_eq!(status.unfinished().next().unwrap().url, broken);
+
+    let mut downloader = Downloader::from_journal(target.path(), config)
+      .await
+      .unwrap();
+    assert_eq!(downloader.urls(), [broken]);
+    downloader.with_transport(transport);
+    let report = downloader.start().await.unwrap().await.unwrap();
+
+    let result = report.downloaded().next().unwrap().result().unwrap();
+    assert_eq!(result.resumed_bytes, 4);
+    assert_eq!(std::fs::read(&result.path).unwrap(), b"0123456789");
+
+    let resumed = Journal::status(target.path()).await.unwrap().unwrap();
+    assert_eq!(resumed.batch, status.batch);
+    assert!(resumed.is_complete());
+  }
+
+  #[tokio::test]
+  async fn test_continue_manifest_batch() {
+    let transport = Arc::new(MemoryTransport::new());
+    let url = "https://example.com/data?id=genes";
+    transport.insert(
+      url,
+      Fixture::new("0123456789")
+        .header(reqwest::header::ETAG, "\"v1\"")
+        .fault(Fault::Truncate(4))
+    );
+
+    let target = TempDir::new().unwrap();
+    let config = Config::builder().max_retries(0).journal(true).build();
+    let mut downloader = Downloader::new_with_config(
+      Vec::<&str>::new(),
+      target.path(),
+      config.clone()
+    )
+    .unwrap();
+    downloader.with_transport(transport.clone()).with_entry(
+      ManifestEntry::new(url)
+        .subdir("reference")
+        .filename("genes.tsv")
+    );
+    let report = downloader.start().await.unwrap().await.unwrap();
+    assert_eq!(report.failed().count(), 1);
+
+    // The partial file is found under the name the manifest gave it
+    let mut downloader = Downloader::from_journal(target.path(), config)
+      .await
+      .unwrap();
+    downloader.with_transport(transport);
+    let report = downloader.start().await.unwrap().await.unwrap();
+
+    let result = report.downloaded().next().unwrap().result().unwrap();
+    assert_eq!(result.path, target.path().join("reference/genes.tsv"));
+    assert_eq!(result.resumed_bytes, 4);
+    assert_eq!(std::fs::read(&result.path).unwrap(), b"0123456789");
+  }
+}
@@ -2,14 +2,34 @@
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // Internal modules
+mod audit;
+mod auth;
+#[cfg(feature = "progress-bars")]
+mod bars;
+mod cache;
+mod checksum;
+mod client;
 mod config;
+mod control;
 mod core;
+mod decompress;
+mod disk;
 mod error;
 mod events;
 mod filename;
+mod handle;
+mod hooks;
+mod journal;
+mod manifest;
+mod metadata;
 mod preview;
 mod progress;
+mod report;
+mod retry;
+mod segment;
 mod task;
+mod throttle;
+mod transport;
 mod utils;
 mod validation;
 
@@ -22,18 +42,58 @@ pub use tokio::sync::broadcast;
 extern crate tracing;
 
 // Re-export main types for convenience
+#[cfg(feature = "progress-bars")]
+pub use crate::bars::IndicatifEventSink;
 pub use crate::{
-  config::{Config, ConfigBuilder, OverwritePolicy},
+  audit::{JsonLinesEventSink, Record as EventRecord},
+  auth::{
+    BasicAuth, BearerToken, CredentialProvider, Credentials, EnvCredentials,
+    HostPattern, Netrc
+  },
+  cache::{
+    Cache, Entry as CacheEntry, Link as CacheLink,
+    Verification as CacheVerification
+  },
+  checksum::{Algorithm as ChecksumAlgorithm, Checksum},
+  client::build_client,
+  config::{ClientIdentity, Config, ConfigBuilder, OverwritePolicy},
+  control::Control,
   core::Downloader,
+  decompress::Decompression,
   error::{Error, ErrorKind, Result},
   events::{
     CollectingEventSink, CompositeEventSink, DownloadEvent, EventSink,
     LoggingEventSink
   },
   filename::{ConflictResolver, ConflictStrategy, Strategy},
-  preview::{Conflict, Manifest, Status, Target},
-  progress::{Reporter, Sender, Snapshot},
+  handle::DownloadHandle,
+  hooks::{
+    Context as HookContext, Failure as HookFailure, Hook,
+    Trigger as HookTrigger
+  },
+  journal::{
+    BatchStatus, FileState, FileStatus, JOURNAL_FILE, Journal,
+    Record as JournalRecord, State as JournalState
+  },
+  manifest::{
+    Entry as ManifestEntry, Format as ManifestFormat, Manifest as BatchManifest
+  },
+  metadata::Validators,
+  preview::{Conflict, Manifest, Probe, Status, Target},
+  progress::{
+    File as FileSnapshot, Phase as FilePhase, Reporter, Sender, Snapshot
+  },
+  report::{
+    BatchReport, Entry as ReportEntry, Failure as DownloadFailure,
+    Outcome as DownloadOutcome, SkipReason
+  },
+  retry::{ExponentialBackoff, RetryBudget, RetryContext, RetryPolicy},
   task::{DownloadTask, TaskExecutor, TaskResult},
+  throttle::Throttle,
+  transport::{
+    Body, Fault, FileTransport, Fixture, HttpTransport, MemoryTransport,
+    Request, Response, Transport, Transports
+  },
   utils::{download, download_with_config, format_filesize},
   validation::{Url, UrlValidator, UrlValidatorBuilder}
 };
@@ -46,6 +106,7 @@ mod prelude {
     config::Config,
     core::Downloader,
     filename::Strategy,
+    handle::DownloadHandle,
     preview::{Conflict, Manifest, Status, Target},
     progress::{Reporter, Sender, Snapshot},
     utils::{download, download_with_config},
@@ -0,0 +1,682 @@
+//! Batch manifests
+//!
+//! A manifest describes a batch of downloads in a file: TOML with a
+//! `[[files]]` table per entry, JSON with a `files` array of the same
+//! entries, or plain text with one URL per line. Besides the URL, an entry
+//! may set the target filename and subdirectory, the expected checksum, a
+//! size limit, extra request headers, a priority and mirror URLs. Entries
+//! are validated while parsing, so errors point at the line of the
+//! offending value.
+//!
+//! ```toml
+//! [[files]]
+//! url = "https://example.com/data/genes.tsv"
+//! subdir = "reference"
+//! checksum = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+//! max_size = 1048576
+//! priority = 10
+//! headers = { Authorization = "Bearer token" }
+//! mirrors = ["https://mirror.example.org/data/genes.tsv"]
+//! ```
+
+use crate::*;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer, de};
+use std::{
+  collections::BTreeMap,
+  path::{Component, Path, PathBuf}
+};
+
+/// Name reported for manifests parsed from memory.
+const INLINE: &str = "<manifest>";
+
+/// A batch of downloads described in a manifest file.
+///
+/// # Examples
+///
+/// ```rust
+/// use downloader::{BatchManifest, ManifestFormat};
+///
+/// let manifest = BatchManifest::parse(
+///   "# Reference data\nhttps://example.com/a.tsv\nhttps://example.com/b.tsv\n",
+///   ManifestFormat::Text
+/// )
+/// .unwrap();
+/// assert_eq!(manifest.files.len(), 2);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+  /// Files to download, each URL at most once
+  #[serde(default)]
+  pub files: Vec<Entry>
+}
+
+/// A file of a manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+  /// URL to download
+  #[serde(deserialize_with = "url")]
+  pub url: String,
+
+  /// Target filename instead of the one derived from the URL
+  #[serde(default, deserialize_with = "filename")]
+  pub filename: Option<String>,
+
+  /// Directory below the target directory to download to
+  #[serde(default, deserialize_with = "subdir")]
+  pub subdir: Option<PathBuf>,
+
+  /// Expected checksum as `algorithm:hex` or bare hex
+  #[serde(default, deserialize_with = "checksum")]
+  pub checksum: Option<Checksum>,
+
+  /// Maximum size in bytes, overriding the configured limit
+  #[serde(default)]
+  pub max_size: Option<u64>,
+
+  /// Headers sent in addition to the configured ones
+  #[serde(default, deserialize_with = "headers")]
+  pub headers: BTreeMap<String, String>,
+
+  /// Files with a higher priority are started first
+  #[serde(default)]
+  pub priority: i32,
+
+  /// Alternative URLs serving the same file
+  #[serde(default, deserialize_with = "mirrors")]
+  pub mirrors: Vec<String>
+}
+
+/// File format of a manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// `[[files]]` tables
+  Toml,
+
+  /// An object with a `files` array
+  Json,
+
+  /// One URL per line, with `#` comments
+  Text
+}
+
+impl Format {
+  /// Detects the format from the file extension, defaulting to plain text.
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+    match path
+      .as_ref()
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .map(str::to_ascii_lowercase)
+      .as_deref()
+    {
+      Some("toml") => Self::Toml,
+      Some("json") => Self::Json,
+      _ => Self::Text
+    }
+  }
+}
+
+impl Manifest {
+  /// Reads and validates the manifest at `path`, detecting the format from
+  /// the file extension.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::FileSystem` if the file cannot be read, and
+  /// `Error::Manifest` with the line of the first invalid value otherwise.
+  pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref();
+    let content =
+      tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| Error::FileSystem {
+          message: format!("Failed to read manifest '{}': {e}", path.display())
+        })?;
+
+    Self::parse(&content, Format::from_path(path)).map_err(|e| match e {
+      Error::Manifest { line, message, .. } => Error::Manifest {
+        path: path.display().to_string(),
+        line,
+        message
+      },
+      e => e
+    })
+  }
+
+  /// Parses and validates a manifest.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::Manifest` with the line of the first invalid value.
+  pub fn parse(content: &str, format: Format) -> Result<Self> {
+    // Entries with the line of their URL, to report repeated ones
+    let files = match format {
+      Format::Toml => Self::parse_toml(content)?,
+      Format::Json => Self::parse_json(content)?,
+      Format::Text => Self::parse_text(content)?
+    };
+
+    let mut unique: Vec<Entry> = Vec::with_capacity(files.len());
+    for (line, entry) in files {
+      if unique.iter().any(|e| e.url == entry.url) {
+        return Err(Error::manifest(
+          INLINE,
+          line,
+          format!("duplicate entry for '{}'", entry.url)
+        ));
+      }
+      unique.push(entry);
+    }
+
+    Ok(Self { files: unique })
+  }
+
+  /// Parses `[[files]]` tables.
+  fn parse_toml(content: &str) -> Result<Vec<(usize, Entry)>> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Spanned {
+      #[serde(default)]
+      files: Vec<toml::Spanned<Entry>>
+    }
+
+    let manifest: Spanned = toml::from_str(content).map_err(|e| {
+      let line = e.span().map_or(1, |span| line_at(content, span.start));
+      Error::manifest(INLINE, line, e.message().trim())
+    })?;
+
+    Ok(
+      manifest
+        .files
+        .into_iter()
+        .map(|entry| (line_at(content, entry.span().start), entry.into_inner()))
+        .collect()
+    )
+  }
+
+  /// Parses an object with a `files` array.
+  fn parse_json(content: &str) -> Result<Vec<(usize, Entry)>> {
+    let manifest: Self = serde_json::from_str(content).map_err(|e| {
+      let message = e.to_string();
+      let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+      Error::manifest(INLINE, e.line().max(1), message)
+    })?;
+
+    // serde_json keeps no positions, so entries are located by their `url`
+    // keys
+    let mut lines = content
+      .match_indices("\"url\"")
+      .filter(|(offset, key)| {
+        content[offset + key.len()..].trim_start().starts_with(':')
+      })
+      .map(|(offset, _)| line_at(content, offset));
+    Ok(
+      manifest
+        .files
+        .into_iter()
+        .map(|entry| (lines.next().unwrap_or(1), entry))
+        .collect()
+    )
+  }
+
+  /// Parses one URL per line, skipping blank lines and `#` comments.
+  fn parse_text(content: &str) -> Result<Vec<(usize, Entry)>> {
+    let mut files = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let url = parse_url(line)
+        .map_err(|message| Error::manifest(INLINE, index + 1, message))?;
+      files.push((index + 1, Entry::new(url)));
+    }
+
+    Ok(files)
+  }
+
+  /// Creates a downloader for the files of the manifest.
+  pub fn into_downloader<P: AsRef<Path>>(
+    self,
+    target_dir: P,
+    config: Config
+  ) -> Result<Downloader> {
+    let mut downloader =
+      Downloader::new_with_config(Vec::<String>::new(), target_dir, config)?;
+    for entry in self.files {
+      downloader.with_entry(entry);
+    }
+    Ok(downloader)
+  }
+}
+
+impl Entry {
+  /// Creates an entry downloading `url` with the configured settings.
+  pub fn new<S: Into<String>>(url: S) -> Self {
+    Self {
+      url: url.into(),
+      filename: None,
+      subdir: None,
+      checksum: None,
+      max_size: None,
+      headers: BTreeMap::new(),
+      priority: 0,
+      mirrors: Vec::new()
+    }
+  }
+
+  /// Sets the target filename.
+  pub fn filename<S: Into<String>>(mut self, filename: S) -> Self {
+    self.filename = Some(filename.into());
+    self
+  }
+
+  /// Sets the directory below the target directory.
+  pub fn subdir<P: Into<PathBuf>>(mut self, subdir: P) -> Self {
+    self.subdir = Some(subdir.into());
+    self
+  }
+
+  /// Sets the expected checksum.
+  pub fn checksum(mut self, checksum: Checksum) -> Self {
+    self.checksum = Some(checksum);
+    self
+  }
+
+  /// Sets the maximum size in bytes.
+  pub fn max_size(mut self, size: u64) -> Self {
+    self.max_size = Some(size);
+    self
+  }
+
+  /// Adds a request header.
+  pub fn header<K: Into<String>, V: Into<String>>(
+    mut self,
+    key: K,
+    value: V
+  ) -> Self {
+    self.headers.insert(key.into(), value.into());
+    self
+  }
+
+  /// Sets the priority.
+  pub fn priority(mut self, priority: i32) -> Self {
+    self.priority = priority;
+    self
+  }
+
+  /// Adds a mirror URL.
+  pub fn mirror<S: Into<String>>(mut self, url: S) -> Self {
+    self.mirrors.push(url.into());
+    self
+  }
+
+  /// Moves a validated URL to the target filename and subdirectory of the
+  /// entry.
+  pub(crate) fn apply_target(
+    &self,
+    url: &mut validation::Url,
+    target_dir: &Path
+  ) {
+    if self.filename.is_none() && self.subdir.is_none() {
+      return;
+    }
+
+    if let Some(filename) = &self.filename {
+      url.filename = filename.clone();
+    }
+    let dir = match &self.subdir {
+      Some(subdir) => target_dir.join(subdir),
+      None => target_dir.to_path_buf()
+    };
+    url.target_path = dir.join(&url.filename);
+    url.exists = url.target_path.exists();
+  }
+
+  /// Returns `config` with the size limit and headers of the entry.
+  pub(crate) fn config(&self, config: &Config) -> Config {
+    let mut config = config.clone();
+    if self.max_size.is_some() {
+      config.max_file_size = self.max_size;
+    }
+    config.custom_headers.extend(
+      self
+        .headers
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+    );
+    config
+  }
+
+  /// Returns the parsed mirror URLs, in the order to try them.
+  pub(crate) fn mirror_urls(&self) -> Vec<reqwest::Url> {
+    self
+      .mirrors
+      .iter()
+      .filter_map(|url| reqwest::Url::parse(url).ok())
+      .collect()
+  }
+}
+
+/// Returns the 1-based line of the byte `offset` in `content`.
+fn line_at(content: &str, offset: usize) -> usize {
+  let offset = offset.min(content.len());
+  content.as_bytes()[..offset]
+    .iter()
+    .filter(|&&byte| byte == b'\n')
+    .count()
+    + 1
+}
+
+/// Returns the message of a validation error without its prefix.
+fn message(error: Error) -> String {
+  match error {
+    Error::Validation { message } => message,
+    e => e.to_string()
+  }
+}
+
+fn parse_url(url: &str) -> std::result::Result<String, String> {
+  let url = url.trim();
+  reqwest::Url::parse(url)
+    .map(|_| url.to_string())
+    .map_err(|e| format!("invalid URL '{url}': {e}"))
+}
+
+fn url<'de, D: Deserializer<'de>>(
+  deserializer: D
+) -> std::result::Result<String, D::Error> {
+  parse_url(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+fn mirrors<'de, D: Deserializer<'de>>(
+  deserializer: D
+) -> std::result::Result<Vec<String>, D::Error> {
+  Vec::<String>::deserialize(deserializer)?
+    .iter()
+    .map(|url| parse_url(url).map_err(de::Error::custom))
+    .collect()
+}
+
+fn filename<'de, D: Deserializer<'de>>(
+  deserializer: D
+) -> std::result::Result<Option<String>, D::Error> {
+  let filename = String::deserialize(deserializer)?;
+  if filename.is_empty()
+    || filename == "."
+    || filename == ".."
+    || filename.contains(['/', '\\'])
+  {
+    return Err(de::Error::custom(format!(
+      "invalid filename '{filename}': expected a name without directories"
+    )));
+  }
+  Ok(Some(filename))
+}
+
+fn subdir<'de, D: Deserializer<'de>>(
+  deserializer: D
+) -> std::result::Result<Option<PathBuf>, D::Error> {
+  let subdir = PathBuf::from(String::deserialize(deserializer)?);
+  if subdir.as_os_str().is_empty()
+    || !subdir
+      .components()
+      .all(|component| matches!(component, Component::Normal(_)))
+  {
+    return Err(de::Error::custom(format!(
+      "invalid subdir '{}': expected a relative path inside the target \
+       directory",
+      subdir.display()
+    )));
+  }
+  Ok(Some(subdir))
+}
+
+fn checksum<'de, D: Deserializer<'de>>(
+  deserializer: D
+) -> std::result::Result<Option<Checksum>, D::Error> {
+  String::deserialize(deserializer)?
+    .parse()
+    .map(Some)
+    .map_err(|e| de::Error::custom(message(e)))
+}
+
+fn headers<'de, D: Deserializer<'de>>(
+  deserializer: D
+) -> std::result::Result<BTreeMap<String, String>, D::Error> {
+  let headers = BTreeMap::<String, String>::deserialize(deserializer)?;
+  for (key, value) in &headers {
+    if HeaderName::try_from(key).is_err() {
+      return Err(de::Error::custom(format!("invalid header name '{key}'")));
+    }
+    if HeaderValue::try_from(value).is_err() {
+      return Err(de::Error::custom(format!(
+        "invalid value for header '{key}'"
+      )));
+    }
+  }
+  Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+  use tempfile::TempDir;
+
+  const SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+  fn error_line(result: Result<Manifest>) -> (usize, String) {
+    match result {
+      Err(Error::Manifest { line, message, .. }) => (line, message),
+      other => panic!("expected a manifest error, got {other:?}")
+    }
+  }
+
+  #[test]
+  fn test_parse_formats() {
+    let toml = format!(
+      r#"
+[[files]]
+url = "https://example.com/a.tsv"
+subdir = "reference/genes"
+checksum = "sha256:{SHA256}"
+max_size = 1024
+priority = 5
+headers = {{ Accept = "text/plain" }}
+mirrors = ["https://mirror.example.org/a.tsv"]
+
+[[files]]
+url = "https://example.com/b.tsv"
+filename = "b-latest.tsv"
+"#
+    );
+    let json = format!(
+      r#"{{
+  "files": [
+    {{
+      "url": "https://example.com/a.tsv",
+      "subdir": "reference/genes",
+      "checksum": "sha256:{SHA256}",
+      "max_size": 1024,
+      "priority": 5,
+      "headers": {{ "Accept": "text/plain" }},
+      "mirrors": ["https://mirror.example.org/a.tsv"]
+    }},
+    {{ "url": "https://example.com/b.tsv", "filename": "b-latest.tsv" }}
+  ]
+}}"#
+    );
+
+    let expected = vec![
+      Entry::new("https://example.com/a.tsv")
+        .subdir("reference/genes")
+        .checksum(Checksum::sha256(SHA256).unwrap())
+        .max_size(1024)
+        .priority(5)
+        .header("Accept", "text/plain")
+        .mirror("https://mirror.example.org/a.tsv"),
+      Entry::new("https://example.com/b.tsv").filename("b-latest.tsv"),
+    ];
+    assert_eq!(
+      Manifest::parse(&toml, Format::Toml).unwrap().files,
+      expected
+    );
+    assert_eq!(
+      Manifest::parse(&json, Format::Json).unwrap().files,
+      expected
+    );
+
+    let text = "# Reference data\n\nhttps://example.com/a.tsv\n  \
+                https://example.com/b.tsv  \n";
+    let manifest = Manifest::parse(text, Format::Text).unwrap();
+    assert_eq!(
+      manifest.files,
+      vec![
+        Entry::new("https://example.com/a.tsv"),
+        Entry::new("https://example.com/b.tsv"),
+      ]
+    );
+
+    assert_eq!(Format::from_path("batch.TOML"), Format::Toml);
+    assert_eq!(Format::from_path("batch.json"), Format::Json);
+    assert_eq!(Format::from_path("urls.txt"), Format::Text);
+  }
+
+  #[test]
+  fn test_errors_report_line() {
+    let toml = r#"
+[[files]]
+url = "https://example.com/a.tsv"
+
+[[files]]
+url = "https://example.com/b.tsv"
+checksum = "sha256:abc"
+"#;
+    let (line, message) = error_line(Manifest::parse(toml, Format::Toml));
+    assert_eq!(line, 7);
+    assert!(message.contains("Invalid sha256 digest"), "{message}");
+
+    let json = r#"{
+  "files": [
+    { "url": "https://example.com/a.tsv" },
+    { "url": "https://example.com/b.tsv",
+      "subdir": "../outside" }
+  ]
+}"#;
+    let (line, message) = error_line(Manifest::parse(json, Format::Json));
+    assert_eq!(line, 5);
+    assert!(message.contains("invalid subdir"), "{message}");
+
+    let toml = "[[files]]\nurl = \"https://example.com/a.tsv\"\nsize = 3\n";
+    let (line, message) = error_line(Manifest::parse(toml, Format::Toml));
+    assert_eq!(line, 3);
+    assert!(message.contains("unknown field"), "{message}");
+
+    let text = "https://example.com/a.tsv\n# mirror\nnot a url\n";
+    let (line, _) = error_line(Manifest::parse(text, Format::Text));
+    assert_eq!(line, 3);
+
+    let text = "https://example.com/a.tsv\nhttps://example.com/a.tsv\n";
+    let (line, message) = error_line(Manifest::parse(text, Format::Text));
+    assert_eq!(line, 2);
+    assert!(message.contains("duplicate"), "{message}");
+
+    let toml = "[[files]]\nurl = \"https://example.com/a.tsv\"\n\n\
+                [[files]]\nurl = \"https://example.com/a.tsv\"\n";
+    let (line, message) = error_line(Manifest::parse(toml, Format::Toml));
+    assert_eq!(line, 4);
+    assert!(message.contains("duplicate"), "{message}");
+
+    let json = r#"{"files": [
+  {"url": "https://example.com/a.tsv"},
+  {"url": "https://example.com/b.tsv",
+   "mirrors": ["https://example.org/url"]},
+  {"priority": 1,
+   "url": "https://example.com/a.tsv"}
+]}"#;
+    let (line, message) = error_line(Manifest::parse(json, Format::Json));
+    assert_eq!(line, 6);
+    assert!(message.contains("duplicate"), "{message}");
+  }
+
+  #[tokio::test]
+  async fn test_load_reports_path() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("batch.toml");
+    tokio::fs::write(
+      &path,
+      "[[files]]\nurl = \"https://example.com/\"\nfilename = \"../a\"\n"
+    )
+    .await
+    .unwrap();
+
+    let error = Manifest::load(&path).await.unwrap_err();
+    assert!(matches!(error, Error::Manifest { line: 3, .. }));
+    assert!(error.to_string().contains("batch.toml"));
+    assert_eq!(error.category(), ErrorKind::Validation);
+  }
+
+  #[tokio::test]
+  async fn test_downloader_from_manifest() {
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert("https://example.com/a.tsv", Fixture::new("a\n"));
+    transport.insert("https://example.com/b.tsv", Fixture::new("b\n"));
+    transport.insert("https://example.com/c.tsv", Fixture::new("c\n"));
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("batch.toml");
+    let manifest = r#"
+[[files]]
+url = "https://example.com/a.tsv"
+
+[[files]]
+url = "https://example.com/b.tsv"
+subdir = "nested/dir"
+priority = 2
+headers = { X-Batch = "nightly" }
+
+[[files]]
+url = "https://example.com/c.tsv"
+filename = "c-renamed.tsv"
+priority = 1
+"#;
+    tokio::fs::write(&path, manifest).await.unwrap();
+
+    let target = dir.path().join("out");
+    let config = Config::builder().concurrency_limit(Some(1)).build();
+    let mut downloader = Downloader::from_manifest(&path, &target, config)
+      .await
+      .unwrap();
+    downloader.with_transport(transport.clone());
+
+    let report = downloader.start().await.unwrap().await.unwrap();
+    assert!(report.is_success());
+    assert_eq!(
+      std::fs::read_to_string(target.join("nested/dir/b.tsv")).unwrap(),
+      "b\n"
+    );
+    assert_eq!(
+      std::fs::read_to_string(target.join("c-renamed.tsv")).unwrap(),
+      "c\n"
+    );
+    assert!(target.join("a.tsv").exists());
+
+    let requests: Vec<_> = transport
+      .requests()
+      .into_iter()
+      .filter(|request| request.method == reqwest::Method::GET)
+      .collect();
+    let order: Vec<_> = requests.iter().map(|r| r.url.path()).collect();
+    assert_eq!(order, ["/b.tsv", "/c.tsv", "/a.tsv"]);
+    assert_eq!(requests[0].headers["x-batch"], "nightly");
+    assert!(!requests[1].headers.contains_key("x-batch"));
+  }
+}
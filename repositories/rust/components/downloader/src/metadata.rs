@@ -0,0 +1,268 @@
+//! Response validators and sidecar metadata files
+//!
+//! This module captures the cache validators a server sends with a response
+//! (`ETag`, `Last-Modified`, `Content-Length`) and persists them next to a
+//! file so that later requests can be made conditional on them.
+
+use crate::*;
+use reqwest::header::{
+  CONTENT_LENGTH, ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE,
+  IF_NONE_MATCH, LAST_MODIFIED
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  path::{Path, PathBuf},
+  time::SystemTime
+};
+
+/// Extension appended to a file's name to form its sidecar metadata path.
+pub const SIDECAR_EXTENSION: &str = "meta";
+
+/// Validators identifying a specific version of a remote resource.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+  /// Entity tag as sent by the server, including quotes and any `W/` prefix
+  pub etag: Option<String>,
+
+  /// Last-Modified date as sent by the server
+  pub last_modified: Option<String>,
+
+  /// Full length of the resource in bytes (if known)
+  pub content_length: Option<u64>
+}
+
+impl Validators {
+  /// Extracts validators from response headers.
+  pub fn from_headers(headers: &HeaderMap) -> Self {
+    let header = |name| {
+      headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+    };
+
+    Self {
+      etag: header(ETAG),
+      last_modified: header(LAST_MODIFIED),
+      content_length: header(CONTENT_LENGTH).and_then(|s| s.parse().ok())
+    }
+  }
+
+  /// Creates validators for a local file without a sidecar, using its
+  /// modification time as the Last-Modified date.
+  pub fn from_modified(modified: SystemTime) -> Self {
+    let modified = chrono::DateTime::<chrono::Utc>::from(modified);
+    Self {
+      last_modified: Some(
+        modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+      ),
+      ..Self::default()
+    }
+  }
+
+  /// Returns true if no validator was captured.
+  pub fn is_empty(&self) -> bool {
+    self.etag.is_none() && self.last_modified.is_none()
+  }
+
+  /// Returns the value to send in an `If-Range` header, if any.
+  ///
+  /// Weak entity tags cannot be used for range requests, so this falls back
+  /// to the Last-Modified date when the server only sent a weak tag.
+  pub fn if_range(&self) -> Option<&str> {
+    self
+      .etag
+      .as_deref()
+      .filter(|etag| !etag.starts_with("W/"))
+      .or(self.last_modified.as_deref())
+  }
+
+  /// Returns the `If-None-Match`/`If-Modified-Since` headers that make a
+  /// request conditional on the resource having changed.
+  pub fn conditional_headers(&self) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut insert = |name, value: &Option<String>| {
+      if let Some(value) = value
+        && let Ok(value) = HeaderValue::from_str(value)
+      {
+        headers.insert(name, value);
+      }
+    };
+
+    insert(IF_NONE_MATCH, &self.etag);
+    insert(IF_MODIFIED_SINCE, &self.last_modified);
+    headers
+  }
+
+  /// Compares these validators with those of another response.
+  ///
+  /// Returns `Some(true)` if both describe the same version of the resource,
+  /// `Some(false)` if it has changed, and `None` if they cannot be compared.
+  /// Entity tags are compared weakly, as for `If-None-Match`.
+  pub fn same_version(&self, other: &Validators) -> Option<bool> {
+    if let (Some(a), Some(b)) = (self.content_length, other.content_length)
+      && a != b
+    {
+      return Some(false);
+    }
+
+    let weak = |etag: &str| etag.trim_start_matches("W/").to_string();
+    match (&self.etag, &other.etag) {
+      (Some(a), Some(b)) => Some(weak(a) == weak(b)),
+      _ => match (&self.last_modified, &other.last_modified) {
+        (Some(a), Some(b)) => Some(a == b),
+        _ => None
+      }
+    }
+  }
+
+  /// Loads validators from the sidecar file belonging to `path`.
+  ///
+  /// Returns `None` if the sidecar is missing or unreadable.
+  pub async fn load(path: &Path) -> Option<Self> {
+    let sidecar = sidecar_path(path);
+    let contents = tokio::fs::read(&sidecar).await.ok()?;
+    match serde_json::from_slice(&contents) {
+      Ok(validators) => Some(validators),
+      Err(e) => {
+        debug!("Ignoring unreadable sidecar {}: {}", sidecar.display(), e);
+        None
+      }
+    }
+  }
+
+  /// Saves validators to the sidecar file belonging to `path`.
+  pub async fn save(&self, path: &Path) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let contents =
+      serde_json::to_vec_pretty(self).map_err(|e| Error::FileSystem {
+        message: format!("Failed to serialize metadata: {e}")
+      })?;
+
+    tokio::fs::write(&sidecar, contents)
+      .await
+      .map_err(|e| Error::FileSystem {
+        message: format!(
+          "Failed to write metadata to {}: {e}",
+          sidecar.display()
+        )
+      })
+  }
+
+  /// Removes the sidecar file belonging to `path`, if present.
+  pub async fn remove(path: &Path) {
+    let sidecar = sidecar_path(path);
+    if let Err(e) = tokio::fs::remove_file(&sidecar).await
+      && e.kind() != std::io::ErrorKind::NotFound
+    {
+      debug!("Failed to remove sidecar {}: {}", sidecar.display(), e);
+    }
+  }
+}
+
+/// Returns the sidecar metadata path for a file (`name.ext` ->
+/// `name.ext.meta`).
+pub fn sidecar_path(path: &Path) -> PathBuf {
+  let mut name = path.as_os_str().to_os_string();
+  name.push(".");
+  name.push(SIDECAR_EXTENSION);
+  PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::header::HeaderValue;
+
+  #[test]
+  fn test_from_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, HeaderValue::from_static("\"abc123\""));
+    headers.insert(
+      LAST_MODIFIED,
+      HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT")
+    );
+    headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1024"));
+
+    let validators = Validators::from_headers(&headers);
+    assert_eq!(validators.etag.as_deref(), Some("\"abc123\""));
+    assert_eq!(validators.content_length, Some(1024));
+    assert_eq!(validators.if_range(), Some("\"abc123\""));
+  }
+
+  #[test]
+  fn test_if_range_skips_weak_etag() {
+    let validators = Validators {
+      etag: Some("W/\"abc123\"".to_string()),
+      last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+      content_length: None
+    };
+    assert_eq!(validators.if_range(), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+
+    let weak_only = Validators {
+      last_modified: None,
+      ..validators
+    };
+    assert_eq!(weak_only.if_range(), None);
+  }
+
+  #[test]
+  fn test_conditional_headers() {
+    let validators = Validators::from_modified(SystemTime::UNIX_EPOCH);
+    let headers = validators.conditional_headers();
+    assert_eq!(
+      headers.get(IF_MODIFIED_SINCE).unwrap(),
+      "Thu, 01 Jan 1970 00:00:00 GMT"
+    );
+    assert!(headers.get(IF_NONE_MATCH).is_none());
+  }
+
+  #[test]
+  fn test_same_version() {
+    let local = Validators {
+      etag: Some("\"v1\"".to_string()),
+      last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+      content_length: Some(42)
+    };
+
+    let weak = Validators {
+      etag: Some("W/\"v1\"".to_string()),
+      ..local.clone()
+    };
+    assert_eq!(local.same_version(&weak), Some(true));
+
+    let resized = Validators {
+      content_length: Some(43),
+      ..local.clone()
+    };
+    assert_eq!(local.same_version(&resized), Some(false));
+
+    assert_eq!(local.same_version(&Validators::default()), None);
+  }
+
+  #[test]
+  fn test_sidecar_path() {
+    assert_eq!(
+      sidecar_path(Path::new("/tmp/title.basics.tsv.gz.part")),
+      PathBuf::from("/tmp/title.basics.tsv.gz.part.meta")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_sidecar_roundtrip() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("file.bin");
+    let validators = Validators {
+      etag: Some("\"v1\"".to_string()),
+      last_modified: None,
+      content_length: Some(42)
+    };
+
+    validators.save(&path).await.unwrap();
+    assert_eq!(Validators::load(&path).await, Some(validators));
+
+    Validators::remove(&path).await;
+    assert_eq!(Validators::load(&path).await, None);
+  }
+}
@@ -1,8 +1,16 @@
 use crate::*;
+use reqwest::{
+  Method,
+  header::{ACCEPT_RANGES, CONTENT_TYPE}
+};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+/// Number of HEAD requests sent at once without a concurrency limit
+pub(crate) const PROBE_CONCURRENCY: usize = 8;
+
 /// Preview information about planned downloads
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
   pub files: Vec<Target>,
   pub total_size: Option<u64>,
@@ -18,50 +26,293 @@ impl Manifest {
       "Size unknown".to_string()
     }
   }
+
+  /// Serializes the preview to pretty-printed JSON, e.g. for a dry run.
+  pub fn to_json(&self) -> Result<String> {
+    serde_json::to_string_pretty(self).map_err(|e| Error::Validation {
+      message: format!("Failed to serialize preview: {e}")
+    })
+  }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Target {
   pub url: String,
   pub filename: String,
   pub target_path: PathBuf,
+  /// Target path before conflict resolution renamed the file
+  pub renamed_from: Option<PathBuf>,
   pub estimated_size: Option<u64>,
-  pub status: Status
+  pub status: Status,
+  /// Response to the HEAD request, if metadata was fetched
+  pub probe: Option<Probe>,
+  /// Why the file cannot be downloaded, e.g. the URL validation error
+  pub reason: Option<String>
+}
+
+impl Target {
+  /// Creates an entry for a URL that failed validation, without a target.
+  pub(crate) fn invalid(url: &str, error: &Error) -> Self {
+    Self {
+      url: url.to_string(),
+      filename: String::new(),
+      target_path: PathBuf::new(),
+      renamed_from: None,
+      estimated_size: None,
+      status: Status::InvalidUrl,
+      probe: None,
+      reason: Some(error.to_string())
+    }
+  }
+}
+
+/// Response to the HEAD request sent for a file during preview.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probe {
+  /// HTTP status code
+  pub status_code: u16,
+  /// Media type of the file
+  pub content_type: Option<String>,
+  /// Whether the server accepts byte range requests
+  pub accept_ranges: bool,
+  /// ETag, Last-Modified and Content-Length of the file
+  pub validators: Validators
+}
+
+impl Probe {
+  /// Sends a HEAD request for `url`.
+  ///
+  /// # Errors
+  ///
+  /// Returns the transport error if no response was received.
+  pub(crate) async fn fetch(
+    transport: &dyn Transport,
+    config: &Config,
+    url: &reqwest::Url
+  ) -> Result<Self> {
+    let response = transport
+      .send(Request::with_config(Method::HEAD, url, config))
+      .await?;
+    Ok(Self::from_headers(response.status(), response.headers()))
+  }
+
+  /// Builds the probe from the status and headers of a HEAD response.
+  pub(crate) fn from_headers(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap
+  ) -> Self {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    Self {
+      status_code: status.as_u16(),
+      content_type: header(CONTENT_TYPE).map(str::to_string),
+      accept_ranges: header(ACCEPT_RANGES)
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("bytes")),
+      validators: Validators::from_headers(headers)
+    }
+  }
+
+  /// Returns true if the server answered with a success status.
+  pub fn is_success(&self) -> bool {
+    (200..300).contains(&self.status_code)
+  }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Status {
   Ready,
   Exists,
+  /// Exists and matches the remote resource according to its sidecar
+  UpToDate,
+  /// Exists but the remote resource has changed since it was downloaded
+  Stale,
   InvalidUrl,
   TooLarge,
-  AccessDenied
+  AccessDenied,
+  /// The server could not be reached or answered with an error
+  Unreachable,
+  /// The server asks for credentials that were missing or rejected
+  AuthRequired,
+  /// Not enough free space for the file after those before it
+  InsufficientSpace,
+  /// Files cannot be created in the target directory
+  PermissionDenied
 }
 
 impl Status {
   pub async fn new(
     max_file_size: Option<u64>,
     validated: &validation::Url,
-    size: Option<u64>
+    probe: Option<&Probe>
   ) -> Self {
+    if let Some(probe) = probe {
+      match probe.status_code {
+        401 | 407 => return Self::AuthRequired,
+        403 => return Self::AccessDenied,
+        // Servers without HEAD support may still serve the file
+        405 | 501 => {}
+        code if !(200..400).contains(&code) => return Self::Unreachable,
+        _ => {}
+      }
+    }
+
+    let remote = probe.filter(|p| p.is_success()).map(|p| &p.validators);
     if validated.exists {
-      return Self::Exists;
+      let local = Validators::load(&validated.target_path).await;
+      return match (local, remote) {
+        (Some(local), Some(remote)) => match local.same_version(remote) {
+          Some(true) => Self::UpToDate,
+          Some(false) => Self::Stale,
+          None => Self::Exists
+        },
+        _ => Self::Exists
+      };
     }
 
     if let Some(max_size) = max_file_size
-      && let Some(file_size) = size
+      && let Some(file_size) = remote.and_then(|v| v.content_length)
       && file_size > max_size
     {
       return Self::TooLarge;
     }
 
-    //TODO: Could add more status checks here (permissions, disk space, etc.)
     Self::Ready
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conflict {
   pub filename: String,
-  pub urls: Vec<String>
+  pub urls: Vec<String>,
+  /// Distinct paths the conflicting URLs are downloaded to, in URL order
+  pub targets: Vec<PathBuf>
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::header::{CONTENT_DISPOSITION, ETAG};
+  use std::sync::Arc;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn test_preview_checks_each_file() {
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      "https://example.com/ready.csv",
+      Fixture::new("a,b\n")
+        .header(CONTENT_TYPE, "text/csv")
+        .header(ETAG, "\"v1\"")
+    );
+    transport.insert(
+      "https://example.com/private.csv",
+      Fixture::new("").status(401)
+    );
+    transport.insert(
+      "https://example.com/forbidden.csv",
+      Fixture::new("").status(403)
+    );
+    transport
+      .insert("https://example.com/gone.csv", Fixture::new("").status(410));
+    transport.insert(
+      "https://example.com/no-head.csv",
+      Fixture::new("").status(405)
+    );
+    transport
+      .insert("https://example.com/huge.csv", Fixture::new(vec![0u8; 64]));
+
+    let target = TempDir::new().unwrap();
+    let mut downloader = Downloader::new(
+      vec![
+        "https://example.com/ready.csv",
+        "not a url",
+        "https://example.com/private.csv",
+        "https://example.com/forbidden.csv",
+        "https://example.com/gone.csv",
+        "https://example.com/no-head.csv",
+        "https://example.com/huge.csv",
+      ],
+      target.path()
+    )
+    .unwrap();
+    downloader
+      .with_transport(transport)
+      .with_max_file_size(Some(32));
+
+    let preview = downloader.preview().await.unwrap();
+    let statuses: Vec<_> = preview.files.iter().map(|f| f.status).collect();
+    assert_eq!(
+      statuses,
+      [
+        Status::Ready,
+        Status::InvalidUrl,
+        Status::AuthRequired,
+        Status::AccessDenied,
+        Status::Unreachable,
+        Status::Ready,
+        Status::TooLarge,
+      ]
+    );
+
+    let ready = &preview.files[0];
+    assert_eq!(ready.estimated_size, Some(4));
+    let probe = ready.probe.as_ref().unwrap();
+    assert_eq!(probe.status_code, 200);
+    assert_eq!(probe.content_type.as_deref(), Some("text/csv"));
+    assert!(probe.accept_ranges);
+    assert_eq!(probe.validators.etag.as_deref(), Some("\"v1\""));
+
+    let invalid = &preview.files[1];
+    assert_eq!(invalid.url, "not a url");
+    assert!(invalid.reason.is_some());
+    assert_eq!(preview.files[4].probe.as_ref().unwrap().status_code, 410);
+    // Files without a known size leave the total unknown
+    assert_eq!(preview.total_size, None);
+    assert!(preview.warnings.iter().any(|w| w == "1 URLs are invalid"));
+
+    let json = preview.to_json().unwrap();
+    assert!(json.contains("\"auth_required\""));
+    let parsed: Manifest = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.files.len(), 7);
+    assert_eq!(parsed.files[6].status, Status::TooLarge);
+  }
+
+  #[tokio::test]
+  async fn test_preview_reuses_filename_probe() {
+    let transport = Arc::new(MemoryTransport::new());
+    for id in 1..=3 {
+      transport.insert(
+        format!("https://example.com/export?id={id}"),
+        Fixture::new("a,b\n").header(
+          CONTENT_DISPOSITION,
+          format!("attachment; filename=\"report-{id}.csv\"")
+        )
+      );
+    }
+
+    let target = TempDir::new().unwrap();
+    let mut downloader = Downloader::new(
+      vec![
+        "https://example.com/export?id=1",
+        "https://example.com/export?id=2",
+        "https://example.com/export?id=3",
+      ],
+      target.path()
+    )
+    .unwrap();
+    downloader.with_transport(transport.clone());
+
+    let preview = downloader.preview().await.unwrap();
+    let filenames: Vec<_> =
+      preview.files.iter().map(|f| f.filename.as_str()).collect();
+    assert_eq!(filenames, ["report-1.csv", "report-2.csv", "report-3.csv"]);
+    assert!(preview.files.iter().all(|f| f.status == Status::Ready));
+    assert_eq!(preview.total_size, Some(12));
+
+    // One HEAD per file names it and serves as its probe
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests.iter().all(|r| r.method == Method::HEAD));
+  }
 }
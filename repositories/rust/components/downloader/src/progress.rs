@@ -4,30 +4,53 @@
 //! allowing users to monitor download progress, estimate completion times,
 //! and receive detailed statistics about ongoing downloads.
 
+use crate::Throttle;
 use std::{
-  sync::{
-    Arc,
-    atomic::{AtomicUsize, Ordering}
-  },
+  collections::BTreeMap,
+  sync::{Arc, Mutex},
   time::{Duration, Instant}
 };
 use tokio::sync::{broadcast, mpsc};
 
+/// Time constant of the moving average used for download speeds.
+///
+/// Speeds are plain averages until this much time has passed, and
+/// exponentially smoothed afterwards so a stalled download slows down the
+/// reported speed within a few seconds.
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone)]
 pub struct Reporter {
   state: Arc<State>,
-  tx: broadcast::Sender<Snapshot>
+  tx: broadcast::Sender<Snapshot>,
+  progress_tx: Sender
 }
 
 /// Internal state for tracking progress across all downloads.
 #[derive(Debug)]
 struct State {
   total_files: usize,
-  completed_files: AtomicUsize,
-  failed_files: AtomicUsize,
-  total_bytes: AtomicUsize,
-  downloaded_bytes: AtomicUsize,
-  start_time: Instant
+  start_time: Instant,
+  throttle: Throttle,
+  tracker: Mutex<Tracker>
+}
+
+/// Latest state of every file, as tasks report running totals rather than
+/// increments.
+#[derive(Debug)]
+struct Tracker {
+  files: BTreeMap<usize, (File, Meter)>,
+  meter: Meter
+}
+
+/// Smoothed transfer rate of a running byte count.
+#[derive(Debug, Clone)]
+struct Meter {
+  started: Instant,
+  last: Instant,
+  bytes: usize,
+  counted: usize,
+  rate: f64
 }
 
 /// A snapshot of current download progress.
@@ -54,11 +77,62 @@ pub struct Snapshot {
   /// Elapsed time since download started
   pub elapsed: Duration,
 
-  /// Current download speed in bytes per second
+  /// Current download speed in bytes per second, smoothed over the last few
+  /// seconds
   pub speed_bps: f64,
 
   /// Estimated time remaining (if calculable)
-  pub eta: Option<Duration>
+  pub eta: Option<Duration>,
+
+  /// Number of downloads currently held back by the bandwidth limit
+  pub throttled: usize,
+
+  /// Progress of each file, ordered by index
+  pub files: Vec<File>
+}
+
+/// Progress of a single file within a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+  /// Index of the file in the batch
+  pub index: usize,
+
+  /// Bytes downloaded by the current attempt
+  pub bytes: usize,
+
+  /// Total size of the file (if known)
+  pub total: Option<usize>,
+
+  /// Where the file is in its download
+  pub phase: Phase,
+
+  /// Current download speed of the file in bytes per second
+  pub speed_bps: f64,
+
+  /// Number of attempts started so far
+  pub attempts: usize,
+
+  /// Error message if the download failed
+  pub error: Option<String>
+}
+
+/// Stage of a single file download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+  /// Waiting for a download slot
+  Pending,
+
+  /// Receiving data
+  Downloading,
+
+  /// Waiting before the next attempt
+  Retrying,
+
+  /// Finished successfully
+  Completed,
+
+  /// Failed or was cancelled
+  Failed
 }
 
 /// Progress update for a single file download.
@@ -77,7 +151,10 @@ pub struct FileProgress {
   pub completed: bool,
 
   /// Error message if download failed
-  pub error: Option<String>
+  pub error: Option<String>,
+
+  /// Number of the attempt starting with this update, if any
+  pub attempt: Option<usize>
 }
 
 /// Sender for progress updates from individual download tasks.
@@ -89,52 +166,31 @@ pub struct Sender {
 impl Reporter {
   /// Creates a new progress reporter for the specified number of files.
   pub fn new(total_files: usize) -> Self {
+    Self::with_throttle(total_files, Throttle::unlimited())
+  }
+
+  /// Creates a progress reporter whose snapshots show when downloads are
+  /// held back by the given bandwidth limiter.
+  pub fn with_throttle(total_files: usize, throttle: Throttle) -> Self {
     let (tx, rx) = broadcast::channel(1024);
+    let state = Arc::new(State::new(total_files, throttle));
 
-    let state = Arc::new(State {
-      total_files,
-      completed_files: AtomicUsize::new(0),
-      failed_files: AtomicUsize::new(0),
-      total_bytes: AtomicUsize::new(0),
-      downloaded_bytes: AtomicUsize::new(0),
-      start_time: Instant::now()
-    });
+    // Start progress tracking task
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
 
     let reporter = Self {
       state: state.clone(),
-      tx: tx.clone()
+      tx: tx.clone(),
+      progress_tx: Sender { tx: progress_tx }
     };
 
-    // Start progress tracking task
-    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
-    let progress_sender = Sender { tx: progress_tx };
-
     tokio::spawn(async move {
       let mut last_update = Instant::now();
       let update_interval = Duration::from_millis(500);
 
       while let Some(file_progress) = progress_rx.recv().await {
         trace!("Received file progress: {:?}", file_progress);
-
-        // Update state based on file progress
-        if file_progress.completed {
-          if file_progress.error.is_some() {
-            state.failed_files.fetch_add(1, Ordering::Relaxed);
-          } else {
-            state.completed_files.fetch_add(1, Ordering::Relaxed);
-          }
-        }
-
-        // Update byte counts
-        if file_progress.bytes_downloaded > 0 {
-          state
-            .downloaded_bytes
-            .fetch_add(file_progress.bytes_downloaded, Ordering::Relaxed);
-        }
-
-        if let Some(total) = file_progress.total_bytes {
-          state.total_bytes.fetch_add(total, Ordering::Relaxed);
-        }
+        let done = state.update(file_progress, Instant::now());
 
         // Send snapshot if enough time has passed
         let now = Instant::now();
@@ -145,9 +201,7 @@ impl Reporter {
         }
 
         // Check if all downloads are complete
-        let completed = state.completed_files.load(Ordering::Relaxed);
-        let failed = state.failed_files.load(Ordering::Relaxed);
-        if completed + failed >= state.total_files {
+        if done >= state.total_files {
           // Send final snapshot
           let final_snapshot = Self::create_snapshot(&state);
           let _ = tx.send(final_snapshot);
@@ -164,24 +218,21 @@ impl Reporter {
   /// Creates a progress reporter that's already completed (for edge cases).
   pub fn completed() -> Self {
     let (tx, _) = broadcast::channel(1);
-    let state = Arc::new(State {
-      total_files: 0,
-      completed_files: AtomicUsize::new(0),
-      failed_files: AtomicUsize::new(0),
-      total_bytes: AtomicUsize::new(0),
-      downloaded_bytes: AtomicUsize::new(0),
-      start_time: Instant::now()
-    });
+    let state = Arc::new(State::new(0, Throttle::unlimited()));
+
+    // Nothing is tracked, so reports go nowhere
+    let (progress_tx, _) = mpsc::unbounded_channel();
 
-    Self { state, tx }
+    Self {
+      state,
+      tx,
+      progress_tx: Sender { tx: progress_tx }
+    }
   }
 
   /// Returns a sender for reporting progress from download tasks.
   pub fn sender(&self) -> Sender {
-    // This would need to be connected to the internal progress tracking
-    // For now, return a dummy sender
-    let (tx, _) = mpsc::unbounded_channel();
-    Sender { tx }
+    self.progress_tx.clone()
   }
 
   /// Subscribes to progress updates.
@@ -199,23 +250,37 @@ impl Reporter {
 
   /// Creates a progress snapshot from the current state.
   fn create_snapshot(state: &State) -> Snapshot {
-    let completed = state.completed_files.load(Ordering::Relaxed);
-    let failed = state.failed_files.load(Ordering::Relaxed);
-    let total_bytes_val = state.total_bytes.load(Ordering::Relaxed);
-    let downloaded_bytes = state.downloaded_bytes.load(Ordering::Relaxed);
-    let elapsed = state.start_time.elapsed();
-
-    let total_bytes = if total_bytes_val > 0 {
-      Some(total_bytes_val)
-    } else {
-      None
-    };
+    let now = Instant::now();
+    let mut tracker = state.tracker.lock().unwrap();
+    let mut completed = 0;
+    let mut failed = 0;
+    let mut total_bytes = 0;
+    let mut downloaded_bytes = 0;
+    let mut files = Vec::with_capacity(tracker.files.len());
+
+    for (file, meter) in tracker.files.values_mut() {
+      match file.phase {
+        Phase::Completed => completed += 1,
+        Phase::Failed => failed += 1,
+        Phase::Downloading => {
+          // Let the speed of a stalled download decay
+          meter.sample(now, file.bytes);
+          file.speed_bps = meter.rate;
+        }
+        Phase::Pending | Phase::Retrying => {}
+      }
+      downloaded_bytes += file.bytes;
+      total_bytes += file.total.unwrap_or_default();
+      files.push(file.clone());
+    }
 
-    // Calculate download speed
-    let speed_bps = if elapsed.as_secs_f64() > 0.0 {
-      downloaded_bytes as f64 / elapsed.as_secs_f64()
+    tracker.meter.sample(now, downloaded_bytes);
+    let speed_bps = tracker.meter.rate;
+
+    let total_bytes = if total_bytes > 0 {
+      Some(total_bytes)
     } else {
-      0.0
+      None
     };
 
     // Calculate ETA
@@ -237,13 +302,168 @@ impl Reporter {
       failed,
       total_bytes,
       downloaded_bytes,
-      elapsed,
+      elapsed: state.start_time.elapsed(),
       speed_bps,
-      eta
+      eta,
+      throttled: state.throttle.throttled(),
+      files
+    }
+  }
+}
+
+impl State {
+  fn new(total_files: usize, throttle: Throttle) -> Self {
+    let start_time = Instant::now();
+    let files = (0..total_files)
+      .map(|index| (index, (File::new(index), Meter::new(start_time))))
+      .collect();
+
+    Self {
+      total_files,
+      start_time,
+      throttle,
+      tracker: Mutex::new(Tracker {
+        files,
+        meter: Meter::new(start_time)
+      })
+    }
+  }
+
+  /// Applies a report to the file it is about, returning the number of
+  /// files that finished.
+  fn update(&self, progress: FileProgress, now: Instant) -> usize {
+    let mut tracker = self.tracker.lock().unwrap();
+    let (file, meter) = tracker
+      .files
+      .entry(progress.file_index)
+      .or_insert_with(|| (File::new(progress.file_index), Meter::new(now)));
+
+    if let Some(attempt) = progress.attempt {
+      file.attempts = attempt;
+      file.speed_bps = 0.0;
+      *meter = Meter::new(now);
+      meter.bytes = file.bytes;
+      file.phase = if attempt > 1 {
+        Phase::Retrying
+      } else {
+        Phase::Downloading
+      };
+    }
+
+    if progress.completed {
+      if let Some(error) = progress.error {
+        file.phase = Phase::Failed;
+        file.error = Some(error);
+      } else {
+        file.phase = Phase::Completed;
+        file.total = file.total.or(Some(progress.bytes_downloaded));
+      }
+      file.speed_bps = 0.0;
+    } else if progress.attempt.is_none() {
+      if file.phase != Phase::Downloading {
+        file.phase = Phase::Downloading;
+        file.attempts = file.attempts.max(1);
+      }
+      meter.sample(now, progress.bytes_downloaded);
+      file.speed_bps = meter.rate;
+    }
+
+    // Failures report no bytes, keeping what the file got before
+    if progress.bytes_downloaded > 0 {
+      file.bytes = progress.bytes_downloaded;
+    }
+    if let Some(total) = progress.total_bytes {
+      file.total = Some(total);
+    }
+
+    let downloaded = tracker.files.values().map(|(file, _)| file.bytes).sum();
+    tracker.meter.sample(now, downloaded);
+
+    tracker
+      .files
+      .values()
+      .filter(|(file, _)| file.is_finished())
+      .count()
+  }
+}
+
+impl Meter {
+  fn new(now: Instant) -> Self {
+    Self {
+      started: now,
+      last: now,
+      bytes: 0,
+      counted: 0,
+      rate: 0.0
+    }
+  }
+
+  /// Updates the rate with the running byte count at `now`.
+  ///
+  /// A count lower than the previous one means the download restarted, so
+  /// it only moves the baseline.
+  fn sample(&mut self, now: Instant, bytes: usize) {
+    let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+    if elapsed <= 0.0 {
+      // Keep the bytes for the next sample with a measurable interval
+      self.bytes = self.bytes.min(bytes);
+      return;
+    }
+
+    let delta = bytes.saturating_sub(self.bytes);
+    self.counted += delta;
+    self.bytes = bytes;
+    self.last = now;
+
+    let age = now.saturating_duration_since(self.started);
+    if age < SPEED_WINDOW {
+      self.rate = self.counted as f64 / age.as_secs_f64();
+    } else {
+      let weight = 1.0 - (-elapsed / SPEED_WINDOW.as_secs_f64()).exp();
+      self.rate += weight * (delta as f64 / elapsed - self.rate);
     }
   }
 }
 
+impl File {
+  fn new(index: usize) -> Self {
+    Self {
+      index,
+      bytes: 0,
+      total: None,
+      phase: Phase::Pending,
+      speed_bps: 0.0,
+      attempts: 0,
+      error: None
+    }
+  }
+
+  /// Returns true if the file completed or failed.
+  pub fn is_finished(&self) -> bool {
+    matches!(self.phase, Phase::Completed | Phase::Failed)
+  }
+
+  /// Calculates the byte completion percentage (0-100) if the size is known.
+  pub fn percentage(&self) -> Option<f64> {
+    self.total.map(|total| {
+      if total == 0 {
+        100.0
+      } else {
+        (self.bytes as f64 / total as f64) * 100.0
+      }
+    })
+  }
+
+  /// Estimates the time until the file finishes at its current speed.
+  pub fn eta(&self) -> Option<Duration> {
+    let remaining = self.total?.checked_sub(self.bytes)?;
+    if self.phase != Phase::Downloading || self.speed_bps <= 0.0 {
+      return None;
+    }
+    Some(Duration::from_secs_f64(remaining as f64 / self.speed_bps))
+  }
+}
+
 impl Snapshot {
   /// Calculates the completion percentage (0-100).
   pub fn percentage(&self) -> f64 {
@@ -271,6 +491,11 @@ impl Snapshot {
     self.completed + self.failed >= self.total
   }
 
+  /// Returns true if any download is held back by the bandwidth limit.
+  pub fn is_throttled(&self) -> bool {
+    self.throttled > 0
+  }
+
   /// Returns true if all downloads completed successfully.
   pub fn is_successful(&self) -> bool {
     self.completed == self.total && self.failed == 0
@@ -307,7 +532,11 @@ impl Snapshot {
         format_bytes(self.downloaded_bytes as u64),
         format_bytes(total_bytes as u64)
       );
-      let speed = self.speed_human();
+      let speed = if self.is_throttled() {
+        format!("{} (throttled)", self.speed_human())
+      } else {
+        self.speed_human()
+      };
 
       if let Some(eta) = self.eta_human() {
         format!(
@@ -337,7 +566,8 @@ impl Sender {
       bytes_downloaded,
       total_bytes: None,
       completed: true,
-      error: None
+      error: None,
+      attempt: None
     });
   }
 
@@ -348,7 +578,8 @@ impl Sender {
       bytes_downloaded: 0,
       total_bytes: None,
       completed: true,
-      error: Some(error)
+      error: Some(error),
+      attempt: None
     });
   }
 
@@ -364,7 +595,25 @@ impl Sender {
       bytes_downloaded,
       total_bytes,
       completed: false,
-      error: None
+      error: None,
+      attempt: None
+    });
+  }
+
+  /// Reports that a file download started its first attempt.
+  pub fn started(&self, file_index: usize) {
+    self.retrying(file_index, 1);
+  }
+
+  /// Reports that a file download is about to make another attempt.
+  pub fn retrying(&self, file_index: usize, attempt: usize) {
+    self.report(FileProgress {
+      file_index,
+      bytes_downloaded: 0,
+      total_bytes: None,
+      completed: false,
+      error: None,
+      attempt: Some(attempt)
     });
   }
 }
@@ -463,7 +712,9 @@ mod tests {
       downloaded_bytes: 300,
       elapsed: Duration::from_secs(10),
       speed_bps: 30.0,
-      eta: Some(Duration::from_secs(23))
+      eta: Some(Duration::from_secs(23)),
+      throttled: 0,
+      files: Vec::new()
     };
 
     assert_eq!(snapshot.percentage(), 30.0);
@@ -487,4 +738,88 @@ mod tests {
     let snapshot = rx.recv().await.unwrap();
     assert!(snapshot.completed > 0 || snapshot.failed > 0);
   }
+
+  #[tokio::test]
+  async fn test_progress_counts_running_totals() {
+    let reporter = Reporter::new(2);
+    let mut rx = reporter.subscribe();
+    let sender = reporter.sender();
+
+    // Tasks report the bytes downloaded so far, not increments
+    sender.progress(0, 100, Some(300));
+    sender.progress(0, 200, Some(300));
+    sender.progress(1, 50, Some(100));
+    sender.completed(0, 300);
+    sender.completed(1, 100);
+
+    let snapshot = rx.recv().await.unwrap();
+    assert_eq!(snapshot.downloaded_bytes, 400);
+    assert_eq!(snapshot.total_bytes, Some(400));
+    assert!(snapshot.is_successful());
+  }
+
+  #[test]
+  fn test_meter_smooths_speed() {
+    let start = Instant::now();
+    let mut meter = Meter::new(start);
+
+    // 1000 bytes per second for twice the averaging window
+    for tick in 1..=100 {
+      meter.sample(
+        start + Duration::from_millis(tick * 100),
+        tick as usize * 100
+      );
+    }
+    assert!((meter.rate - 1000.0).abs() < 1.0);
+
+    // A burst moves the speed only part of the way
+    let mut now = start + Duration::from_secs(10);
+    now += Duration::from_millis(100);
+    meter.sample(now, 10_000 + 1_000);
+    assert!(meter.rate > 1000.0 && meter.rate < 2000.0);
+
+    // A stall lets it decay, and a restart is not counted as progress
+    now += Duration::from_secs(10);
+    meter.sample(now, 11_000);
+    assert!(meter.rate < 200.0);
+    now += Duration::from_millis(100);
+    meter.sample(now, 0);
+    assert!(meter.rate < 200.0);
+  }
+
+  #[tokio::test]
+  async fn test_progress_tracks_each_file() {
+    let reporter = Reporter::new(3);
+    let sender = reporter.sender();
+
+    sender.started(0);
+    sender.progress(0, 400, Some(1000));
+    sender.started(1);
+    sender.progress(1, 300, Some(500));
+    sender.retrying(1, 2);
+    sender.progress(1, 100, Some(500));
+    sender.completed(0, 1000);
+
+    // Let the tracking task drain the channel
+    while reporter.current_snapshot().completed == 0 {
+      tokio::task::yield_now().await;
+    }
+    let snapshot = reporter.current_snapshot();
+
+    // The restarted file only counts the bytes of its current attempt
+    assert_eq!(snapshot.downloaded_bytes, 1100);
+    assert_eq!(snapshot.total_bytes, Some(1500));
+
+    let files = &snapshot.files;
+    assert_eq!(files.len(), 3);
+    assert_eq!(files[0].phase, Phase::Completed);
+    assert_eq!(files[0].percentage(), Some(100.0));
+    assert_eq!(files[0].attempts, 1);
+    assert_eq!(files[1].phase, Phase::Downloading);
+    assert_eq!(files[1].attempts, 2);
+    assert_eq!(files[1].bytes, 100);
+    assert_eq!(files[2].phase, Phase::Pending);
+    assert_eq!(files[2].attempts, 0);
+    assert!(!snapshot.is_complete());
+  }
 }
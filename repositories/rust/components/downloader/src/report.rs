@@ -0,0 +1,316 @@
+//! Structured results of a batch of downloads
+//!
+//! A [`BatchReport`] has one [`Entry`] per URL telling whether the file was
+//! skipped, downloaded or failed. Failed entries keep the typed error along
+//! with the number of attempts, and the report can rebuild a [`Downloader`]
+//! that retries just those URLs.
+
+use crate::*;
+use serde::{Serialize, Serializer};
+use std::{fmt, path::PathBuf, time::Duration};
+
+/// Outcome of every URL of a batch.
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+  /// One entry per URL; downloads in task order, then skipped files
+  pub entries: Vec<Entry>,
+
+  /// Time from the start of the batch until every download finished
+  #[serde(serialize_with = "as_secs")]
+  pub elapsed: Duration,
+
+  /// Hooks that failed on downloaded files; these do not fail the batch
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub hook_failures: Vec<HookFailure>,
+
+  /// Downloader settings for retrying failed entries
+  #[serde(skip)]
+  retry: Option<Downloader>
+}
+
+/// Outcome of a single URL.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+  /// URL as given to the downloader
+  pub url: String,
+
+  /// Target path of the file
+  pub target: PathBuf,
+
+  /// What happened to the file
+  #[serde(flatten)]
+  pub outcome: Outcome
+}
+
+/// What happened to a file of the batch.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+  /// The file was not downloaded
+  Skipped { reason: SkipReason },
+
+  /// The file was downloaded, or found up to date
+  Downloaded(TaskResult),
+
+  /// The download failed
+  Failed(Failure)
+}
+
+/// Why a file was skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+  /// The target exists and the overwrite policy is `Skip`
+  Exists
+}
+
+/// A failed download.
+#[derive(Debug, Serialize)]
+pub struct Failure {
+  /// Error of the last attempt
+  #[serde(serialize_with = "as_string")]
+  pub error: Error,
+
+  /// Category of the error
+  pub kind: ErrorKind,
+
+  /// Number of attempts made (0 if the download never started)
+  pub attempts: usize,
+
+  /// Time spent on the download, including retry delays
+  #[serde(serialize_with = "as_secs")]
+  pub duration: Duration,
+
+  /// Steps that may resolve the error
+  pub suggested_actions: Vec<&'static str>
+}
+
+impl Failure {
+  /// Creates a failure, categorizing the error.
+  pub fn new(error: Error, attempts: usize, duration: Duration) -> Self {
+    Self {
+      kind: error.category(),
+      suggested_actions: error.suggested_actions(),
+      error,
+      attempts,
+      duration
+    }
+  }
+}
+
+impl fmt::Display for SkipReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SkipReason::Exists => write!(f, "file exists")
+    }
+  }
+}
+
+impl Entry {
+  /// Returns the download result, if the file was downloaded.
+  pub fn result(&self) -> Option<&TaskResult> {
+    match &self.outcome {
+      Outcome::Downloaded(result) => Some(result),
+      _ => None
+    }
+  }
+
+  /// Returns the failure, if the download failed.
+  pub fn failure(&self) -> Option<&Failure> {
+    match &self.outcome {
+      Outcome::Failed(failure) => Some(failure),
+      _ => None
+    }
+  }
+
+  /// Returns true if the file was skipped.
+  pub fn is_skipped(&self) -> bool {
+    matches!(self.outcome, Outcome::Skipped { .. })
+  }
+}
+
+impl BatchReport {
+  /// Creates a report from its entries.
+  pub(crate) fn new(
+    entries: Vec<Entry>,
+    elapsed: Duration,
+    retry: Option<Downloader>
+  ) -> Self {
+    Self {
+      entries,
+      elapsed,
+      hook_failures: Vec::new(),
+      retry
+    }
+  }
+
+  /// Adds the failures of hooks run on the downloaded files.
+  pub(crate) fn with_hook_failures(
+    mut self,
+    failures: Vec<HookFailure>
+  ) -> Self {
+    self.hook_failures = failures;
+    self
+  }
+
+  /// Returns the entries of downloaded files.
+  pub fn downloaded(&self) -> impl Iterator<Item = &Entry> {
+    self.entries.iter().filter(|entry| entry.result().is_some())
+  }
+
+  /// Returns the entries of failed downloads, including cancelled ones.
+  pub fn failed(&self) -> impl Iterator<Item = &Entry> {
+    self
+      .entries
+      .iter()
+      .filter(|entry| entry.failure().is_some())
+  }
+
+  /// Returns the entries of skipped files.
+  pub fn skipped(&self) -> impl Iterator<Item = &Entry> {
+    self.entries.iter().filter(|entry| entry.is_skipped())
+  }
+
+  /// Returns the number of downloads that were cancelled.
+  pub fn cancelled(&self) -> usize {
+    self
+      .failed()
+      .filter(|entry| {
+        matches!(
+          entry.failure(),
+          Some(Failure {
+            error: Error::Cancelled,
+            ..
+          })
+        )
+      })
+      .count()
+  }
+
+  /// Returns true if no download failed.
+  pub fn is_success(&self) -> bool {
+    self.failed().next().is_none()
+  }
+
+  /// Returns a downloader for retrying the failed entries with the settings
+  /// of the original batch, or `None` if nothing failed.
+  pub fn retry_failed(&self) -> Option<Downloader> {
+    let urls: Vec<String> =
+      self.failed().map(|entry| entry.url.clone()).collect();
+    if urls.is_empty() {
+      return None;
+    }
+
+    self
+      .retry
+      .as_ref()
+      .map(|downloader| downloader.with_urls(urls))
+  }
+
+  /// Serializes the report to pretty-printed JSON.
+  pub fn to_json(&self) -> Result<String> {
+    serde_json::to_string_pretty(self).map_err(|e| Error::Validation {
+      message: format!("Failed to serialize batch report: {e}")
+    })
+  }
+}
+
+/// Serializes a duration as fractional seconds.
+pub(crate) fn as_secs<S: Serializer>(
+  duration: &Duration,
+  serializer: S
+) -> std::result::Result<S::Ok, S::Error> {
+  serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Serializes a value by its `Display` output.
+fn as_string<T: fmt::Display, S: Serializer>(
+  value: &T,
+  serializer: S
+) -> std::result::Result<S::Ok, S::Error> {
+  serializer.collect_str(value)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn report() -> BatchReport {
+    let result = TaskResult {
+      index: 0,
+      path: PathBuf::from("downloads/a.txt"),
+      renamed_from: None,
+      bytes_downloaded: 10,
+      resumed_bytes: 0,
+      duration: Duration::from_millis(1500),
+      retry_count: 0,
+      final_speed: 6.7,
+      not_modified: false,
+      decompressed_bytes: None,
+      mirror: None,
+      cached: false
+    };
+    let error = Error::http_error(404, "https://example.com/b.txt", "missing");
+
+    BatchReport::new(
+      vec![
+        Entry {
+          url: "https://example.com/a.txt".to_string(),
+          target: PathBuf::from("downloads/a.txt"),
+          outcome: Outcome::Downloaded(result)
+        },
+        Entry {
+          url: "https://example.com/b.txt".to_string(),
+          target: PathBuf::from("downloads/b.txt"),
+          outcome: Outcome::Failed(Failure::new(
+            error,
+            3,
+            Duration::from_secs(2)
+          ))
+        },
+        Entry {
+          url: "https://example.com/c.txt".to_string(),
+          target: PathBuf::from("downloads/c.txt"),
+          outcome: Outcome::Skipped {
+            reason: SkipReason::Exists
+          }
+        },
+      ],
+      Duration::from_secs(3),
+      Some(Downloader::default())
+    )
+  }
+
+  #[test]
+  fn test_entries_by_outcome() {
+    let report = report();
+    assert_eq!(report.downloaded().count(), 1);
+    assert_eq!(report.skipped().count(), 1);
+    assert_eq!(report.cancelled(), 0);
+    assert!(!report.is_success());
+
+    let failure = report.failed().next().unwrap().failure().unwrap();
+    assert_eq!(failure.kind, ErrorKind::Http);
+    assert_eq!(failure.attempts, 3);
+  }
+
+  #[test]
+  fn test_report_to_json() {
+    let json: serde_json::Value =
+      serde_json::from_str(&report().to_json().unwrap()).unwrap();
+
+    assert_eq!(json["elapsed"], 3.0);
+    assert_eq!(json["entries"][0]["status"], "downloaded");
+    assert_eq!(json["entries"][0]["duration"], 1.5);
+    assert_eq!(json["entries"][1]["status"], "failed");
+    assert_eq!(json["entries"][1]["kind"], "Http");
+    assert_eq!(json["entries"][1]["attempts"], 3);
+    assert_eq!(json["entries"][2]["reason"], "exists");
+  }
+
+  #[test]
+  fn test_retry_failed() {
+    let downloader = report().retry_failed().unwrap();
+    assert_eq!(downloader.urls(), ["https://example.com/b.txt"]);
+  }
+}
@@ -0,0 +1,285 @@
+//! Retry policies for failed download attempts
+//!
+//! A [`RetryPolicy`] decides whether a failed attempt is retried and how long
+//! to wait first. The default [`ExponentialBackoff`] only retries recoverable
+//! errors, doubles the delay on each attempt up to a maximum, adds jitter so
+//! that concurrent downloads do not retry in lockstep, and honors the delay a
+//! server requests through `Retry-After`.
+
+use crate::*;
+use std::{
+  collections::hash_map::RandomState,
+  fmt::Debug,
+  hash::{BuildHasher, Hasher},
+  sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering}
+  },
+  time::Duration
+};
+
+/// Information about a failed attempt passed to a [`RetryPolicy`].
+#[derive(Debug)]
+pub struct RetryContext<'a> {
+  /// Error of the failed attempt
+  pub error: &'a Error,
+
+  /// Number of attempts made so far (1 after the first failure)
+  pub attempt: usize,
+
+  /// Maximum number of attempts allowed (`Config::max_retries`)
+  pub max_attempts: usize,
+
+  /// Base delay between attempts (`Config::retry_delay`)
+  pub base_delay: Duration
+}
+
+/// Decides whether and when a failed download attempt is retried.
+pub trait RetryPolicy: Send + Sync + Debug {
+  /// Returns the delay before the next attempt, or `None` to give up.
+  fn retry_delay(&self, context: &RetryContext<'_>) -> Option<Duration>;
+
+  /// Returns the maximum number of retries shared by all files of a batch
+  /// (None = unlimited).
+  fn batch_budget(&self) -> Option<usize> {
+    None
+  }
+}
+
+/// Exponential backoff with jitter for recoverable errors.
+///
+/// The delay before attempt `n + 1` is `base_delay * multiplier^(n - 1)`,
+/// capped at `max_delay` and scaled by a random factor in
+/// `1 - jitter..=1 + jitter`. Rate limited responses wait at least as long as
+/// the server asked for, even beyond `max_delay`.
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+  /// Factor applied to the delay after each attempt
+  pub multiplier: f64,
+
+  /// Upper bound of the computed delay
+  pub max_delay: Duration,
+
+  /// Fraction of the delay to randomize (0.0 = no jitter)
+  pub jitter: f64,
+
+  /// Maximum number of retries across a batch (None = unlimited)
+  pub budget: Option<usize>
+}
+
+impl Default for ExponentialBackoff {
+  fn default() -> Self {
+    Self {
+      multiplier: 2.0,
+      max_delay: Duration::from_secs(60),
+      jitter: 0.2,
+      budget: None
+    }
+  }
+}
+
+impl ExponentialBackoff {
+  /// Creates a backoff policy with default settings.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the factor applied to the delay after each attempt.
+  pub fn multiplier(mut self, multiplier: f64) -> Self {
+    self.multiplier = multiplier;
+    self
+  }
+
+  /// Sets the upper bound of the computed delay.
+  pub fn max_delay(mut self, max_delay: Duration) -> Self {
+    self.max_delay = max_delay;
+    self
+  }
+
+  /// Sets the fraction of the delay to randomize.
+  pub fn jitter(mut self, jitter: f64) -> Self {
+    self.jitter = jitter.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Sets the maximum number of retries across a batch.
+  pub fn budget(mut self, budget: usize) -> Self {
+    self.budget = Some(budget);
+    self
+  }
+
+  /// Returns the backoff delay before the attempt following `attempt`,
+  /// without jitter.
+  fn backoff(&self, base_delay: Duration, attempt: usize) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+    let delay = base_delay.as_secs_f64() * self.multiplier.powi(exponent);
+    Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+  }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+  fn retry_delay(&self, context: &RetryContext<'_>) -> Option<Duration> {
+    if context.attempt >= context.max_attempts
+      || !context.error.is_recoverable()
+    {
+      return None;
+    }
+
+    let backoff = self.backoff(context.base_delay, context.attempt);
+    let delay = backoff.mul_f64(1.0 + self.jitter * (2.0 * random() - 1.0));
+
+    match context.error {
+      Error::RateLimited { retry_after, .. } =>
+        Some(delay.max(Duration::from_secs(*retry_after))),
+      _ => Some(delay)
+    }
+  }
+
+  fn batch_budget(&self) -> Option<usize> {
+    self.budget
+  }
+}
+
+/// Retries remaining for one batch of downloads.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone, Default)]
+pub struct RetryBudget {
+  /// Remaining retries (None = unlimited)
+  remaining: Option<Arc<AtomicUsize>>
+}
+
+impl RetryBudget {
+  /// Creates a budget allowing `limit` retries (None = unlimited).
+  pub fn new(limit: Option<usize>) -> Self {
+    Self {
+      remaining: limit.map(|limit| Arc::new(AtomicUsize::new(limit)))
+    }
+  }
+
+  /// Creates a budget without limit.
+  pub fn unlimited() -> Self {
+    Self::default()
+  }
+
+  /// Takes one retry from the budget, returning false if none is left.
+  pub fn try_acquire(&self) -> bool {
+    match &self.remaining {
+      Some(remaining) => remaining
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+        .is_ok(),
+      None => true
+    }
+  }
+
+  /// Returns the number of retries left (None = unlimited).
+  pub fn remaining(&self) -> Option<usize> {
+    self
+      .remaining
+      .as_ref()
+      .map(|remaining| remaining.load(Ordering::Acquire))
+  }
+}
+
+/// Parses a `Retry-After` header value into seconds from now.
+///
+/// Accepts both delay seconds and an HTTP date; dates in the past yield 0.
+pub fn parse_retry_after(value: &str) -> Option<u64> {
+  let value = value.trim();
+  if let Ok(seconds) = value.parse() {
+    return Some(seconds);
+  }
+
+  let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+  let seconds = (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+    .num_seconds()
+    .max(0);
+  Some(seconds as u64)
+}
+
+/// Returns a random number in `0.0..1.0` for jitter.
+fn random() -> f64 {
+  let mut hasher = RandomState::new().build_hasher();
+  hasher.write_u64(
+    std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map(|d| d.as_nanos() as u64)
+      .unwrap_or_default()
+  );
+  (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn context(error: &Error, attempt: usize) -> RetryContext<'_> {
+    RetryContext {
+      error,
+      attempt,
+      max_attempts: 5,
+      base_delay: Duration::from_secs(1)
+    }
+  }
+
+  #[test]
+  fn test_backoff_grows_and_caps() {
+    let policy = ExponentialBackoff::new()
+      .jitter(0.0)
+      .max_delay(Duration::from_secs(3));
+    let error = Error::http_error(503, "https://example.com", "unavailable");
+
+    assert_eq!(
+      policy.retry_delay(&context(&error, 1)),
+      Some(Duration::from_secs(1))
+    );
+    assert_eq!(
+      policy.retry_delay(&context(&error, 2)),
+      Some(Duration::from_secs(2))
+    );
+    assert_eq!(
+      policy.retry_delay(&context(&error, 4)),
+      Some(Duration::from_secs(3))
+    );
+    assert_eq!(policy.retry_delay(&context(&error, 5)), None);
+  }
+
+  #[test]
+  fn test_only_recoverable_errors_are_retried() {
+    let policy = ExponentialBackoff::new();
+    let not_found = Error::http_error(404, "https://example.com", "not found");
+    let too_large = Error::file_too_large(10, 5);
+
+    assert_eq!(policy.retry_delay(&context(&not_found, 1)), None);
+    assert_eq!(policy.retry_delay(&context(&too_large, 1)), None);
+  }
+
+  #[test]
+  fn test_rate_limited_honors_retry_after() {
+    let policy = ExponentialBackoff::new().jitter(0.0);
+    let error = Error::rate_limited("https://example.com", 120);
+
+    assert_eq!(
+      policy.retry_delay(&context(&error, 1)),
+      Some(Duration::from_secs(120))
+    );
+  }
+
+  #[test]
+  fn test_retry_budget() {
+    let budget = RetryBudget::new(Some(1));
+    let shared = budget.clone();
+
+    assert!(budget.try_acquire());
+    assert!(!shared.try_acquire());
+    assert_eq!(shared.remaining(), Some(0));
+    assert!(RetryBudget::unlimited().try_acquire());
+  }
+
+  #[test]
+  fn test_parse_retry_after() {
+    assert_eq!(parse_retry_after("120"), Some(120));
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+    assert_eq!(parse_retry_after("soon"), None);
+  }
+}
@@ -0,0 +1,540 @@
+//! Segmented downloads of a single large file
+//!
+//! When a server supports byte ranges, a large file can be split into
+//! several segments that are fetched over separate connections and joined
+//! into the temp file once all of them are complete.
+
+use crate::*;
+use futures::stream::{FuturesUnordered, StreamExt};
+use reqwest::{
+  Method, StatusCode,
+  header::{ACCEPT_RANGES, CONTENT_RANGE, IF_RANGE, RANGE}
+};
+use std::{
+  collections::VecDeque,
+  path::{Path, PathBuf},
+  sync::{
+    Mutex,
+    atomic::{AtomicU64, AtomicUsize, Ordering}
+  },
+  time::Instant
+};
+use tokio::{
+  fs::{File, OpenOptions},
+  io::AsyncWriteExt
+};
+
+/// An inclusive byte range of a file downloaded as one segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+  /// Position of the segment within the file
+  pub index: usize,
+
+  /// First byte of the segment
+  pub start: u64,
+
+  /// Last byte of the segment (inclusive)
+  pub end: u64
+}
+
+impl Segment {
+  /// Returns the number of bytes in this segment.
+  pub fn len(&self) -> u64 {
+    self.end - self.start + 1
+  }
+
+  /// Returns true if the segment covers no bytes.
+  pub fn is_empty(&self) -> bool {
+    self.end < self.start
+  }
+}
+
+/// Splits a file of `total` bytes into at most `max_segments` segments of at
+/// least `min_size` bytes each.
+///
+/// Returns a single segment when the file is too small to be worth
+/// splitting.
+pub fn plan(total: u64, max_segments: usize, min_size: u64) -> Vec<Segment> {
+  if total == 0 {
+    return Vec::new();
+  }
+
+  let count = (total / min_size.max(1)).clamp(1, max_segments.max(1) as u64);
+  let size = total / count;
+
+  (0..count)
+    .map(|i| Segment {
+      index: i as usize,
+      start: i * size,
+      end: if i == count - 1 {
+        total - 1
+      } else {
+        (i + 1) * size - 1
+      }
+    })
+    .collect()
+}
+
+/// Returns the path of the file holding one segment of a temp file.
+pub fn segment_path(temp_path: &Path, index: usize) -> PathBuf {
+  let mut name = temp_path.as_os_str().to_os_string();
+  name.push(format!(".seg{index}"));
+  PathBuf::from(name)
+}
+
+/// Shared state of the segments of one download.
+struct Progress {
+  /// Segments not yet claimed by a worker
+  queue: Mutex<VecDeque<Segment>>,
+  /// Segments not yet completed
+  remaining: AtomicUsize,
+  /// Bytes on disk across all segments
+  downloaded: AtomicU64,
+  /// Time of the last progress report
+  last_report: Mutex<Instant>
+}
+
+/// Result of probing a URL before a segmented download.
+#[derive(Debug)]
+pub(crate) enum Probe {
+  /// The file can be split; carries its validators and total size
+  Segmented(Validators, u64),
+  /// The file should be downloaded as a single stream
+  Single,
+  /// The existing file is up to date
+  NotModified
+}
+
+impl DownloadTask {
+  /// Checks whether this file should be downloaded in segments.
+  ///
+  /// The probe is made conditional on `existing` validators, so an
+  /// unchanged file is detected without downloading anything. Otherwise
+  /// the file is split when the server advertises byte range support and
+  /// the file is large enough.
+  pub(crate) async fn probe_segmented(
+    &self,
+    existing: Option<&Validators>
+  ) -> Probe {
+    let mut request =
+      Request::with_config(Method::HEAD, self.source(), &self.config);
+    if let Some(existing) = existing {
+      request = request.headers(existing.conditional_headers());
+    }
+
+    let response = match self.transport.send(request).await {
+      Ok(response) if response.status() == StatusCode::NOT_MODIFIED =>
+        return Probe::NotModified,
+      Ok(response) if response.status().is_success() => response,
+      Ok(response) => {
+        debug!(
+          "Task {}: HEAD returned {}, not segmenting",
+          self.index,
+          response.status()
+        );
+        return Probe::Single;
+      }
+      Err(e) => {
+        debug!("Task {}: HEAD failed, not segmenting: {}", self.index, e);
+        return Probe::Single;
+      }
+    };
+
+    let accepts_ranges = response
+      .headers()
+      .get(ACCEPT_RANGES)
+      .and_then(|v| v.to_str().ok())
+      .is_some_and(|v| v.eq_ignore_ascii_case("bytes"));
+
+    let validators = Validators::from_headers(response.headers());
+    match validators.content_length {
+      Some(total)
+        if accepts_ranges
+          && total >= self.config.min_segment_size.saturating_mul(2) =>
+        Probe::Segmented(validators, total),
+      _ => Probe::Single
+    }
+  }
+
+  /// Downloads the file as concurrent ranged segments and joins them into
+  /// the temp file.
+  ///
+  /// The first segment worker runs on the permit already held by this task;
+  /// the others each wait for a permit from the executor's semaphore, so a
+  /// segmented download never exceeds the configured concurrency.
+  ///
+  /// Returns the total bytes and the bytes reused from earlier attempts.
+  pub(crate) async fn download_segmented(
+    &self,
+    validators: Validators,
+    total: u64
+  ) -> Result<(u64, u64)> {
+    if let Some(max_size) = self.config.max_file_size
+      && total > max_size
+    {
+      return Err(Error::FileTooLarge {
+        size: total,
+        max_size
+      });
+    }
+
+    let segments =
+      plan(total, self.config.segments, self.config.min_segment_size);
+
+    // Segments from an earlier attempt are only reused if the resource is
+    // unchanged since they were written
+    let saved = Validators::load(&self.temp_path).await;
+    let reusable = self.config.resume
+      && saved.is_some_and(|saved| {
+        saved.if_range().is_some()
+          && saved.if_range() == validators.if_range()
+          && saved.content_length == validators.content_length
+      });
+
+    let mut resumed_bytes = 0;
+    for segment in &segments {
+      let path = segment_path(&self.temp_path, segment.index);
+      let size = if reusable {
+        tokio::fs::metadata(&path)
+          .await
+          .map(|m| m.len())
+          .unwrap_or(0)
+      } else {
+        0
+      };
+
+      if size > segment.len() || (!reusable && path.exists()) {
+        tokio::fs::remove_file(&path).await?;
+      } else {
+        resumed_bytes += size;
+      }
+    }
+
+    if self.config.resume {
+      validators.save(&self.temp_path).await?;
+    }
+
+    self
+      .journal(journal::State::Started {
+        offset: resumed_bytes,
+        validators: validators.clone()
+      })
+      .await;
+
+    info!(
+      "Task {}: Downloading {} bytes in {} segments ({} bytes resumed)",
+      self.index,
+      total,
+      segments.len(),
+      resumed_bytes
+    );
+
+    let progress = Progress {
+      remaining: AtomicUsize::new(segments.len()),
+      queue: Mutex::new(segments.iter().copied().collect()),
+      downloaded: AtomicU64::new(resumed_bytes),
+      last_report: Mutex::new(Instant::now())
+    };
+
+    let mut workers = FuturesUnordered::new();
+    for worker in 0..segments.len() {
+      workers.push(self.segment_worker(worker, &validators, total, &progress));
+    }
+
+    // Waiting workers are dropped as soon as every segment is done
+    while let Some(result) = workers.next().await {
+      result?;
+      if progress.remaining.load(Ordering::Acquire) == 0 {
+        break;
+      }
+    }
+    drop(workers);
+
+    self.join_segments(&segments).await?;
+
+    Ok((total, resumed_bytes))
+  }
+
+  /// Claims and downloads segments until none are left.
+  async fn segment_worker(
+    &self,
+    worker: usize,
+    validators: &Validators,
+    total: u64,
+    progress: &Progress
+  ) -> Result<()> {
+    let _permit = match (&self.semaphore, worker) {
+      (Some(semaphore), 1..) =>
+        Some(semaphore.acquire().await.map_err(|e| Error::TaskFailed {
+          index: self.index,
+          reason: format!("Semaphore closed: {e}")
+        })?),
+      _ => None
+    };
+
+    loop {
+      let Some(segment) = progress.queue.lock().unwrap().pop_front() else {
+        return Ok(());
+      };
+
+      trace!(
+        "Task {}: Worker {} downloading segment {} ({}-{})",
+        self.index, worker, segment.index, segment.start, segment.end
+      );
+
+      self
+        .download_segment(segment, validators, total, progress)
+        .await?;
+      progress.remaining.fetch_sub(1, Ordering::AcqRel);
+    }
+  }
+
+  /// Downloads the missing bytes of one segment into its segment file.
+  async fn download_segment(
+    &self,
+    segment: Segment,
+    validators: &Validators,
+    total: u64,
+    progress: &Progress
+  ) -> Result<()> {
+    let path = segment_path(&self.temp_path, segment.index);
+    let existing = tokio::fs::metadata(&path)
+      .await
+      .map(|m| m.len())
+      .unwrap_or(0);
+    if existing == segment.len() {
+      return Ok(());
+    }
+
+    let mut request =
+      Request::with_config(Method::GET, self.source(), &self.config);
+    request = request.header(
+      RANGE,
+      format!("bytes={}-{}", segment.start + existing, segment.end)
+    );
+    if let Some(if_range) = validators.if_range() {
+      request = request.header(IF_RANGE, if_range);
+    }
+
+    let response = self.transport.send(request).await?;
+
+    // A throttled segment can be retried as is
+    if let Some(retry_after) = task::rate_limit(&response) {
+      return Err(Error::rate_limited(self.source().as_str(), retry_after));
+    }
+
+    if let Some(error) =
+      auth::authentication_error(&response, self.source().as_str())
+    {
+      return Err(error);
+    }
+
+    // Anything but a partial response means the segments can't be joined
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+      Validators::remove(&self.temp_path).await;
+      return Err(Error::HttpStatus {
+        status: response.status().as_u16(),
+        url: self.source().to_string(),
+        message: format!(
+          "Expected partial content for segment {}",
+          segment.index
+        )
+      });
+    }
+
+    // Bytes from anywhere but the requested start would corrupt the file
+    let start = response
+      .headers()
+      .get(CONTENT_RANGE)
+      .and_then(|v| v.to_str().ok())
+      .and_then(task::parse_content_range)
+      .map(|(start, _)| start);
+    if start != Some(segment.start + existing) {
+      Validators::remove(&self.temp_path).await;
+      return Err(Error::HttpStatus {
+        status: response.status().as_u16(),
+        url: self.source().to_string(),
+        message: format!(
+          "Unexpected Content-Range for segment {} at byte {}",
+          segment.index,
+          segment.start + existing
+        )
+      });
+    }
+
+    let mut file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)
+      .await
+      .map_err(|e| Error::FileSystem {
+        message: format!("Failed to open segment file: {e}")
+      })?;
+    if self.config.preallocate {
+      disk::preallocate(&file, &path, existing, segment.len() - existing)?;
+    }
+
+    let mut written = existing;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk_result) = stream.next().await {
+      let chunk = chunk_result?;
+
+      self.control.checkpoint().await?;
+      self.throttle.acquire(self.host(), chunk.len() as u64).await;
+
+      file
+        .write_all(&chunk)
+        .await
+        .map_err(|e| Error::FileSystem {
+          message: format!("Failed to write segment file: {e}")
+        })?;
+
+      written += chunk.len() as u64;
+      let downloaded = progress
+        .downloaded
+        .fetch_add(chunk.len() as u64, Ordering::AcqRel)
+        + chunk.len() as u64;
+
+      self
+        .report_segment_progress(progress, downloaded, total)
+        .await;
+    }
+
+    file.flush().await.map_err(|e| Error::FileSystem {
+      message: format!("Failed to flush segment file: {e}")
+    })?;
+
+    // The bytes received so far stay in the segment file for a resume
+    if written != segment.len() {
+      return Err(Error::interrupted(
+        self.source().as_str(),
+        format!(
+          "Segment {} ended after {} of {} bytes",
+          segment.index,
+          written,
+          segment.len()
+        )
+      ));
+    }
+
+    Ok(())
+  }
+
+  /// Reports combined progress of all segments at the configured interval.
+  async fn report_segment_progress(
+    &self,
+    progress: &Progress,
+    downloaded: u64,
+    total: u64
+  ) {
+    {
+      let now = Instant::now();
+      let mut last_report = progress.last_report.lock().unwrap();
+      if now.duration_since(*last_report) < self.config.progress_interval {
+        return;
+      }
+      *last_report = now;
+    }
+
+    self.progress_tx.progress(
+      self.index,
+      downloaded as usize,
+      Some(total as usize)
+    );
+
+    self
+      .event_sink
+      .on_event(DownloadEvent::FileProgress {
+        index: self.index,
+        bytes_downloaded: downloaded,
+        total_bytes: Some(total),
+        percentage: (downloaded as f64 / total as f64) * 100.0,
+        // Segments are decompressed once they are joined
+        decompressed_bytes: None
+      })
+      .await;
+
+    self
+      .journal(journal::State::Bytes {
+        downloaded,
+        total: Some(total)
+      })
+      .await;
+  }
+
+  /// Concatenates the segment files into the temp file and removes them.
+  async fn join_segments(&self, segments: &[Segment]) -> Result<()> {
+    let mut temp_file =
+      self
+        .create_temp_file()
+        .await
+        .map_err(|e| Error::FileSystem {
+          message: format!("Failed to create temp file: {e}")
+        })?;
+
+    for segment in segments {
+      let path = segment_path(&self.temp_path, segment.index);
+      let mut segment_file = File::open(&path).await?;
+      tokio::io::copy(&mut segment_file, &mut temp_file)
+        .await
+        .map_err(|e| Error::FileSystem {
+          message: format!("Failed to join segment {}: {e}", segment.index)
+        })?;
+    }
+
+    temp_file.flush().await?;
+    drop(temp_file);
+
+    for segment in segments {
+      let path = segment_path(&self.temp_path, segment.index);
+      if let Err(e) = tokio::fs::remove_file(&path).await {
+        debug!("Failed to remove segment file {}: {}", path.display(), e);
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_plan_splits_evenly() {
+    let segments = plan(100, 4, 10);
+    assert_eq!(segments.len(), 4);
+    assert_eq!(
+      segments[0],
+      Segment {
+        index: 0,
+        start: 0,
+        end: 24
+      }
+    );
+    assert_eq!(segments[3].end, 99);
+    assert_eq!(segments.iter().map(Segment::len).sum::<u64>(), 100);
+  }
+
+  #[test]
+  fn test_plan_respects_min_size() {
+    assert_eq!(plan(100, 8, 40).len(), 2);
+    assert_eq!(plan(100, 8, 200).len(), 1);
+    assert!(plan(0, 4, 10).is_empty());
+  }
+
+  #[test]
+  fn test_plan_gives_remainder_to_last_segment() {
+    let segments = plan(103, 4, 10);
+    assert_eq!(segments[3].start, 75);
+    assert_eq!(segments[3].len(), 28);
+  }
+
+  #[test]
+  fn test_segment_path() {
+    assert_eq!(
+      segment_path(Path::new("/tmp/a.tsv.gz.part"), 2),
+      PathBuf::from("/tmp/a.tsv.gz.part.seg2")
+    );
+  }
+}
@@ -10,7 +10,7 @@ use std::{
   time::{Duration, Instant},
 };
 use tokio::{
-  fs::{File, rename},
+  fs::{File, OpenOptions, rename},
   io::AsyncWriteExt,
   sync::Semaphore,
   time::sleep,
@@ -25,20 +25,42 @@ use tokio::{
 pub struct DownloadTask {
   /// The URL to download from
   pub url: reqwest::Url,
+  /// The URL as given, identifying the file in the journal
+  pub original_url: String,
   /// Temporary file path for atomic operations
   pub temp_path: PathBuf,
   /// Final destination path
   pub final_path: PathBuf,
   /// Task index for identification and logging
   pub index: usize,
-  /// HTTP client with configured settings
-  pub client: reqwest::Client,
+  /// Transport fetching the URL
+  pub transport: Arc<dyn Transport>,
   /// Configuration settings
   pub config: crate::Config,
   /// Progress reporter
   pub progress_tx: progress::Sender,
   /// Event sink for notifications
   pub event_sink: Arc<dyn EventSink>,
+  /// Executor semaphore shared with segment workers (None = unlimited)
+  pub semaphore: Option<Arc<Semaphore>>,
+  /// Expected digest of the downloaded file
+  pub checksum: Option<Checksum>,
+  /// Target path before conflict resolution renamed the file
+  pub renamed_from: Option<PathBuf>,
+  /// Retries left for the batch this task belongs to
+  pub retry_budget: RetryBudget,
+  /// Bandwidth limiter shared with the other tasks of the executor
+  pub throttle: Throttle,
+  /// Cancellation and pause handle shared with the executor
+  pub control: Control,
+  /// Journal of the batch this task belongs to
+  pub journal: Option<Journal>,
+  /// Format to decompress the body from into the final path
+  pub decompress: Option<Decompression>,
+  /// URLs serving the same file, tried in order once `url` fails
+  pub mirrors: Vec<reqwest::Url>,
+  /// Mirror requested instead of `url` (None = `url` itself)
+  pub mirror: Option<reqwest::Url>,
 }
 
 impl DownloadTask {
@@ -54,16 +76,39 @@ impl DownloadTask {
   /// # Returns
   ///
   /// Returns `Ok(TaskResult)` on success or an error if all retry attempts
-  /// fail.
+  /// fail. Returns `Error::Cancelled` if the task's control is cancelled, in
+  /// which case any partial file is left in place for a later resume.
   pub async fn execute(self) -> Result<TaskResult> {
+    self.run().await.map_err(|failure| failure.error)
+  }
+
+  /// Executes the download task, describing a failure with the number of
+  /// attempts made and the time spent.
+  ///
+  /// Once the retries of a URL are exhausted, or it fails in a way retrying
+  /// cannot fix, the download moves on to the next mirror, resuming the
+  /// partial file if the mirror serves the same validators.
+  pub(crate) async fn run(
+    mut self,
+  ) -> std::result::Result<TaskResult, report::Failure> {
     let start_time = Instant::now();
-    let filename = self
-      .final_path
+    let final_path = self.final_path.clone();
+    let filename = final_path
       .file_name()
       .and_then(|n| n.to_str())
       .unwrap_or("unknown");
 
+    // Queued tasks wait here while the batch is paused
+    if self.control.checkpoint().await.is_err() {
+      return Err(report::Failure::new(
+        self.cancel().await,
+        0,
+        start_time.elapsed(),
+      ));
+    }
+
     // Notify that file download is starting
+    self.progress_tx.started(self.index);
     self
       .event_sink
       .on_event(DownloadEvent::FileStarted {
@@ -80,20 +125,45 @@ impl DownloadTask {
 
     let mut last_error = None;
     let mut retry_count = 0;
+    let mut attempts = 0;
+    let mut mirrors = self.mirrors.clone().into_iter();
 
     // Retry loop
     loop {
-      match self.attempt_download().await {
-        Ok(bytes_downloaded) => {
+      let attempt = tokio::select! {
+        attempt = self.attempt_download() => attempt,
+        _ = self.control.cancelled() => Err(Error::Cancelled),
+      };
+
+      match attempt {
+        Ok(Outcome::NotModified) => {
+          return Ok(
+            self
+              .finish_not_modified(filename, start_time, retry_count)
+              .await,
+          );
+        }
+        Ok(Outcome::Downloaded(Attempt {
+          bytes_downloaded,
+          resumed_bytes,
+          digests,
+          validators,
+          decompressed_bytes,
+          cached,
+        })) => {
           let duration = start_time.elapsed();
           let final_speed = if duration.as_secs_f64() > 0.0 {
-            bytes_downloaded as f64 / duration.as_secs_f64()
+            (bytes_downloaded - resumed_bytes) as f64 / duration.as_secs_f64()
           } else {
             0.0
           };
 
+          if !cached {
+            self.store_in_cache(&digests, &validators).await;
+          }
+
           // Atomically move to final location
-          if let Err(e) = rename(&self.temp_path, &self.final_path).await {
+          if let Err(e) = self.move_to_final_path().await {
             let error = Error::FileSystem {
               message: format!("Failed to move file to final location: {e}"),
             };
@@ -108,16 +178,55 @@ impl DownloadTask {
               })
               .await;
 
-            return Err(error);
+            self
+              .journal(journal::State::Failed {
+                error: error.to_string(),
+              })
+              .await;
+
+            return Err(report::Failure::new(
+              error,
+              retry_count + 1,
+              start_time.elapsed(),
+            ));
           }
 
+          Validators::remove(&self.temp_path).await;
+
+          if self.config.checksum_sidecar {
+            self.write_checksum_sidecar(&digests).await;
+          }
+
+          // Keep the validators for the next conditional request, and drop
+          // any left by an earlier run so they cannot describe this file
+          if self.config.overwrite_policy == OverwritePolicy::IfModified {
+            if let Err(e) = validators.save(&self.final_path).await {
+              self.file_warning(e).await;
+            }
+          } else {
+            Validators::remove(&self.final_path).await;
+          }
+
+          self
+            .journal(journal::State::Completed {
+              bytes_downloaded,
+              validators,
+            })
+            .await;
+
           let result = TaskResult {
             index: self.index,
             path: self.final_path.clone(),
+            renamed_from: self.renamed_from.clone(),
             bytes_downloaded,
+            resumed_bytes,
             duration,
             retry_count,
             final_speed,
+            not_modified: false,
+            decompressed_bytes,
+            mirror: self.mirror.as_ref().map(ToString::to_string),
+            cached,
           };
 
           // Report successful completion
@@ -132,6 +241,7 @@ impl DownloadTask {
               filename: filename.to_string(),
               bytes_downloaded,
               duration,
+              mirror: self.mirror.as_ref().map(ToString::to_string),
             })
             .await;
 
@@ -145,6 +255,13 @@ impl DownloadTask {
 
           return Ok(result);
         }
+        Err(Error::Cancelled) => {
+          return Err(report::Failure::new(
+            self.cancel().await,
+            retry_count + 1,
+            start_time.elapsed(),
+          ));
+        }
         Err(e) => {
           error!(
             "Download {} attempt {} failed: {}",
@@ -152,34 +269,57 @@ impl DownloadTask {
             retry_count + 1,
             e
           );
-          last_error = Some(e);
           retry_count += 1;
-
-          if retry_count >= self.config.max_retries {
-            break;
-          }
+          attempts += 1;
+
+          let Some(delay) = self.retry_delay(&e, attempts) else {
+            match mirrors.next().filter(|_| fails_over(&e)) {
+              Some(mirror) => {
+                self.fail_over(mirror, &e).await;
+                attempts = 0;
+                last_error = Some(e);
+                continue;
+              }
+              None => {
+                last_error = Some(e);
+                break;
+              }
+            }
+          };
 
           // Notify about retry
+          self.progress_tx.retrying(self.index, attempts + 1);
           self
             .event_sink
             .on_event(DownloadEvent::FileRetrying {
               index: self.index,
-              url: self.url.to_string(),
-              attempt: retry_count + 1,
+              url: self.source().to_string(),
+              attempt: attempts + 1,
               max_attempts: self.config.max_retries,
-              delay: self.config.retry_delay,
+              delay,
+              reason: e.to_string(),
             })
             .await;
 
           warn!(
             "Retrying download {} in {:.1}s (attempt {}/{})",
             self.index,
-            self.config.retry_delay.as_secs_f64(),
-            retry_count + 1,
+            delay.as_secs_f64(),
+            attempts + 1,
             self.config.max_retries
           );
 
-          sleep(self.config.retry_delay).await;
+          last_error = Some(e);
+          tokio::select! {
+            _ = sleep(delay) => {}
+            _ = self.control.cancelled() => {
+              return Err(report::Failure::new(
+                self.cancel().await,
+                retry_count,
+                start_time.elapsed(),
+              ));
+            }
+          }
         }
       }
     }
@@ -192,6 +332,12 @@ impl DownloadTask {
 
     self.progress_tx.failed(self.index, final_error.to_string());
 
+    self
+      .journal(journal::State::Failed {
+        error: final_error.to_string(),
+      })
+      .await;
+
     self
       .event_sink
       .on_event(DownloadEvent::FileFailed {
@@ -202,37 +348,257 @@ impl DownloadTask {
       })
       .await;
 
-    Err(final_error)
+    Err(report::Failure::new(
+      final_error,
+      retry_count,
+      start_time.elapsed(),
+    ))
   }
 
-  /// Attempts a single download without retry logic.
-  async fn attempt_download(&self) -> Result<u64> {
-    trace!("Attempting download for task {}: {}", self.index, self.url);
+  /// Reports the task as cancelled and returns the error to end it with.
+  async fn cancel(&self) -> Error {
+    info!("Download {} cancelled", self.index);
+
+    self
+      .progress_tx
+      .failed(self.index, Error::Cancelled.to_string());
+
+    self
+      .event_sink
+      .on_event(DownloadEvent::FileCancelled {
+        index: self.index,
+        url: self.url.to_string(),
+      })
+      .await;
+
+    self
+      .journal(journal::State::Failed {
+        error: Error::Cancelled.to_string(),
+      })
+      .await;
+
+    Error::Cancelled
+  }
+
+  /// Switches the download to `mirror` after the current URL failed with
+  /// `error`.
+  async fn fail_over(&mut self, mirror: reqwest::Url, error: &Error) {
+    warn!(
+      "Download {} failed at {}, falling back to mirror {}",
+      self.index,
+      self.source(),
+      mirror
+    );
+
+    self
+      .event_sink
+      .on_event(DownloadEvent::Warning {
+        message: format!(
+          "Failed at {}: {error}, falling back to mirror {mirror}",
+          self.source()
+        ),
+        context: Some(self.url.to_string()),
+      })
+      .await;
 
-    // Build request with custom headers
-    let mut request = self.client.get(self.url.clone());
+    self.mirror = Some(mirror);
+  }
 
-    for (key, value) in &self.config.custom_headers {
-      request = request.header(key, value);
+  /// Returns the URL requested, the current mirror or `url` itself.
+  pub(crate) fn source(&self) -> &reqwest::Url {
+    self.mirror.as_ref().unwrap_or(&self.url)
+  }
+
+  /// Records a state transition of this file in the batch journal.
+  pub(crate) async fn journal(&self, state: journal::State) {
+    if let Some(journal) = &self.journal {
+      journal.record(&self.original_url, state).await;
     }
+  }
 
-    // Make the HTTP request
-    let response = request.send().await.map_err(|e| Error::RequestFailed {
-      url: self.url.to_string(),
-      download: e,
+  /// Returns the delay before retrying after `error`, or `None` if the
+  /// retry policy gives up or the batch has no retries left.
+  fn retry_delay(&self, error: &Error, attempt: usize) -> Option<Duration> {
+    let delay = self.config.retry_policy.retry_delay(&RetryContext {
+      error,
+      attempt,
+      max_attempts: self.config.max_retries,
+      base_delay: self.config.retry_delay,
     })?;
 
+    if !self.retry_budget.try_acquire() {
+      warn!(
+        "Download {}: Retry budget of the batch exhausted",
+        self.index
+      );
+      return None;
+    }
+
+    Some(delay)
+  }
+
+  /// Attempts a single download without retry logic.
+  ///
+  /// If a partial temp file from an earlier attempt is present, the request
+  /// asks for the remaining bytes only and falls back to a full download
+  /// when the server ignores the range or the resource has changed.
+  ///
+  /// Under [`OverwritePolicy::IfModified`] the request is conditional on the
+  /// existing file's validators, and a 304 response leaves it untouched.
+  async fn attempt_download(&self) -> Result<Outcome> {
+    trace!(
+      "Attempting download for task {}: {}",
+      self.index,
+      self.source()
+    );
+
+    let mut hashers = self.hashers();
+    let existing = self.existing_validators().await;
+    let cached = self.cached_entry(existing.as_ref()).await;
+
+    // A file whose expected checksum is cached needs no request at all
+    if let Some(checksum) = &self.checksum
+      && checksum.algorithm == checksum::Algorithm::Sha256
+    {
+      let validators = cached
+        .as_ref()
+        .filter(|entry| entry.digest == checksum.expected)
+        .map(|entry| entry.validators.clone())
+        .unwrap_or_default();
+      if let Some(attempt) =
+        self.serve_cached(&checksum.expected, validators).await?
+      {
+        return self.verify(attempt).await.map(Outcome::Downloaded);
+      }
+    }
+
+    // Without an existing file to update, the cached version of the URL is
+    // revalidated instead
+    let conditional = existing
+      .clone()
+      .or_else(|| cached.as_ref().map(|entry| entry.validators.clone()));
+
+    // A single-stream partial is resumed as is rather than segmented
+    if self.config.segments > 1
+      && !(self.config.resume && self.temp_path.exists())
+    {
+      match self.probe_segmented(conditional.as_ref()).await {
+        segment::Probe::NotModified => {
+          return self.not_modified(cached.as_ref()).await;
+        }
+        segment::Probe::Segmented(validators, total) => {
+          let (bytes_downloaded, resumed_bytes) =
+            self.download_segmented(validators.clone(), total).await?;
+
+          // Segments arrive out of order, so the joined file is hashed
+          if !hashers.is_empty() {
+            checksum::hash_file_into(&self.temp_path, &mut hashers).await?;
+          }
+          let decompressed_bytes =
+            self.discard_invalid(self.decompress_file().await).await?;
+
+          let attempt = Attempt::new(
+            bytes_downloaded,
+            resumed_bytes,
+            hashers,
+            validators,
+            decompressed_bytes,
+          );
+          return self.verify(attempt).await.map(Outcome::Downloaded);
+        }
+        segment::Probe::Single => {}
+      }
+    }
+
+    let mut partial = if self.config.resume {
+      self.partial_download().await
+    } else {
+      None
+    };
+
+    let response = loop {
+      let response = self
+        .send_request(partial.as_ref(), conditional.as_ref())
+        .await?;
+
+      // The partial no longer fits the resource, so start from scratch
+      if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+        && partial.take().is_some()
+      {
+        debug!(
+          "Task {}: Server rejected range, restarting download",
+          self.index
+        );
+        continue;
+      }
+
+      break response;
+    };
+
+    if conditional.is_some()
+      && response.status() == reqwest::StatusCode::NOT_MODIFIED
+    {
+      return self.not_modified(cached.as_ref()).await;
+    }
+
     // Check response status
+    if let Some(retry_after) = rate_limit(&response) {
+      return Err(Error::rate_limited(self.source().as_str(), retry_after));
+    }
+
+    if let Some(error) =
+      auth::authentication_error(&response, self.source().as_str())
+    {
+      return Err(error);
+    }
+
     if !response.status().is_success() {
       return Err(Error::HttpStatus {
         status: response.status().as_u16(),
-        url: self.url.to_string(),
+        url: self.source().to_string(),
         message: "Failed to download file".to_string(),
       });
     }
 
+    let mut validators = Validators::from_headers(response.headers());
+
+    // A 200 response to a range request means the server ignored the range
+    // or the If-Range validator no longer matches
+    let offset = match partial {
+      Some((offset, _))
+        if response.status() == reqwest::StatusCode::PARTIAL_CONTENT =>
+      {
+        let start = response
+          .headers()
+          .get(reqwest::header::CONTENT_RANGE)
+          .and_then(|v| v.to_str().ok())
+          .and_then(parse_content_range)
+          .map(|(start, _)| start);
+
+        if start != Some(offset) {
+          return Err(Error::HttpStatus {
+            status: response.status().as_u16(),
+            url: self.source().to_string(),
+            message: format!(
+              "Unexpected Content-Range for resume at byte {offset}"
+            ),
+          });
+        }
+
+        info!("Task {}: Resuming download at byte {}", self.index, offset);
+        offset
+      }
+      _ => {
+        if self.config.resume {
+          validators.save(&self.temp_path).await?;
+        }
+        0
+      }
+    };
+
     // Check content length and file size limits
-    let content_length = response.content_length();
+    let content_length = response.content_length().map(|len| offset + len);
+    validators.content_length = content_length;
     if let (Some(max_size), Some(content_len)) =
       (self.config.max_file_size, content_length)
       && content_len > max_size
@@ -248,37 +614,495 @@ impl DownloadTask {
       self.index, content_length
     );
 
+    self
+      .journal(journal::State::Started {
+        offset,
+        validators: validators.clone(),
+      })
+      .await;
+
+    // Bytes kept from an earlier attempt have to be hashed before new ones
+    if offset > 0 && !hashers.is_empty() {
+      checksum::hash_file_into(&self.temp_path, &mut hashers).await?;
+    }
+
     // Download with progress reporting
-    self.download_with_progress(response, content_length).await
+    let result = self
+      .download_with_progress(response, offset, content_length, &mut hashers)
+      .await;
+    let (bytes_downloaded, decompressed_bytes) =
+      self.discard_invalid(result).await?;
+
+    self
+      .verify(Attempt::new(
+        bytes_downloaded,
+        offset,
+        hashers,
+        validators,
+        decompressed_bytes,
+      ))
+      .await
+      .map(Outcome::Downloaded)
+  }
+
+  /// Returns the cache entry of the URL to revalidate, unless an existing
+  /// file is checked for updates instead.
+  async fn cached_entry(
+    &self,
+    existing: Option<&Validators>,
+  ) -> Option<cache::Entry> {
+    let cache = self.config.cache.as_ref()?;
+    if existing.is_some() {
+      return None;
+    }
+
+    cache
+      .lookup(self.url.as_str())
+      .await
+      .filter(|entry| !entry.validators.is_empty())
+  }
+
+  /// Handles a 304 response: the cached version of the URL is served if it
+  /// was revalidated, otherwise the existing file is up to date.
+  async fn not_modified(
+    &self,
+    cached: Option<&cache::Entry>,
+  ) -> Result<Outcome> {
+    let Some(entry) = cached else {
+      return Ok(Outcome::NotModified);
+    };
+
+    match self
+      .serve_cached(&entry.digest, entry.validators.clone())
+      .await?
+    {
+      Some(attempt) => self.verify(attempt).await.map(Outcome::Downloaded),
+      // The corrupt file is gone from the cache, so a retry downloads it
+      None => Err(Error::interrupted(
+        self.url.as_str(),
+        "Cached file is corrupt",
+      )),
+    }
+  }
+
+  /// Places the cached file with the SHA-256 `digest` at the temp path,
+  /// returning `None` if it is not cached.
+  ///
+  /// The file is hashed like a download, and one that no longer matches its
+  /// digest is removed from the cache.
+  async fn serve_cached(
+    &self,
+    digest: &str,
+    validators: Validators,
+  ) -> Result<Option<Attempt>> {
+    let Some(cache) = self.config.cache.as_ref().filter(|c| c.contains(digest))
+    else {
+      return Ok(None);
+    };
+
+    // Another downloader may have evicted the file since
+    let Some(link) = cache.try_link(digest, &self.temp_path).await? else {
+      return Ok(None);
+    };
+    debug!(
+      "Task {}: Serving {} from the cache ({:?})",
+      self.index, self.url, link
+    );
+
+    let mut hashers = self.hashers();
+    checksum::hash_file_into(&self.temp_path, &mut hashers).await?;
+    let attempt = Attempt::new(0, 0, hashers, validators, None);
+    if attempt.digest(checksum::Algorithm::Sha256) != Some(digest) {
+      warn!("Task {}: Cached file {} is corrupt", self.index, digest);
+      self.remove_temp_files().await;
+      cache.discard(digest).await?;
+      return Ok(None);
+    }
+
+    let size = tokio::fs::metadata(&self.temp_path).await?.len();
+    if let Err(e) = cache
+      .touch(self.url.as_str(), &attempt.validators, digest, size)
+      .await
+    {
+      self.file_warning(e).await;
+    }
+
+    let decompressed_bytes =
+      self.discard_invalid(self.decompress_file().await).await?;
+    Ok(Some(Attempt {
+      decompressed_bytes,
+      cached: true,
+      ..attempt
+    }))
+  }
+
+  /// Stores the finished temp file in the cache, if any.
+  ///
+  /// A cache that cannot be written does not fail the download.
+  async fn store_in_cache(
+    &self,
+    digests: &[(checksum::Algorithm, String)],
+    validators: &Validators,
+  ) {
+    let Some(cache) = &self.config.cache else {
+      return;
+    };
+    let Some((_, digest)) = digests
+      .iter()
+      .find(|(algorithm, _)| *algorithm == checksum::Algorithm::Sha256)
+    else {
+      return;
+    };
+
+    if let Err(e) = cache
+      .insert(self.url.as_str(), validators, &self.temp_path, digest)
+      .await
+    {
+      self.file_warning(e).await;
+    }
+  }
+
+  /// Creates an empty temp file.
+  ///
+  /// A temp file may be a link to a cached file, so it is replaced rather
+  /// than truncated.
+  pub(crate) async fn create_temp_file(&self) -> std::io::Result<File> {
+    if let Err(e) = tokio::fs::remove_file(&self.temp_path).await
+      && e.kind() != std::io::ErrorKind::NotFound
+    {
+      return Err(e);
+    }
+    File::create(&self.temp_path).await
+  }
+
+  /// Returns the validators of the existing final file when it should only
+  /// be replaced if the remote resource has changed.
+  ///
+  /// Files downloaded without a sidecar fall back to their modification
+  /// time.
+  async fn existing_validators(&self) -> Option<Validators> {
+    if self.config.overwrite_policy != OverwritePolicy::IfModified {
+      return None;
+    }
+
+    let metadata = tokio::fs::metadata(&self.final_path).await.ok()?;
+    match Validators::load(&self.final_path).await {
+      Some(validators) if !validators.is_empty() => Some(validators),
+      _ => metadata.modified().ok().map(Validators::from_modified),
+    }
+  }
+
+  /// Completes a task whose existing file the server reported unchanged.
+  async fn finish_not_modified(
+    &self,
+    filename: &str,
+    start_time: Instant,
+    retry_count: usize,
+  ) -> TaskResult {
+    // A partial left by an interrupted update is no longer needed
+    if let Err(e) = tokio::fs::remove_file(&self.temp_path).await
+      && e.kind() != std::io::ErrorKind::NotFound
+    {
+      debug!("Failed to remove temp file: {}", e);
+    }
+    Validators::remove(&self.temp_path).await;
+
+    self
+      .journal(journal::State::Completed {
+        bytes_downloaded: 0,
+        validators: Validators::load(&self.final_path)
+          .await
+          .unwrap_or_default(),
+      })
+      .await;
+
+    self.progress_tx.completed(self.index, 0);
+
+    self
+      .event_sink
+      .on_event(DownloadEvent::FileNotModified {
+        index: self.index,
+        url: self.url.to_string(),
+        filename: filename.to_string(),
+      })
+      .await;
+
+    info!(
+      "Download {} skipped: {} is up to date",
+      self.index, filename
+    );
+
+    TaskResult {
+      index: self.index,
+      path: self.final_path.clone(),
+      renamed_from: self.renamed_from.clone(),
+      bytes_downloaded: 0,
+      resumed_bytes: 0,
+      duration: start_time.elapsed(),
+      retry_count,
+      final_speed: 0.0,
+      not_modified: true,
+      decompressed_bytes: None,
+      mirror: self.mirror.as_ref().map(ToString::to_string),
+      cached: false,
+    }
+  }
+
+  /// Creates hashers for the expected checksum, the checksum sidecar and the
+  /// cache.
+  fn hashers(&self) -> Vec<checksum::Hasher> {
+    let mut algorithms = Vec::new();
+    if let Some(checksum) = &self.checksum {
+      algorithms.push(checksum.algorithm);
+    }
+    if (self.config.checksum_sidecar || self.config.cache.is_some())
+      && !algorithms.contains(&checksum::Algorithm::Sha256)
+    {
+      algorithms.push(checksum::Algorithm::Sha256);
+    }
+
+    algorithms.into_iter().map(checksum::Hasher::new).collect()
+  }
+
+  /// Checks the computed digests against the expected checksum.
+  ///
+  /// A temp file that fails verification is removed so that a retry starts
+  /// from scratch instead of resuming corrupt data.
+  async fn verify(&self, attempt: Attempt) -> Result<Attempt> {
+    let Some(checksum) = &self.checksum else {
+      return Ok(attempt);
+    };
+
+    let digest = attempt
+      .digest(checksum.algorithm)
+      .unwrap_or_default()
+      .to_string();
+    if let Err(e) = checksum.verify(self.source().as_str(), &digest) {
+      self.remove_temp_files().await;
+      return Err(e);
+    }
+
+    debug!(
+      "Task {}: {} checksum verified",
+      self.index, checksum.algorithm
+    );
+    Ok(attempt)
+  }
+
+  /// Removes the temp files of a download whose bytes turned out to be
+  /// corrupt, so that a retry starts from scratch.
+  async fn remove_temp_files(&self) {
+    if let Err(e) = tokio::fs::remove_file(&self.temp_path).await {
+      debug!("Failed to remove corrupt temp file: {}", e);
+    }
+    Validators::remove(&self.temp_path).await;
+    if self.decompress.is_some() {
+      let _ =
+        tokio::fs::remove_file(decompress::decoded_temp_path(&self.temp_path))
+          .await;
+    }
+  }
+
+  /// Removes the temp files if `result` failed because the downloaded
+  /// bytes cannot be decompressed.
+  async fn discard_invalid<T>(&self, result: Result<T>) -> Result<T> {
+    if let Err(Error::ContentValidation { .. }) = &result {
+      self.remove_temp_files().await;
+    }
+    result
+  }
+
+  /// Decompresses the complete temp file, returning the decompressed size,
+  /// or `None` without a format to decompress.
+  async fn decompress_file(&self) -> Result<Option<u64>> {
+    let Some(format) = self.decompress else {
+      return Ok(None);
+    };
+
+    let mut decoder =
+      decompress::Decoder::create(format, &self.temp_path).await?;
+    decoder
+      .write_file(self.source().as_str(), &self.temp_path)
+      .await?;
+    decoder.finish(self.source().as_str()).await.map(Some)
+  }
+
+  /// Moves the finished download to the final path.
+  ///
+  /// A decompressed download moves its decompressed temp file instead, and
+  /// the compressed one is kept next to it or removed.
+  async fn move_to_final_path(&self) -> std::io::Result<()> {
+    let Some(format) = self.decompress else {
+      return rename(&self.temp_path, &self.final_path).await;
+    };
+
+    rename(
+      decompress::decoded_temp_path(&self.temp_path),
+      &self.final_path,
+    )
+    .await?;
+    if self.config.keep_compressed {
+      rename(&self.temp_path, format.encoded_path(&self.final_path)).await
+    } else {
+      tokio::fs::remove_file(&self.temp_path).await
+    }
+  }
+
+  /// Writes the `.sha256` sidecar next to the final file.
+  async fn write_checksum_sidecar(
+    &self,
+    digests: &[(checksum::Algorithm, String)],
+  ) {
+    let Some((_, digest)) = digests
+      .iter()
+      .find(|(algorithm, _)| *algorithm == checksum::Algorithm::Sha256)
+    else {
+      return;
+    };
+
+    // The digest is of the downloaded bytes, so a decompressed file only
+    // gets a sidecar for its kept original
+    let path = match self.decompress {
+      None => self.final_path.clone(),
+      Some(format) if self.config.keep_compressed => {
+        format.encoded_path(&self.final_path)
+      }
+      Some(_) => return,
+    };
+    if let Err(e) = checksum::write_sidecar(&path, digest).await {
+      self.file_warning(e).await;
+    }
+  }
+
+  /// Reports a problem with the files of a download that does not fail it,
+  /// such as a sidecar that could not be written.
+  async fn file_warning(&self, error: Error) {
+    warn!("Task {}: {}", self.index, error);
+    self
+      .event_sink
+      .on_event(DownloadEvent::Warning {
+        message: error.to_string(),
+        context: Some(self.final_path.display().to_string()),
+      })
+      .await;
+  }
+
+  /// Sends the download request, asking for a byte range when resuming and
+  /// making it conditional when an existing file may be up to date.
+  async fn send_request(
+    &self,
+    partial: Option<&(u64, Validators)>,
+    existing: Option<&Validators>,
+  ) -> Result<Response> {
+    // Build request with custom headers and credentials
+    let mut request =
+      Request::with_config(reqwest::Method::GET, self.source(), &self.config);
+
+    if let Some((offset, validators)) = partial {
+      request =
+        request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+      if let Some(if_range) = validators.if_range() {
+        request = request.header(reqwest::header::IF_RANGE, if_range);
+      }
+    }
+
+    if let Some(existing) = existing {
+      request = request.headers(existing.conditional_headers());
+    }
+
+    self.transport.send(request).await
+  }
+
+  /// Returns the size and validators of a resumable partial temp file.
+  ///
+  /// A partial is only resumable if the validators of the response that
+  /// produced it were recorded, so the server can confirm it is unchanged.
+  async fn partial_download(&self) -> Option<(u64, Validators)> {
+    let size = tokio::fs::metadata(&self.temp_path).await.ok()?.len();
+    if size == 0 {
+      return None;
+    }
+
+    match Validators::load(&self.temp_path).await {
+      Some(validators) if validators.if_range().is_some() => {
+        Some((size, validators))
+      }
+      _ => {
+        debug!(
+          "Task {}: Partial file has no validators, restarting",
+          self.index
+        );
+        None
+      }
+    }
+  }
+
+  /// Returns the host used to look up per-host bandwidth limits.
+  pub(crate) fn host(&self) -> &str {
+    self.source().host_str().unwrap_or_default()
   }
 
   /// Downloads response body with progress reporting.
-  // In your DownloadTask::download_with_progress method, handle closed channels
-  // gracefully:
+  ///
+  /// Bytes are appended to the temp file when `offset` is non-zero, and the
+  /// reported progress includes the bytes already on disk. With a format to
+  /// decompress, the bytes are also decompressed as they arrive, and the
+  /// decompressed size is returned as well.
   async fn download_with_progress(
     &self,
-    response: reqwest::Response,
+    response: Response,
+    offset: u64,
     content_length: Option<u64>,
-  ) -> Result<u64> {
+    hashers: &mut [checksum::Hasher],
+  ) -> Result<(u64, Option<u64>)> {
     use tokio_stream::StreamExt;
 
-    // Create temporary file
-    let mut temp_file =
-      File::create(&self.temp_path)
-        .await
-        .map_err(|e| Error::FileSystem {
-          message: format!("Failed to create temp file: {e}"),
-        })?;
+    // Create temporary file, or append to the partial one when resuming
+    let temp_file = if offset > 0 {
+      OpenOptions::new().append(true).open(&self.temp_path).await
+    } else {
+      self.create_temp_file().await
+    };
+    let mut temp_file = temp_file.map_err(|e| Error::FileSystem {
+      message: format!("Failed to create temp file: {e}"),
+    })?;
+
+    // Reserve the remaining bytes so a full disk fails the download now
+    if self.config.preallocate
+      && let Some(total) = content_length
+    {
+      disk::preallocate(
+        &temp_file,
+        &self.temp_path,
+        offset,
+        total.saturating_sub(offset),
+      )?;
+    }
+
+    // Bytes kept for a resume are decompressed before the new ones
+    let mut decoder = match self.decompress {
+      Some(format) => {
+        let mut decoder =
+          decompress::Decoder::create(format, &self.temp_path).await?;
+        if offset > 0 {
+          decoder
+            .write_file(self.source().as_str(), &self.temp_path)
+            .await?;
+        }
+        Some(decoder)
+      }
+      None => None,
+    };
 
     let mut stream = response.bytes_stream();
-    let mut bytes_downloaded = 0u64;
+    let mut bytes_downloaded = offset;
     let mut last_progress_report = Instant::now();
 
     while let Some(chunk_result) = stream.next().await {
-      let chunk = chunk_result.map_err(|e| Error::RequestFailed {
-        url: self.url.to_string(),
-        download: e,
-      })?;
+      let chunk = chunk_result?;
+
+      self.control.checkpoint().await?;
+      self.throttle.acquire(self.host(), chunk.len() as u64).await;
 
       // Write chunk to file
       temp_file
@@ -288,6 +1112,14 @@ impl DownloadTask {
           message: format!("Failed to write to temp file: {e}"),
         })?;
 
+      for hasher in hashers.iter_mut() {
+        hasher.update(&chunk);
+      }
+
+      if let Some(decoder) = &mut decoder {
+        decoder.write(self.source().as_str(), &chunk).await?;
+      }
+
       bytes_downloaded += chunk.len() as u64;
 
       // Report progress periodically
@@ -308,7 +1140,6 @@ impl DownloadTask {
           content_length.map(|c| c as usize),
         );
 
-        // FIXED: Handle closed event sink gracefully
         self
           .event_sink
           .on_event(DownloadEvent::FileProgress {
@@ -316,6 +1147,14 @@ impl DownloadTask {
             bytes_downloaded,
             total_bytes: content_length,
             percentage,
+            decompressed_bytes: decoder.as_ref().map(|d| d.bytes()),
+          })
+          .await;
+
+        self
+          .journal(journal::State::Bytes {
+            downloaded: bytes_downloaded,
+            total: content_length,
           })
           .await;
 
@@ -340,12 +1179,27 @@ impl DownloadTask {
 
     drop(temp_file); // Close file handle
 
+    // The bytes received so far stay in the temp file for a resume
+    if let Some(total) = content_length
+      && bytes_downloaded < total
+    {
+      return Err(Error::interrupted(
+        self.source().as_str(),
+        format!("Body ended after {bytes_downloaded} of {total} bytes"),
+      ));
+    }
+
+    let decompressed_bytes = match decoder {
+      Some(decoder) => Some(decoder.finish(self.source().as_str()).await?),
+      None => None,
+    };
+
     trace!(
       "Task {}: Downloaded {} bytes to {:?}",
       self.index, bytes_downloaded, self.temp_path
     );
 
-    Ok(bytes_downloaded)
+    Ok((bytes_downloaded, decompressed_bytes))
   }
   // async fn download_with_progress(
   //   &self,
@@ -439,21 +1293,135 @@ impl DownloadTask {
   // }
 }
 
+/// Outcome of a download attempt that did not fail.
+#[derive(Debug)]
+enum Outcome {
+  /// The temp file holds the downloaded content
+  Downloaded(Attempt),
+  /// The existing file is up to date
+  NotModified,
+}
+
+/// Outcome of a successful download attempt.
+#[derive(Debug)]
+struct Attempt {
+  /// Bytes in the temp file
+  bytes_downloaded: u64,
+  /// Bytes reused from an earlier attempt
+  resumed_bytes: u64,
+  /// Digests of the temp file, as lowercase hex
+  digests: Vec<(checksum::Algorithm, String)>,
+  /// Validators of the downloaded version of the resource
+  validators: Validators,
+  /// Bytes in the decompressed temp file, if the body was decompressed
+  decompressed_bytes: Option<u64>,
+  /// Whether the temp file was placed from the cache
+  cached: bool,
+}
+
+impl Attempt {
+  /// Creates an attempt outcome, finishing the given hashers.
+  fn new(
+    bytes_downloaded: u64,
+    resumed_bytes: u64,
+    hashers: Vec<checksum::Hasher>,
+    validators: Validators,
+    decompressed_bytes: Option<u64>,
+  ) -> Self {
+    Self {
+      bytes_downloaded,
+      resumed_bytes,
+      digests: hashers
+        .into_iter()
+        .map(|hasher| (hasher.algorithm(), hasher.finalize()))
+        .collect(),
+      validators,
+      decompressed_bytes,
+      cached: false,
+    }
+  }
+
+  /// Returns the digest computed with the given algorithm, if any.
+  fn digest(&self, algorithm: checksum::Algorithm) -> Option<&str> {
+    self
+      .digests
+      .iter()
+      .find(|(a, _)| *a == algorithm)
+      .map(|(_, digest)| digest.as_str())
+  }
+}
+
+/// Returns the requested delay in seconds if the response signals rate
+/// limiting: a 429, or a 503 with a `Retry-After` header.
+pub(crate) fn rate_limit(response: &Response) -> Option<u64> {
+  let retry_after = response
+    .headers()
+    .get(reqwest::header::RETRY_AFTER)
+    .and_then(|v| v.to_str().ok())
+    .and_then(retry::parse_retry_after);
+
+  match response.status() {
+    reqwest::StatusCode::TOO_MANY_REQUESTS => Some(retry_after.unwrap_or(0)),
+    reqwest::StatusCode::SERVICE_UNAVAILABLE => retry_after,
+    _ => None,
+  }
+}
+
+/// Returns true if a mirror may succeed where the current URL failed with
+/// `error`, unlike local failures and limits the file itself exceeds.
+fn fails_over(error: &Error) -> bool {
+  !matches!(
+    error,
+    Error::FileSystem { .. }
+      | Error::FileTooLarge { .. }
+      | Error::InsufficientSpace { .. }
+  )
+}
+
+/// Parses a `Content-Range` header value (`bytes start-end/total`).
+///
+/// Returns the first byte position and the complete length, if known.
+pub(crate) fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+  let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+  let (start, _end) = range.split_once('-')?;
+  let total = match total.trim() {
+    "*" => None,
+    total => Some(total.parse().ok()?),
+  };
+
+  Some((start.trim().parse().ok()?, total))
+}
+
 /// Statistics about a completed download task.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TaskResult {
   /// Task index
   pub index: usize,
   /// Final file path
   pub path: PathBuf,
-  /// Number of bytes downloaded
+  /// Target path before conflict resolution renamed the file
+  pub renamed_from: Option<PathBuf>,
+  /// Number of bytes downloaded, including any resumed bytes
   pub bytes_downloaded: u64,
+  /// Number of bytes reused from a partial download
+  pub resumed_bytes: u64,
   /// Time taken to complete the download
+  #[serde(serialize_with = "crate::report::as_secs")]
   pub duration: Duration,
   /// Number of retry attempts made
   pub retry_count: usize,
   /// Final download speed in bytes per second
   pub final_speed: f64,
+  /// Whether the existing file was up to date and left untouched
+  pub not_modified: bool,
+  /// Size of the final file if the downloaded bytes were decompressed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub decompressed_bytes: Option<u64>,
+  /// Mirror the file was downloaded from instead of its URL
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub mirror: Option<String>,
+  /// Whether the file was served from the shared cache
+  pub cached: bool,
 }
 
 /// Executes download tasks with configurable concurrency control.
@@ -464,6 +1432,11 @@ pub struct TaskResult {
 pub struct TaskExecutor {
   /// Optional concurrency limit
   concurrency_limit: Option<usize>,
+  /// Bandwidth limiter shared by all tasks, replacing their own if set
+  throttle: Option<Throttle>,
+  /// Cancellation and pause handle shared by all tasks, replacing their own
+  /// if set
+  control: Option<Control>,
 }
 
 impl TaskExecutor {
@@ -474,7 +1447,23 @@ impl TaskExecutor {
   /// * `concurrency_limit` - Maximum number of concurrent downloads (None =
   ///   unlimited)
   pub fn new(concurrency_limit: Option<usize>) -> Self {
-    Self { concurrency_limit }
+    Self {
+      concurrency_limit,
+      throttle: None,
+      control: None,
+    }
+  }
+
+  /// Sets the bandwidth limiter shared by all tasks of this executor.
+  pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+    self.throttle = Some(throttle);
+    self
+  }
+
+  /// Sets the cancellation and pause handle watched by all tasks.
+  pub fn with_control(mut self, control: Control) -> Self {
+    self.control = Some(control);
+    self
   }
 
   /// Executes a batch of download tasks with progress monitoring.
@@ -494,6 +1483,20 @@ impl TaskExecutor {
     &self,
     tasks: Vec<DownloadTask>,
   ) -> Vec<Result<TaskResult>> {
+    self
+      .run(tasks)
+      .await
+      .into_iter()
+      .map(|result| result.map_err(|failure| failure.error))
+      .collect()
+  }
+
+  /// Executes a batch of download tasks, describing each failure with the
+  /// number of attempts made and the time spent.
+  pub(crate) async fn run(
+    &self,
+    tasks: Vec<DownloadTask>,
+  ) -> Vec<std::result::Result<TaskResult, report::Failure>> {
     if tasks.is_empty() {
       return Vec::new();
     }
@@ -515,7 +1518,7 @@ impl TaskExecutor {
     &self,
     tasks: Vec<DownloadTask>,
     limit: usize,
-  ) -> Vec<Result<TaskResult>> {
+  ) -> Vec<std::result::Result<TaskResult, report::Failure>> {
     let semaphore = Arc::new(Semaphore::new(limit));
     let mut handles = Vec::with_capacity(tasks.len());
 
@@ -525,13 +1528,20 @@ impl TaskExecutor {
       tasks.len()
     );
 
-    for task in tasks {
+    for mut task in tasks {
+      task.semaphore = Some(semaphore.clone());
+      if let Some(throttle) = &self.throttle {
+        task.throttle = throttle.clone();
+      }
+      if let Some(control) = &self.control {
+        task.control = control.clone();
+      }
       let permit = semaphore.clone();
       let handle = tokio::spawn(async move {
         let _permit = permit.acquire().await.unwrap();
         let task_index = task.index; // ✅ Store index before moving task
         debug!("Task {} acquired semaphore permit", task_index);
-        let result = task.execute().await;
+        let result = task.run().await;
         debug!("Task {} released semaphore permit", task_index); // ✅ Use stored index
         result
       });
@@ -546,10 +1556,11 @@ impl TaskExecutor {
         Ok(task_result) => results.push(task_result),
         Err(join_error) => {
           error!("Task {} panicked: {}", index, join_error);
-          results.push(Err(Error::TaskFailed {
+          let error = Error::TaskFailed {
             index,
             reason: join_error.to_string(),
-          }));
+          };
+          results.push(Err(report::Failure::new(error, 0, Duration::ZERO)));
         }
       }
     }
@@ -561,14 +1572,20 @@ impl TaskExecutor {
   async fn execute_unlimited(
     &self,
     tasks: Vec<DownloadTask>,
-  ) -> Vec<Result<TaskResult>> {
+  ) -> Vec<std::result::Result<TaskResult, report::Failure>> {
     let mut handles = Vec::with_capacity(tasks.len());
 
     debug!("Executing {} tasks with unlimited concurrency", tasks.len());
 
-    for task in tasks {
+    for mut task in tasks {
+      if let Some(throttle) = &self.throttle {
+        task.throttle = throttle.clone();
+      }
+      if let Some(control) = &self.control {
+        task.control = control.clone();
+      }
       let handle = tokio::spawn(async move {
-        task.execute().await // ✅ Simple - no need to access task after this
+        task.run().await // ✅ Simple - no need to access task after this
       });
       handles.push(handle);
     }
@@ -579,10 +1596,11 @@ impl TaskExecutor {
         Ok(task_result) => results.push(task_result),
         Err(join_error) => {
           error!("Task {} panicked: {}", index, join_error);
-          results.push(Err(Error::TaskFailed {
+          let error = Error::TaskFailed {
             index,
             reason: join_error.to_string(),
-          }));
+          };
+          results.push(Err(report::Failure::new(error, 0, Duration::ZERO)));
         }
       }
     }
@@ -612,6 +1630,12 @@ impl TaskExecutor {
     let mut all_results = Vec::with_capacity(tasks.len());
 
     for (batch_num, batch) in tasks.chunks(batch_size).enumerate() {
+      // Tasks of batches not yet started are cancelled without running
+      if self.control.as_ref().is_some_and(Control::is_cancelled) {
+        all_results.extend(batch.iter().map(|_| Err(Error::Cancelled)));
+        continue;
+      }
+
       debug!(
         "Processing batch {} with {} tasks",
         batch_num + 1,
@@ -640,9 +1664,19 @@ pub struct TaskBuilder {
   final_path: Option<PathBuf>,
   index: usize,
   client: Option<reqwest::Client>,
+  transport: Option<Arc<dyn Transport>>,
   config: Option<crate::Config>,
   progress_tx: Option<progress::Sender>,
   event_sink: Option<Arc<dyn EventSink>>,
+  semaphore: Option<Arc<Semaphore>>,
+  checksum: Option<Checksum>,
+  renamed_from: Option<PathBuf>,
+  retry_budget: RetryBudget,
+  throttle: Throttle,
+  control: Control,
+  journal: Option<Journal>,
+  decompress: Option<Decompression>,
+  mirrors: Vec<reqwest::Url>,
 }
 
 impl TaskBuilder {
@@ -654,9 +1688,19 @@ impl TaskBuilder {
       final_path: None,
       index: 0,
       client: None,
+      transport: None,
       config: None,
       progress_tx: None,
       event_sink: None,
+      semaphore: None,
+      checksum: None,
+      renamed_from: None,
+      retry_budget: RetryBudget::unlimited(),
+      throttle: Throttle::unlimited(),
+      control: Control::new(),
+      journal: None,
+      decompress: None,
+      mirrors: Vec::new(),
     }
   }
 
@@ -684,12 +1728,18 @@ impl TaskBuilder {
     self
   }
 
-  /// Sets the HTTP client.
+  /// Sets the HTTP client, used for HTTP(S) URLs unless a transport is set.
   pub fn client(mut self, client: reqwest::Client) -> Self {
     self.client = Some(client);
     self
   }
 
+  /// Sets the transport fetching the URL.
+  pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+    self.transport = Some(transport);
+    self
+  }
+
   /// Sets the configuration.
   pub fn config(mut self, config: Config) -> Self {
     self.config = Some(config);
@@ -708,6 +1758,60 @@ impl TaskBuilder {
     self
   }
 
+  /// Sets the semaphore limiting concurrent segment downloads.
+  pub fn semaphore(mut self, semaphore: Arc<Semaphore>) -> Self {
+    self.semaphore = Some(semaphore);
+    self
+  }
+
+  /// Sets the expected checksum of the downloaded file.
+  pub fn checksum(mut self, checksum: Checksum) -> Self {
+    self.checksum = Some(checksum);
+    self
+  }
+
+  /// Sets the retry budget shared with the other tasks of a batch.
+  pub fn retry_budget(mut self, budget: RetryBudget) -> Self {
+    self.retry_budget = budget;
+    self
+  }
+
+  /// Sets the bandwidth limiter.
+  pub fn throttle(mut self, throttle: Throttle) -> Self {
+    self.throttle = throttle;
+    self
+  }
+
+  /// Sets the cancellation and pause handle.
+  pub fn control(mut self, control: Control) -> Self {
+    self.control = control;
+    self
+  }
+
+  /// Sets the journal of the batch.
+  pub fn journal(mut self, journal: Journal) -> Self {
+    self.journal = Some(journal);
+    self
+  }
+
+  /// Sets the format to decompress the body from.
+  pub fn decompress(mut self, format: Decompression) -> Self {
+    self.decompress = Some(format);
+    self
+  }
+
+  /// Adds a mirror to fall back to once the URL fails.
+  pub fn mirror(mut self, url: reqwest::Url) -> Self {
+    self.mirrors.push(url);
+    self
+  }
+
+  /// Sets the target path the final path was renamed from.
+  pub fn renamed_from<P: Into<PathBuf>>(mut self, path: P) -> Self {
+    self.renamed_from = Some(path.into());
+    self
+  }
+
   /// Builds the download task.
   ///
   /// # Errors
@@ -723,12 +1827,18 @@ impl TaskBuilder {
     let final_path = self.final_path.ok_or_else(|| Error::Configuration {
       message: "Final path is required".to_string(),
     })?;
-    let client = self.client.ok_or_else(|| Error::Configuration {
-      message: "HTTP client is required".to_string(),
-    })?;
     let config = self.config.ok_or_else(|| Error::Configuration {
       message: "Configuration is required".to_string(),
     })?;
+    let transport = match (self.transport, self.client) {
+      (Some(transport), _) => transport,
+      (None, Some(client)) => Arc::new(Transports::new(client, &config)),
+      (None, None) => {
+        return Err(Error::Configuration {
+          message: "HTTP client or transport is required".to_string(),
+        });
+      }
+    };
     let progress_tx = self.progress_tx.ok_or_else(|| Error::Configuration {
       message: "Progress sender is required".to_string(),
     })?;
@@ -737,14 +1847,25 @@ impl TaskBuilder {
     })?;
 
     Ok(DownloadTask {
+      original_url: url.to_string(),
       url,
       temp_path,
       final_path,
       index: self.index,
-      client,
+      transport,
       config,
       progress_tx,
       event_sink,
+      semaphore: self.semaphore,
+      checksum: self.checksum,
+      renamed_from: self.renamed_from,
+      retry_budget: self.retry_budget,
+      throttle: self.throttle,
+      control: self.control,
+      journal: self.journal,
+      decompress: self.decompress,
+      mirrors: self.mirrors,
+      mirror: None,
     })
   }
 }
@@ -797,6 +1918,335 @@ mod tests {
     assert!(results.is_empty());
   }
 
+  #[tokio::test]
+  async fn test_cancelled_task_keeps_partial() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path().join("file.part");
+    tokio::fs::write(&temp_path, b"partial").await.unwrap();
+
+    let control = Control::new();
+    control.cancel();
+
+    let task = TaskBuilder::new()
+      .url(reqwest::Url::parse("https://example.com/file").unwrap())
+      .temp_path(&temp_path)
+      .final_path(temp_dir.path().join("file"))
+      .client(reqwest::Client::new())
+      .config(Config::default())
+      .progress_sender(Reporter::new(1).sender())
+      .event_sink(Arc::new(NoOpEventSink))
+      .control(control)
+      .build()
+      .unwrap();
+
+    assert!(matches!(task.execute().await, Err(Error::Cancelled)));
+    assert!(temp_path.exists());
+  }
+
+  #[tokio::test]
+  async fn test_unauthorized_is_authentication_error() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      while let Ok((mut stream, _)) = listener.accept().await {
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf).await;
+        let _ = stream
+          .write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n")
+          .await;
+      }
+    });
+
+    let temp_dir = TempDir::new().unwrap();
+    let task = TaskBuilder::new()
+      .url(reqwest::Url::parse(&format!("http://{addr}/file")).unwrap())
+      .temp_path(temp_dir.path().join("file.part"))
+      .final_path(temp_dir.path().join("file"))
+      .client(reqwest::Client::new())
+      .config(Config::default())
+      .progress_sender(Reporter::new(1).sender())
+      .event_sink(Arc::new(NoOpEventSink))
+      .build()
+      .unwrap();
+
+    let failure = task.run().await.unwrap_err();
+    assert!(matches!(failure.error, Error::Authentication { .. }));
+    assert_eq!(failure.attempts, 1);
+    assert!(!failure.suggested_actions.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_executor_keeps_task_throttle() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(url, Fixture::new(vec![0u8; 10_000]));
+
+    let dir = TempDir::new().unwrap();
+    let throttle = Throttle::new(Some(10_000));
+    let task = offline_task(transport, url, Config::default(), &dir)
+      .throttle(throttle.clone())
+      .build()
+      .unwrap();
+
+    let results = TaskExecutor::new(Some(1)).execute(vec![task]).await;
+    assert!(results[0].is_ok());
+
+    // The download drained the bucket of the task's own throttle
+    assert!(throttle.acquire("example.com", 5_000).await);
+  }
+
+  #[tokio::test]
+  async fn test_executor_keeps_task_control() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(url, Fixture::new("0123456789"));
+
+    let dir = TempDir::new().unwrap();
+    let control = Control::new();
+    control.cancel();
+    let task = offline_task(transport, url, Config::default(), &dir)
+      .control(control)
+      .build()
+      .unwrap();
+
+    let results = TaskExecutor::new(None).execute(vec![task]).await;
+    assert!(matches!(results[0], Err(Error::Cancelled)));
+    assert!(!dir.path().join("file").exists());
+  }
+
+  /// Builds a task downloading `url` through `transport` into `dir`.
+  fn offline_task(
+    transport: Arc<MemoryTransport>,
+    url: &str,
+    config: Config,
+    dir: &TempDir,
+  ) -> TaskBuilder {
+    TaskBuilder::new()
+      .url(reqwest::Url::parse(url).unwrap())
+      .temp_path(dir.path().join("file.part"))
+      .final_path(dir.path().join("file"))
+      .transport(transport)
+      .config(config)
+      .progress_sender(Reporter::new(1).sender())
+      .event_sink(Arc::new(NoOpEventSink))
+  }
+
+  #[tokio::test]
+  async fn test_retries_and_resumes_offline() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new("0123456789")
+        .header(reqwest::header::ETAG, "\"v1\"")
+        .fault(Fault::Status(503))
+        .fault(Fault::Refused)
+        .fault(Fault::Truncate(4)),
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = Config::builder()
+      .max_retries(5)
+      .retry_delay(Duration::from_millis(1))
+      .build();
+    let task = offline_task(transport.clone(), url, config, &temp_dir)
+      .build()
+      .unwrap();
+
+    let result = task.execute().await.unwrap();
+    assert_eq!(result.retry_count, 3);
+    assert_eq!(result.resumed_bytes, 4);
+    assert_eq!(std::fs::read(&result.path).unwrap(), b"0123456789");
+
+    let last = transport.requests().pop().unwrap();
+    assert_eq!(last.headers[reqwest::header::RANGE], "bytes=4-");
+  }
+
+  #[tokio::test]
+  async fn test_fails_over_to_mirror_offline() {
+    let url = "https://example.com/file";
+    let mirror = "https://mirror.example.org/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new("0123456789")
+        .header(reqwest::header::ETAG, "\"v1\"")
+        .fault(Fault::Truncate(4))
+        .fault(Fault::Status(404)),
+    );
+    transport.insert(
+      mirror,
+      Fixture::new("0123456789").header(reqwest::header::ETAG, "\"v1\""),
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    let sink = Arc::new(CollectingEventSink::new());
+    let config = Config::builder()
+      .max_retries(3)
+      .retry_delay(Duration::from_millis(1))
+      .build();
+    let task = offline_task(transport.clone(), url, config, &temp_dir)
+      .mirror(reqwest::Url::parse(mirror).unwrap())
+      .event_sink(sink.clone())
+      .build()
+      .unwrap();
+
+    // The 404 cannot be retried, so the mirror resumes the partial file
+    let result = task.execute().await.unwrap();
+    assert_eq!(result.mirror.as_deref(), Some(mirror));
+    assert_eq!(result.retry_count, 2);
+    assert_eq!(result.resumed_bytes, 4);
+    assert_eq!(std::fs::read(&result.path).unwrap(), b"0123456789");
+
+    let last = transport.requests().pop().unwrap();
+    assert_eq!(last.url.as_str(), mirror);
+    assert_eq!(last.headers[reqwest::header::RANGE], "bytes=4-");
+
+    assert!(sink.events().iter().any(|event| matches!(
+      event,
+      DownloadEvent::FileCompleted { mirror: Some(used), .. } if used == mirror
+    )));
+  }
+
+  #[tokio::test]
+  async fn test_progress_reported_offline() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(url, Fixture::new("0123456789").chunk_size(3));
+
+    let temp_dir = TempDir::new().unwrap();
+    let sink = Arc::new(CollectingEventSink::new());
+    let config = Config::builder().progress_interval(Duration::ZERO).build();
+    let task = offline_task(transport, url, config, &temp_dir)
+      .event_sink(sink.clone())
+      .build()
+      .unwrap();
+    task.execute().await.unwrap();
+
+    let progress: Vec<_> = sink
+      .events()
+      .into_iter()
+      .filter_map(|event| match event {
+        DownloadEvent::FileProgress {
+          bytes_downloaded,
+          total_bytes,
+          ..
+        } => Some((bytes_downloaded, total_bytes)),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(
+      progress,
+      [(3, Some(10)), (6, Some(10)), (9, Some(10)), (10, Some(10))]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_size_limit_enforced_offline() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new("0123456789")
+        .chunk_size(3)
+        .without_content_length(),
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = Config::builder().max_file_size(Some(5)).build();
+    let task = offline_task(transport, url, config, &temp_dir)
+      .build()
+      .unwrap();
+
+    let failure = task.run().await.unwrap_err();
+    assert!(matches!(
+      failure.error,
+      Error::FileTooLarge {
+        size: 6,
+        max_size: 5
+      }
+    ));
+    assert_eq!(failure.attempts, 1);
+  }
+
+  #[tokio::test]
+  async fn test_segmented_download_offline() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new("0123456789abcdef").header(reqwest::header::ETAG, "\"v1\""),
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = Config::builder().segments(4).min_segment_size(4).build();
+    let task = offline_task(transport.clone(), url, config, &temp_dir)
+      .build()
+      .unwrap();
+
+    let result = task.execute().await.unwrap();
+    assert_eq!(std::fs::read(&result.path).unwrap(), b"0123456789abcdef");
+
+    let ranged = transport
+      .requests()
+      .iter()
+      .filter(|request| request.headers.contains_key(reqwest::header::RANGE))
+      .count();
+    assert_eq!(ranged, 4);
+  }
+
+  #[tokio::test]
+  async fn test_segment_retried_and_resumed_offline() {
+    let url = "https://example.com/file";
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      url,
+      Fixture::new("0123456789abcdef")
+        .header(reqwest::header::ETAG, "\"v1\"")
+        .fault(Fault::Truncate(2)),
+    );
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = Config::builder()
+      .segments(4)
+      .min_segment_size(4)
+      .retry_delay(Duration::from_millis(1))
+      .build();
+    let task = offline_task(transport.clone(), url, config, &temp_dir)
+      .build()
+      .unwrap();
+
+    let result = task.execute().await.unwrap();
+    assert_eq!(result.retry_count, 1);
+    assert_eq!(std::fs::read(&result.path).unwrap(), b"0123456789abcdef");
+
+    // The truncated segment continues after the bytes it kept
+    let resumed = transport.requests().iter().any(|request| {
+      request
+        .headers
+        .get(reqwest::header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.parse::<u64>().ok())
+        .is_some_and(|start| start % 4 == 2)
+    });
+    assert!(resumed);
+  }
+
+  #[test]
+  fn test_parse_content_range() {
+    assert_eq!(
+      parse_content_range("bytes 100-199/200"),
+      Some((100, Some(200)))
+    );
+    assert_eq!(parse_content_range("bytes 0-99/*"), Some((0, None)));
+    assert_eq!(parse_content_range("bytes */200"), None);
+    assert_eq!(parse_content_range("items 0-1/2"), None);
+  }
+
   #[test]
   fn test_task_builder_validation() {
     let result = TaskBuilder::new().build();
@@ -0,0 +1,270 @@
+//! Bandwidth throttling shared by concurrent downloads
+//!
+//! A [`Throttle`] holds token buckets for a global bytes-per-second cap and
+//! optional per-host caps. Every download task of a batch draws from the same
+//! buckets before writing a chunk, and the limits can be changed while the
+//! batch is running.
+
+use crate::*;
+use std::{
+  collections::HashMap,
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering}
+  },
+  time::{Duration, Instant}
+};
+use tokio::time::sleep;
+
+/// Longest single sleep while waiting for tokens, so that limit changes
+/// take effect promptly.
+const MAX_WAIT_SLICE: Duration = Duration::from_millis(100);
+
+/// A token bucket refilled at a fixed rate.
+///
+/// Requests are charged up front and may drive the balance negative; the
+/// caller then waits until the bucket has refilled the deficit.
+#[derive(Debug)]
+struct TokenBucket {
+  /// Refill rate in bytes per second
+  rate: u64,
+  /// Current balance in bytes
+  tokens: f64,
+  /// Time of the last refill
+  last_refill: Instant
+}
+
+impl TokenBucket {
+  fn new(rate: u64) -> Self {
+    Self {
+      rate: rate.max(1),
+      tokens: rate.max(1) as f64,
+      last_refill: Instant::now()
+    }
+  }
+
+  /// Changes the rate, keeping any deficit.
+  fn set_rate(&mut self, rate: u64) {
+    self.refill();
+    self.rate = rate.max(1);
+    self.tokens = self.tokens.min(self.rate as f64);
+  }
+
+  /// Adds the tokens accrued since the last refill, up to one second's worth.
+  fn refill(&mut self) {
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+    self.tokens =
+      (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+    self.last_refill = now;
+  }
+
+  /// Charges `bytes` against the bucket.
+  fn consume(&mut self, bytes: u64) {
+    self.refill();
+    self.tokens -= bytes as f64;
+  }
+
+  /// Returns how long until the balance is no longer negative.
+  fn wait_time(&mut self) -> Duration {
+    self.refill();
+    if self.tokens >= 0.0 {
+      Duration::ZERO
+    } else {
+      Duration::from_secs_f64(-self.tokens / self.rate as f64)
+    }
+  }
+}
+
+/// Shared state behind a [`Throttle`] handle.
+#[derive(Debug, Default)]
+struct Inner {
+  /// Bucket for all downloads together
+  global: Mutex<Option<TokenBucket>>,
+  /// Buckets for individual hosts
+  hosts: Mutex<HashMap<String, TokenBucket>>,
+  /// Number of downloads currently waiting for tokens
+  throttled: AtomicUsize
+}
+
+/// Global and per-host bandwidth limiter.
+///
+/// Clones share the same buckets, so a handle kept by the caller can adjust
+/// the limits of a running batch.
+///
+/// # Examples
+///
+/// ```rust
+/// use downloader::Throttle;
+///
+/// let throttle = Throttle::new(Some(10 * 1024 * 1024));
+/// throttle.set_host_limit("datasets.imdbws.com", Some(2 * 1024 * 1024));
+/// assert_eq!(throttle.global_limit(), Some(10 * 1024 * 1024));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+  inner: Arc<Inner>
+}
+
+impl Throttle {
+  /// Creates a throttle with an optional global limit in bytes per second.
+  pub fn new(global_limit: Option<u64>) -> Self {
+    let throttle = Self::default();
+    throttle.set_global_limit(global_limit);
+    throttle
+  }
+
+  /// Creates a throttle without any limit.
+  pub fn unlimited() -> Self {
+    Self::default()
+  }
+
+  /// Creates a throttle from the bandwidth limits of a configuration.
+  pub fn from_config(config: &Config) -> Self {
+    let throttle = Self::new(config.bandwidth_limit);
+    for (host, limit) in &config.host_bandwidth_limits {
+      throttle.set_host_limit(host, Some(*limit));
+    }
+    throttle
+  }
+
+  /// Sets or removes the global limit in bytes per second.
+  pub fn set_global_limit(&self, limit: Option<u64>) {
+    let mut global = lock(&self.inner.global);
+    match (global.as_mut(), limit) {
+      (Some(bucket), Some(rate)) => bucket.set_rate(rate),
+      (None, Some(rate)) => *global = Some(TokenBucket::new(rate)),
+      (_, None) => *global = None
+    }
+  }
+
+  /// Sets or removes the limit in bytes per second for one host.
+  pub fn set_host_limit(&self, host: &str, limit: Option<u64>) {
+    let host = host.to_ascii_lowercase();
+    let mut hosts = lock(&self.inner.hosts);
+    match limit {
+      Some(rate) => match hosts.get_mut(&host) {
+        Some(bucket) => bucket.set_rate(rate),
+        None => {
+          hosts.insert(host, TokenBucket::new(rate));
+        }
+      },
+      None => {
+        hosts.remove(&host);
+      }
+    }
+  }
+
+  /// Returns the global limit in bytes per second, if any.
+  pub fn global_limit(&self) -> Option<u64> {
+    lock(&self.inner.global).as_ref().map(|bucket| bucket.rate)
+  }
+
+  /// Returns the limit in bytes per second for a host, if any.
+  pub fn host_limit(&self, host: &str) -> Option<u64> {
+    lock(&self.inner.hosts)
+      .get(&host.to_ascii_lowercase())
+      .map(|bucket| bucket.rate)
+  }
+
+  /// Returns true if neither a global nor a host limit is set.
+  pub fn is_unlimited(&self) -> bool {
+    lock(&self.inner.global).is_none() && lock(&self.inner.hosts).is_empty()
+  }
+
+  /// Returns the number of downloads currently waiting for bandwidth.
+  pub fn throttled(&self) -> usize {
+    self.inner.throttled.load(Ordering::Relaxed)
+  }
+
+  /// Waits until `bytes` from `host` fit within the limits.
+  ///
+  /// Returns true if the caller had to wait.
+  pub async fn acquire(&self, host: &str, bytes: u64) -> bool {
+    let host = host.to_ascii_lowercase();
+    if let Some(bucket) = lock(&self.inner.global).as_mut() {
+      bucket.consume(bytes);
+    }
+    if let Some(bucket) = lock(&self.inner.hosts).get_mut(&host) {
+      bucket.consume(bytes);
+    }
+
+    let mut waited = false;
+    loop {
+      let wait = self.wait_time(&host);
+      if wait.is_zero() {
+        break;
+      }
+
+      if !waited {
+        waited = true;
+        self.inner.throttled.fetch_add(1, Ordering::Relaxed);
+      }
+      sleep(wait.min(MAX_WAIT_SLICE)).await;
+    }
+
+    if waited {
+      self.inner.throttled.fetch_sub(1, Ordering::Relaxed);
+    }
+    waited
+  }
+
+  /// Returns how long a download from `host` has to wait for its deficit.
+  fn wait_time(&self, host: &str) -> Duration {
+    let global = lock(&self.inner.global)
+      .as_mut()
+      .map_or(Duration::ZERO, TokenBucket::wait_time);
+    let host = lock(&self.inner.hosts)
+      .get_mut(host)
+      .map_or(Duration::ZERO, TokenBucket::wait_time);
+    global.max(host)
+  }
+}
+
+/// Locks a mutex, recovering the data if another thread panicked with it.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_limits_are_adjustable() {
+    let throttle = Throttle::unlimited();
+    assert!(throttle.is_unlimited());
+
+    throttle.set_global_limit(Some(1024));
+    throttle.set_host_limit("Example.com", Some(512));
+    assert_eq!(throttle.global_limit(), Some(1024));
+    assert_eq!(throttle.host_limit("example.com"), Some(512));
+
+    throttle.set_global_limit(None);
+    throttle.set_host_limit("example.com", None);
+    assert!(throttle.is_unlimited());
+  }
+
+  #[tokio::test]
+  async fn test_acquire_waits_for_deficit() {
+    let throttle = Throttle::new(Some(10_000));
+
+    // The initial burst covers one second's worth of bytes
+    assert!(!throttle.acquire("example.com", 10_000).await);
+
+    let start = Instant::now();
+    assert!(throttle.acquire("example.com", 1_000).await);
+    assert!(start.elapsed() >= Duration::from_millis(90));
+    assert_eq!(throttle.throttled(), 0);
+  }
+
+  #[tokio::test]
+  async fn test_host_limit_applies_to_host_only() {
+    let throttle = Throttle::unlimited();
+    throttle.set_host_limit("slow.example.com", Some(1_000));
+
+    assert!(!throttle.acquire("fast.example.com", 1_000_000).await);
+    assert!(!throttle.acquire("slow.example.com", 1_000).await);
+    assert!(throttle.acquire("slow.example.com", 100).await);
+  }
+}
@@ -0,0 +1,852 @@
+//! Transports carrying download requests
+//!
+//! A [`Transport`] sends a [`Request`] and returns the status, headers and
+//! body stream of the [`Response`], using HTTP semantics whatever the URL
+//! scheme. Download tasks only talk to a transport, so retries, resumes,
+//! progress reporting and size limits work the same for every source:
+//!
+//! - [`HttpTransport`] sends requests with the configured `reqwest` client
+//! - [`FileTransport`] serves `file://` URLs from the local file system, e.g. a
+//!   mirror of a dataset copied onto an air-gapped machine
+//! - [`MemoryTransport`] serves [`Fixture`]s from memory and can script
+//!   failures, slow chunks and truncated bodies, so downloads can be tested
+//!   offline
+//!
+//! [`Transports`] routes each request to the first transport supporting its
+//! URL.
+
+use crate::*;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use reqwest::{
+  Method, StatusCode,
+  header::{
+    ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, HeaderMap, HeaderName,
+    HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+    RANGE
+  }
+};
+use std::{
+  collections::{HashMap, VecDeque},
+  fmt::{self, Debug},
+  io::{ErrorKind as IoErrorKind, SeekFrom},
+  ops::Range,
+  path::PathBuf,
+  pin::Pin,
+  sync::{Arc, Mutex}
+};
+use tokio::{
+  fs::File,
+  io::{AsyncReadExt, AsyncSeekExt}
+};
+
+/// Stream of the chunks of a response body.
+pub type Body = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+/// Size of the chunks read from local files.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Sends requests for the URLs it supports.
+#[async_trait]
+pub trait Transport: Send + Sync + Debug {
+  /// Returns true if this transport can fetch `url`.
+  fn supports(&self, url: &reqwest::Url) -> bool;
+
+  /// Sends a request, returning the response once its headers are in.
+  ///
+  /// Error statuses are returned as responses; `Err` means that no
+  /// response was received.
+  async fn send(&self, request: Request) -> Result<Response>;
+}
+
+/// A request sent through a [`Transport`].
+#[derive(Debug, Clone)]
+pub struct Request {
+  /// Request method, `GET` or `HEAD`
+  pub method: Method,
+
+  /// URL of the resource
+  pub url: reqwest::Url,
+
+  /// Request headers
+  pub headers: HeaderMap,
+
+  /// Credentials to authenticate with
+  pub credentials: Option<Credentials>
+}
+
+impl Request {
+  /// Creates a request without headers or credentials.
+  pub fn new(method: Method, url: reqwest::Url) -> Self {
+    Self {
+      method,
+      url,
+      headers: HeaderMap::new(),
+      credentials: None
+    }
+  }
+
+  /// Creates a request with the configured custom headers and the
+  /// credentials for `url`.
+  pub(crate) fn with_config(
+    method: Method,
+    url: &reqwest::Url,
+    config: &Config
+  ) -> Self {
+    let mut request = Self::new(method, url.clone());
+    for (key, value) in &config.custom_headers {
+      match (HeaderName::try_from(key), HeaderValue::try_from(value)) {
+        (Ok(name), Ok(value)) => {
+          request.headers.append(name, value);
+        }
+        _ => warn!("Ignoring invalid header '{}'", key)
+      }
+    }
+    request.credentials = auth::credentials(&config.credentials, url);
+    request
+  }
+
+  /// Adds a header, ignoring values that are not valid header values.
+  pub fn header<V: AsRef<str>>(mut self, name: HeaderName, value: V) -> Self {
+    if let Ok(value) = HeaderValue::from_str(value.as_ref()) {
+      self.headers.append(name, value);
+    }
+    self
+  }
+
+  /// Adds several headers.
+  pub fn headers(mut self, headers: HeaderMap) -> Self {
+    self.headers.extend(headers);
+    self
+  }
+
+  /// Returns the value of a request header, if it is valid text.
+  fn header_str(&self, name: HeaderName) -> Option<&str> {
+    self.headers.get(name).and_then(|v| v.to_str().ok())
+  }
+}
+
+/// A response received through a [`Transport`].
+pub struct Response {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Body
+}
+
+impl Response {
+  /// Creates a response.
+  pub fn new(status: StatusCode, headers: HeaderMap, body: Body) -> Self {
+    Self {
+      status,
+      headers,
+      body
+    }
+  }
+
+  /// Creates a response without headers or body.
+  pub fn empty(status: StatusCode) -> Self {
+    Self::new(status, HeaderMap::new(), Box::pin(stream::empty()))
+  }
+
+  /// Returns the status code.
+  pub fn status(&self) -> StatusCode {
+    self.status
+  }
+
+  /// Returns the response headers.
+  pub fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  /// Returns the length of the body from the `Content-Length` header.
+  pub fn content_length(&self) -> Option<u64> {
+    self
+      .headers
+      .get(CONTENT_LENGTH)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.trim().parse().ok())
+  }
+
+  /// Returns the body as a stream of chunks.
+  pub fn bytes_stream(self) -> Body {
+    self.body
+  }
+}
+
+impl Debug for Response {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Response")
+      .field("status", &self.status)
+      .field("headers", &self.headers)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Routes requests to the first transport supporting their URL.
+#[derive(Debug, Clone)]
+pub struct Transports {
+  transports: Vec<Arc<dyn Transport>>
+}
+
+impl Transports {
+  /// Creates a router sending HTTP(S) requests with `client` and serving
+  /// `file://` URLs from the local file system.
+  pub fn new(client: ReqwestClient, config: &Config) -> Self {
+    Self {
+      transports: vec![
+        Arc::new(HttpTransport::new(client, config)),
+        Arc::new(FileTransport),
+      ]
+    }
+  }
+
+  /// Adds a transport that takes precedence over those added before.
+  pub fn with(mut self, transport: Arc<dyn Transport>) -> Self {
+    self.transports.insert(0, transport);
+    self
+  }
+}
+
+#[async_trait]
+impl Transport for Transports {
+  fn supports(&self, url: &reqwest::Url) -> bool {
+    self.transports.iter().any(|t| t.supports(url))
+  }
+
+  async fn send(&self, request: Request) -> Result<Response> {
+    match self.transports.iter().find(|t| t.supports(&request.url)) {
+      Some(transport) => transport.send(request).await,
+      None => Err(Error::InvalidUrl {
+        url: request.url.to_string(),
+        reason: format!("No transport for scheme '{}'", request.url.scheme())
+      })
+    }
+  }
+}
+
+/// Sends HTTP(S) requests with a `reqwest` client.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+  client: ReqwestClient,
+  config: Arc<Config>
+}
+
+impl HttpTransport {
+  /// Creates a transport using `client`, mapping failed requests with the
+  /// timeouts and redirect limit of `config`.
+  pub fn new(client: ReqwestClient, config: &Config) -> Self {
+    Self {
+      client,
+      config: Arc::new(config.clone())
+    }
+  }
+
+  /// Creates a transport with a client built from `config`.
+  ///
+  /// # Errors
+  ///
+  /// Returns `Error::Configuration` if the client cannot be built.
+  pub fn from_config(config: &Config) -> Result<Self> {
+    Ok(Self::new(build_client(config)?, config))
+  }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+  fn supports(&self, url: &reqwest::Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+  }
+
+  async fn send(&self, request: Request) -> Result<Response> {
+    let url = request.url.to_string();
+    let mut builder = self
+      .client
+      .request(request.method, request.url)
+      .headers(request.headers);
+    if let Some(credentials) = &request.credentials {
+      builder = credentials.apply(builder);
+    }
+
+    let response = builder
+      .send()
+      .await
+      .map_err(|e| client::request_error(&self.config, &url, e))?;
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let config = self.config.clone();
+    let body = response
+      .bytes_stream()
+      .map_err(move |e| client::request_error(&config, &url, e));
+
+    Ok(Response::new(status, headers, Box::pin(body)))
+  }
+}
+
+/// Serves `file://` URLs from the local file system.
+///
+/// Files are streamed in chunks and support byte ranges, and their
+/// modification time is sent as `Last-Modified` date, so partial copies
+/// resume and up-to-date files are skipped as with an HTTP server. Missing
+/// files are answered with 404 and unreadable ones with 403.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileTransport;
+
+#[async_trait]
+impl Transport for FileTransport {
+  fn supports(&self, url: &reqwest::Url) -> bool {
+    url.scheme() == "file"
+  }
+
+  async fn send(&self, request: Request) -> Result<Response> {
+    let path = request.url.to_file_path().map_err(|()| Error::InvalidUrl {
+      url: request.url.to_string(),
+      reason: "Not a local file path".to_string()
+    })?;
+
+    let metadata = match tokio::fs::metadata(&path).await {
+      Ok(metadata) if metadata.is_file() => metadata,
+      Ok(_) => return Ok(Response::empty(StatusCode::NOT_FOUND)),
+      Err(e) if e.kind() == IoErrorKind::NotFound =>
+        return Ok(Response::empty(StatusCode::NOT_FOUND)),
+      Err(e) if e.kind() == IoErrorKind::PermissionDenied =>
+        return Ok(Response::empty(StatusCode::FORBIDDEN)),
+      Err(e) =>
+        return Err(Error::FileSystem {
+          message: format!("Failed to read '{}': {e}", path.display())
+        }),
+    };
+
+    let mut headers = HeaderMap::new();
+    if let Ok(modified) = metadata.modified()
+      && let Some(date) = Validators::from_modified(modified).last_modified
+      && let Ok(value) = HeaderValue::from_str(&date)
+    {
+      headers.insert(LAST_MODIFIED, value);
+    }
+
+    let served = Served::new(&request, metadata.len(), headers, true);
+    let body = file_body(path, served.body_range(&request));
+    Ok(Response::new(served.status, served.headers, body))
+  }
+}
+
+/// Streams the `range` of a local file in chunks.
+fn file_body(path: PathBuf, range: Range<u64>) -> Body {
+  let state = (path, None::<File>, range);
+  Box::pin(stream::try_unfold(
+    state,
+    |(path, file, range)| async move {
+      if range.is_empty() {
+        return Ok(None);
+      }
+
+      let mut file = match file {
+        Some(file) => file,
+        None => {
+          let mut file = File::open(&path).await?;
+          file.seek(SeekFrom::Start(range.start)).await?;
+          file
+        }
+      };
+
+      let len = (range.end - range.start).min(FILE_CHUNK_SIZE as u64) as usize;
+      let mut chunk = vec![0; len];
+      let read = file.read(&mut chunk).await?;
+      // A file truncated while reading ends the body early
+      if read == 0 {
+        return Ok(None);
+      }
+      chunk.truncate(read);
+
+      let rest = range.start + read as u64..range.end;
+      Ok(Some((Bytes::from(chunk), (path, Some(file), rest))))
+    }
+  ))
+}
+
+/// A scripted failure of a request to a [`MemoryTransport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+  /// Answer with this status and an empty body
+  Status(u16),
+
+  /// Fail without a response, like a refused connection
+  Refused,
+
+  /// Send the headers of the full response, but end the body early after
+  /// this many bytes
+  Truncate(usize),
+
+  /// Fail the body with an error after this many bytes, like a reset
+  /// connection
+  Reset(usize)
+}
+
+/// A resource served by a [`MemoryTransport`].
+///
+/// Byte ranges are supported unless disabled, and the `ETag` and
+/// `Last-Modified` headers set on the fixture are honored by conditional
+/// requests.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+  body: Bytes,
+  headers: HeaderMap,
+  chunk_size: usize,
+  chunk_delay: Duration,
+  ranges: bool,
+  content_length: bool,
+  status: Option<StatusCode>,
+  faults: VecDeque<Fault>
+}
+
+impl Fixture {
+  /// Creates a fixture with the given body.
+  pub fn new<B: Into<Bytes>>(body: B) -> Self {
+    Self {
+      body: body.into(),
+      headers: HeaderMap::new(),
+      chunk_size: 16 * 1024,
+      chunk_delay: Duration::ZERO,
+      ranges: true,
+      content_length: true,
+      status: None,
+      faults: VecDeque::new()
+    }
+  }
+
+  /// Adds a response header.
+  pub fn header<V: AsRef<str>>(mut self, name: HeaderName, value: V) -> Self {
+    if let Ok(value) = HeaderValue::from_str(value.as_ref()) {
+      self.headers.append(name, value);
+    }
+    self
+  }
+
+  /// Sets the size of the body chunks.
+  pub fn chunk_size(mut self, size: usize) -> Self {
+    self.chunk_size = size.max(1);
+    self
+  }
+
+  /// Sets the delay before each body chunk.
+  pub fn chunk_delay(mut self, delay: Duration) -> Self {
+    self.chunk_delay = delay;
+    self
+  }
+
+  /// Ignores range requests and never advertises range support.
+  pub fn without_ranges(mut self) -> Self {
+    self.ranges = false;
+    self
+  }
+
+  /// Leaves out the `Content-Length` header.
+  pub fn without_content_length(mut self) -> Self {
+    self.content_length = false;
+    self
+  }
+
+  /// Answers every request, `HEAD` included, with `status` and no body.
+  pub fn status(mut self, status: u16) -> Self {
+    self.status = StatusCode::from_u16(status).ok();
+    self
+  }
+
+  /// Scripts a failure for the next `GET` request without one.
+  ///
+  /// Faults are used up in the order they were added, one per `GET`
+  /// request, after which requests succeed. `HEAD` requests never fail.
+  pub fn fault(mut self, fault: Fault) -> Self {
+    self.faults.push_back(fault);
+    self
+  }
+}
+
+/// Serves [`Fixture`]s from memory and records the requests received.
+///
+/// Only URLs with a fixture are supported, so this transport can be put in
+/// front of the regular ones to stub selected HTTP(S) URLs.
+///
+/// # Examples
+///
+/// ```rust
+/// use downloader::{Fault, Fixture, MemoryTransport};
+///
+/// let transport = MemoryTransport::new();
+/// transport.insert(
+///   "https://example.com/data.csv",
+///   Fixture::new("a,b\n1,2\n").chunk_size(4).fault(Fault::Status(503))
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+  fixtures: Mutex<HashMap<String, Fixture>>,
+  requests: Mutex<Vec<Request>>
+}
+
+impl MemoryTransport {
+  /// Creates a transport without fixtures.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Serves `fixture` at `url`, replacing any fixture served there.
+  pub fn insert<S: AsRef<str>>(&self, url: S, fixture: Fixture) {
+    let url = url.as_ref();
+    let key = reqwest::Url::parse(url).map_or(url.to_string(), String::from);
+    self.fixtures.lock().unwrap().insert(key, fixture);
+  }
+
+  /// Returns the requests received so far.
+  pub fn requests(&self) -> Vec<Request> {
+    self.requests.lock().unwrap().clone()
+  }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+  fn supports(&self, url: &reqwest::Url) -> bool {
+    self.fixtures.lock().unwrap().contains_key(url.as_str())
+  }
+
+  async fn send(&self, request: Request) -> Result<Response> {
+    self.requests.lock().unwrap().push(request.clone());
+
+    let (fixture, fault) = {
+      let mut fixtures = self.fixtures.lock().unwrap();
+      let Some(fixture) = fixtures.get_mut(request.url.as_str()) else {
+        return Ok(Response::empty(StatusCode::NOT_FOUND));
+      };
+      let fault = if request.method == Method::GET {
+        fixture.faults.pop_front()
+      } else {
+        None
+      };
+      (fixture.clone(), fault)
+    };
+
+    if let Some(status) = fixture.status {
+      return Ok(Response::empty(status));
+    }
+
+    let url = request.url.to_string();
+    match fault {
+      Some(Fault::Status(status)) => {
+        let status =
+          StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+        return Ok(Response::empty(status));
+      }
+      Some(Fault::Refused) =>
+        return Err(Error::interrupted(&url, "Connection refused")),
+      _ => {}
+    }
+
+    let len = fixture.body.len() as u64;
+    let mut served =
+      Served::new(&request, len, fixture.headers.clone(), fixture.ranges);
+    if !fixture.content_length {
+      served.headers.remove(CONTENT_LENGTH);
+    }
+
+    let range = served.body_range(&request);
+    let (start, end) = (range.start as usize, range.end as usize);
+    let (end, reset) = match fault {
+      Some(Fault::Truncate(n)) => (end.min(start + n), false),
+      Some(Fault::Reset(n)) => (end.min(start + n), true),
+      _ => (end, false)
+    };
+
+    let data = fixture.body.slice(start..end);
+    let chunks: Vec<Bytes> = (0..data.len())
+      .step_by(fixture.chunk_size)
+      .map(|i| data.slice(i..(i + fixture.chunk_size).min(data.len())))
+      .collect();
+    let delay = fixture.chunk_delay;
+    let body = stream::iter(chunks)
+      .then(move |chunk| async move {
+        if !delay.is_zero() {
+          tokio::time::sleep(delay).await;
+        }
+        Ok(chunk)
+      })
+      .chain(stream::iter(
+        reset.then(|| Err(Error::interrupted(&url, "Connection reset")))
+      ));
+
+    Ok(Response::new(served.status, served.headers, Box::pin(body)))
+  }
+}
+
+/// Status, headers and byte range of the response to a request for a local
+/// resource, following the HTTP rules for conditional and range requests.
+struct Served {
+  status: StatusCode,
+  headers: HeaderMap,
+  range: Range<u64>
+}
+
+impl Served {
+  /// Answers `request` for a resource of `len` bytes with the validators in
+  /// `headers`, honoring byte ranges if `ranges` is set.
+  fn new(
+    request: &Request,
+    len: u64,
+    mut headers: HeaderMap,
+    ranges: bool
+  ) -> Self {
+    let validators = Validators::from_headers(&headers);
+    if ranges {
+      headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    }
+
+    let weak = |etag: &str| etag.trim().trim_start_matches("W/").to_string();
+    let not_modified = match request.header_str(IF_NONE_MATCH) {
+      Some(tags) => validators.etag.as_deref().is_some_and(|etag| {
+        tags
+          .split(',')
+          .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag))
+      }),
+      None => request.header_str(IF_MODIFIED_SINCE).is_some_and(|since| {
+        validators.last_modified.as_deref() == Some(since.trim())
+      })
+    };
+    if not_modified {
+      return Self {
+        status: StatusCode::NOT_MODIFIED,
+        headers,
+        range: 0..0
+      };
+    }
+
+    // A range is only served if the resource still matches If-Range
+    let range = request
+      .header_str(RANGE)
+      .filter(|_| ranges)
+      .and_then(parse_range)
+      .filter(|_| {
+        request.header_str(IF_RANGE).is_none_or(|tag| {
+          Some(tag) == validators.etag.as_deref()
+            || Some(tag) == validators.last_modified.as_deref()
+        })
+      });
+
+    let (status, range) = match range {
+      Some((start, _)) if start >= len => {
+        let value = format!("bytes */{len}");
+        headers.insert(CONTENT_RANGE, HeaderValue::from_str(&value).unwrap());
+        (StatusCode::RANGE_NOT_SATISFIABLE, 0..0)
+      }
+      Some((start, end)) => {
+        let end = end.map_or(len - 1, |end| end.min(len - 1));
+        let value = format!("bytes {start}-{end}/{len}");
+        headers.insert(CONTENT_RANGE, HeaderValue::from_str(&value).unwrap());
+        (StatusCode::PARTIAL_CONTENT, start..end + 1)
+      }
+      None => (StatusCode::OK, 0..len)
+    };
+
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+    Self {
+      status,
+      headers,
+      range
+    }
+  }
+
+  /// Returns the byte range to send as body, which is empty for `HEAD`.
+  fn body_range(&self, request: &Request) -> Range<u64> {
+    if request.method == Method::HEAD {
+      0..0
+    } else {
+      self.range.clone()
+    }
+  }
+}
+
+/// Parses a single-range `Range` header value (`bytes=start-` or
+/// `bytes=start-end`).
+fn parse_range(value: &str) -> Option<(u64, Option<u64>)> {
+  let range = value.trim().strip_prefix("bytes=")?;
+  if range.contains(',') {
+    return None;
+  }
+
+  let (start, end) = range.split_once('-')?;
+  let start = start.trim().parse().ok()?;
+  let end = match end.trim() {
+    "" => None,
+    end => Some(end.parse().ok()?)
+  };
+
+  match end {
+    Some(end) if end < start => None,
+    end => Some((start, end))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  fn url(url: &str) -> reqwest::Url {
+    reqwest::Url::parse(url).unwrap()
+  }
+
+  async fn body(response: Response) -> Result<Vec<u8>> {
+    let chunks: Vec<Bytes> = response.bytes_stream().try_collect().await?;
+    Ok(chunks.concat())
+  }
+
+  #[test]
+  fn test_parse_range() {
+    assert_eq!(parse_range("bytes=100-"), Some((100, None)));
+    assert_eq!(parse_range("bytes=0-99"), Some((0, Some(99))));
+    assert_eq!(parse_range("bytes=9-1"), None);
+    assert_eq!(parse_range("bytes=-100"), None);
+    assert_eq!(parse_range("bytes=0-1,5-6"), None);
+  }
+
+  #[tokio::test]
+  async fn test_file_transport_serves_ranges() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("data.bin");
+    tokio::fs::write(&path, b"0123456789").await.unwrap();
+    let file_url = reqwest::Url::from_file_path(&path).unwrap();
+
+    let response = FileTransport
+      .send(Request::new(Method::GET, file_url.clone()))
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.content_length(), Some(10));
+    assert!(response.headers().contains_key(LAST_MODIFIED));
+    assert_eq!(body(response).await.unwrap(), b"0123456789");
+
+    let ranged =
+      Request::new(Method::GET, file_url.clone()).header(RANGE, "bytes=4-");
+    let response = FileTransport.send(ranged).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body(response).await.unwrap(), b"456789");
+
+    let beyond = Request::new(Method::GET, file_url).header(RANGE, "bytes=10-");
+    let response = FileTransport.send(beyond).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let missing = reqwest::Url::from_file_path(temp_dir.path().join("x"));
+    let response = FileTransport
+      .send(Request::new(Method::HEAD, missing.unwrap()))
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[tokio::test]
+  async fn test_memory_transport_scripts_faults() {
+    let transport = MemoryTransport::new();
+    let fixture_url = "https://example.com/data.bin";
+    transport.insert(
+      fixture_url,
+      Fixture::new("0123456789")
+        .chunk_size(3)
+        .fault(Fault::Status(503))
+        .fault(Fault::Refused)
+        .fault(Fault::Truncate(4))
+        .fault(Fault::Reset(2))
+    );
+    let get = || Request::new(Method::GET, url(fixture_url));
+
+    let response = transport.send(get()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    assert!(matches!(
+      transport.send(get()).await,
+      Err(Error::Interrupted { .. })
+    ));
+
+    let response = transport.send(get()).await.unwrap();
+    assert_eq!(response.content_length(), Some(10));
+    assert_eq!(body(response).await.unwrap(), b"0123");
+
+    let response = transport.send(get()).await.unwrap();
+    assert!(matches!(
+      body(response).await,
+      Err(Error::Interrupted { .. })
+    ));
+
+    let response = transport.send(get()).await.unwrap();
+    assert_eq!(body(response).await.unwrap(), b"0123456789");
+    assert_eq!(transport.requests().len(), 5);
+  }
+
+  #[tokio::test]
+  async fn test_memory_transport_conditional_requests() {
+    let transport = MemoryTransport::new();
+    let fixture_url = "https://example.com/data.bin";
+    transport.insert(
+      fixture_url,
+      Fixture::new("0123456789").header(reqwest::header::ETAG, "\"v1\"")
+    );
+
+    let unchanged = Request::new(Method::GET, url(fixture_url))
+      .header(IF_NONE_MATCH, "\"v1\"");
+    let response = transport.send(unchanged).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // A stale If-Range validator gets the full body
+    let stale = Request::new(Method::GET, url(fixture_url))
+      .header(RANGE, "bytes=5-")
+      .header(IF_RANGE, "\"v0\"");
+    let response = transport.send(stale).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let missing = Request::new(Method::GET, url("https://example.com/other"));
+    assert!(!transport.supports(&missing.url));
+    let response = transport.send(missing).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[tokio::test]
+  async fn test_downloader_runs_offline() {
+    let mirror = TempDir::new().unwrap();
+    let path = mirror.path().join("local.csv");
+    tokio::fs::write(&path, "c,d\n3,4\n").await.unwrap();
+    let file_url = reqwest::Url::from_file_path(&path).unwrap();
+
+    let transport = Arc::new(MemoryTransport::new());
+    transport.insert(
+      "https://example.com/remote.csv",
+      Fixture::new("a,b\n1,2\n").fault(Fault::Status(502))
+    );
+
+    let mut validator = UrlValidator::default();
+    validator.allow_scheme("file");
+
+    let target = TempDir::new().unwrap();
+    let config = Config::builder()
+      .retry_delay(Duration::from_millis(1))
+      .build();
+    let mut downloader = Downloader::new_with_config(
+      vec!["https://example.com/remote.csv", file_url.as_str()],
+      target.path(),
+      config
+    )
+    .unwrap();
+    downloader
+      .with_transport(transport)
+      .with_url_validator(validator);
+
+    let report = downloader.start().await.unwrap().await.unwrap();
+    assert!(report.is_success());
+    assert_eq!(
+      std::fs::read_to_string(target.path().join("remote.csv")).unwrap(),
+      "a,b\n1,2\n"
+    );
+    assert_eq!(
+      std::fs::read_to_string(target.path().join("local.csv")).unwrap(),
+      "c,d\n3,4\n"
+    );
+  }
+}
@@ -14,7 +14,7 @@ use crate::*;
 /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
 /// let urls = vec!["https://httpbin.org/json"];
 /// let mut downloader = download(urls, "./downloads")?;
-/// let _progress_rx = downloader.start().await?;
+/// let results = downloader.start().await?.await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -42,7 +42,7 @@ where
 ///
 /// let urls = vec!["https://httpbin.org/delay/30"];
 /// let mut downloader = download_with_config(urls, "./downloads", config)?;
-/// let _progress_rx = downloader.start().await?;
+/// let results = downloader.start().await?.await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -92,15 +92,41 @@ pub fn format_filesize(size: u64) -> String {
 }
 
 pub async fn fetch_content_length(
-  client: &reqwest::Client,
+  transport: &dyn Transport,
+  config: &Config,
   url: &reqwest::Url
 ) -> Option<u64> {
-  match client.head(url.clone()).send().await {
-    Ok(response) => response
-      .headers()
-      .get(reqwest::header::CONTENT_LENGTH)
-      .and_then(|v| v.to_str().ok())
-      .and_then(|s| s.parse().ok()),
+  fetch_validators(transport, config, url)
+    .await?
+    .content_length
+}
+
+/// Fetches the validators (ETag, Last-Modified, Content-Length) of a URL
+/// using a HEAD request.
+pub async fn fetch_validators(
+  transport: &dyn Transport,
+  config: &Config,
+  url: &reqwest::Url
+) -> Option<Validators> {
+  Some(Validators::from_headers(
+    &fetch_headers(transport, config, url).await?
+  ))
+}
+
+/// Fetches the response headers of a URL using a HEAD request.
+pub async fn fetch_headers(
+  transport: &dyn Transport,
+  config: &Config,
+  url: &reqwest::Url
+) -> Option<reqwest::header::HeaderMap> {
+  let request = Request::with_config(reqwest::Method::HEAD, url, config);
+  match transport.send(request).await {
+    Ok(response) if response.status().is_success() =>
+      Some(response.headers().clone()),
+    Ok(response) => {
+      debug!("HEAD for {} returned {}", url, response.status());
+      None
+    }
     Err(e) => {
       debug!("Failed to fetch HEAD for {}: {}", url, e);
       None
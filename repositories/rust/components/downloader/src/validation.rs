@@ -8,6 +8,7 @@
 //   error::{Error, Result}
 // };
 use crate::*;
+use futures::stream::{self, StreamExt};
 use std::{
   collections::HashSet,
   net::{IpAddr, Ipv4Addr, Ipv6Addr},
@@ -22,7 +23,13 @@ pub struct Url {
   pub filename: String,
   pub target_path: PathBuf,
   pub exists: bool,
-  pub size_hint: Option<u64>
+  pub size_hint: Option<u64>,
+  /// Target path before conflict resolution renamed the file
+  pub renamed_from: Option<PathBuf>,
+  /// Format the body is decompressed from while downloading
+  pub decompress: Option<Decompression>,
+  /// HEAD response fetched to name the file, reused by the preview
+  pub probe: Option<preview::Probe>
 }
 
 impl Url {
@@ -31,32 +38,64 @@ impl Url {
     urls: Vec<String>,
     target_dir: &Path,
     filename_strategy: &filename::Strategy,
-    validator: UrlValidator
+    validator: UrlValidator,
+    transport: &dyn Transport,
+    config: &Config
   ) -> Result<Vec<Self>> {
-    let mut validated = Vec::new();
     let url_count = urls.len();
     trace!("Validating {} URLs", url_count);
 
+    let mut parsed = Vec::new();
     for (index, url_str) in urls.iter().enumerate() {
       let idx_str = index + 1;
       // Basic URL parsing and validation
-      let parsed_url = match validator.validate(url_str) {
+      match validator.validate(url_str) {
         Ok(url) => {
           debug!("Validated URL {idx_str} of {url_count}: {url_str}");
-          url
+          parsed.push((index, url_str, url));
         }
-        Err(e) => {
-          warn!("Invalid URL {idx_str}: {url_str} - {e}");
-          continue;
+        Err(e) => warn!("Invalid URL {idx_str}: {url_str} - {e}")
+      }
+    }
+
+    // Ask the servers for the URLs that do not name the file, concurrently
+    // and keeping their order
+    let heads: Vec<_> = stream::iter(&parsed)
+      .map(|(_, _, parsed_url)| async move {
+        if !filename_strategy.uses_response_headers(parsed_url) {
+          return None;
         }
-      };
+        let request =
+          Request::with_config(reqwest::Method::HEAD, parsed_url, config);
+        match transport.send(request).await {
+          Ok(response) => Some((response.status(), response.headers().clone())),
+          Err(e) => {
+            debug!("Failed to fetch HEAD for {}: {}", parsed_url, e);
+            None
+          }
+        }
+      })
+      .buffered(
+        config
+          .concurrency_limit
+          .unwrap_or(preview::PROBE_CONCURRENCY)
+          .max(1)
+      )
+      .collect()
+      .await;
 
-      // Extract filename using configured strategy
-      let filename = filename_strategy
-        .extract_filename(&parsed_url, index)
-        .map_err(|e| Error::Filename {
-          message: format!("Failed to extract filename: {e}")
-        })?;
+    let mut validated = Vec::new();
+    for ((index, url_str, parsed_url), head) in parsed.into_iter().zip(heads) {
+      // Extract filename using configured strategy, using the response
+      // headers when the server answered
+      let filename = match &head {
+        Some((status, headers)) if status.is_success() => filename_strategy
+          .extract_filename_with_headers(&parsed_url, index, headers),
+        _ => filename_strategy.extract_filename(&parsed_url, index)
+      }
+      .map_err(|e| Error::Filename {
+        message: format!("Failed to extract filename: {e}")
+      })?;
 
       let target_path = target_dir.join(&filename);
       let exists = target_path.exists();
@@ -67,7 +106,12 @@ impl Url {
         filename,
         target_path,
         exists,
-        size_hint: None
+        size_hint: None,
+        renamed_from: None,
+        decompress: None,
+        probe: head.map(|(status, headers)| {
+          preview::Probe::from_headers(status, &headers)
+        })
       });
     }
 
@@ -206,6 +250,11 @@ impl UrlValidator {
 
   /// Validates the URL host/domain.
   fn validate_host(&self, url: &reqwest::Url) -> Result<()> {
+    // Local file URLs have no host to check
+    if url.scheme() == "file" && url.host().is_none() {
+      return Ok(());
+    }
+
     let host = url
       .host_str()
       .ok_or_else(|| Error::validation_error("URL must have a host"))?;
@@ -316,11 +365,6 @@ impl UrlValidator {
       ));
     }
 
-    // Check for file URLs
-    if url.scheme() == "file" {
-      return Err(Error::validation_error("File URLs are not supported"));
-    }
-
     // Check for JavaScript URLs
     if url.scheme() == "javascript" {
       return Err(Error::validation_error(
@@ -363,6 +407,8 @@ impl UrlValidator {
   }
 
   /// Adds a scheme to the allowed list.
+  ///
+  /// Allowing `file` lets URLs read local files through the file transport.
   pub fn allow_scheme<S: Into<String>>(&mut self, scheme: S) {
     self.allowed_schemes.insert(scheme.into());
   }
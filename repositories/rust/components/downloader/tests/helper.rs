@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use downloader::{Fixture, MemoryTransport};
+use std::{path::PathBuf, sync::Arc};
 use tempfile::tempdir;
 
 #[cfg(unix)]
@@ -18,8 +19,15 @@ pub fn valid_path<S: AsRef<str>>(name: S) -> PathBuf {
 }
 
 pub fn valid_url() -> &'static str {
-  // Use a small static file for testing
-  "https://httpbin.org/bytes/20"
+  // Served by `transport`, so tests do not need the network
+  "https://example.com/bytes/20"
+}
+
+/// Returns an in-memory transport serving 20 bytes at `valid_url`.
+pub fn transport() -> Arc<MemoryTransport> {
+  let transport = Arc::new(MemoryTransport::new());
+  transport.insert(valid_url(), Fixture::new(vec![0u8; 20]));
+  transport
 }
 
 pub fn invalid_url() -> &'static str {